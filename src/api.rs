@@ -0,0 +1,598 @@
+//! Building scenes from Rust code rather than from scene description files.
+//!
+//! `SceneBuilder` has a method for each directive, taking typed values in
+//! place of tokens, and feeds them to the same `BasicSceneBuilder` that the
+//! parser does. A scene built this way is the same as one parsed from the
+//! equivalent file, errors included.
+
+use std::sync::Arc;
+
+use crate::paramdict::{ParsedParameter, ParsedParameterVector};
+use crate::parser::ParserTarget;
+use crate::scene::{BasicScene, BasicSceneBuilder, INTERNED_STRINGS};
+use crate::util::color::RGB;
+use crate::util::error::{FileLoc, ParseError};
+use crate::util::transform::{self, Transform};
+use crate::util::vecmath::{Normal3f, Point2f, Point3f, Tuple2, Tuple3, Vector3f};
+use crate::Float;
+
+/// The parameters of an entity, added one at a time with their types.
+///
+/// ```
+/// # use rpbrt::api::ParameterList;
+/// # use rpbrt::util::color::RGB;
+/// let params = ParameterList::new()
+///     .float("radius", 2.0)
+///     .rgb("reflectance", RGB::new(0.5, 0.5, 0.5));
+/// ```
+#[derive(Clone, Default)]
+pub struct ParameterList(ParsedParameterVector);
+
+impl ParameterList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(mut self, type_name: &str, name: &str, fill: impl FnOnce(&mut ParsedParameter)) -> Self {
+        let mut param = ParsedParameter::new();
+        param.type_name = INTERNED_STRINGS.lookup(&type_name.to_string());
+        param.name = INTERNED_STRINGS.lookup(&name.to_string());
+        fill(&mut param);
+        self.0.push(param);
+        self
+    }
+
+    pub fn float(self, name: &str, v: Float) -> Self {
+        self.floats(name, &[v])
+    }
+
+    pub fn floats(self, name: &str, v: &[Float]) -> Self {
+        self.add("float", name, |p| p.floats = v.to_vec())
+    }
+
+    pub fn int(self, name: &str, v: i32) -> Self {
+        self.ints(name, &[v])
+    }
+
+    pub fn ints(self, name: &str, v: &[i32]) -> Self {
+        self.add("integer", name, |p| p.ints = v.to_vec())
+    }
+
+    pub fn bool(self, name: &str, v: bool) -> Self {
+        self.add("bool", name, |p| p.bools = vec![v as u8])
+    }
+
+    pub fn string(self, name: &str, v: &str) -> Self {
+        self.add("string", name, |p| p.strings = vec![v.to_string()])
+    }
+
+    pub fn point2s(self, name: &str, v: &[Point2f]) -> Self {
+        self.add("point2", name, |p| {
+            p.floats = v.iter().flat_map(|v| [v.x(), v.y()]).collect()
+        })
+    }
+
+    pub fn point3(self, name: &str, v: Point3f) -> Self {
+        self.point3s(name, &[v])
+    }
+
+    pub fn point3s(self, name: &str, v: &[Point3f]) -> Self {
+        self.add("point3", name, |p| {
+            p.floats = v.iter().flat_map(|v| [v.x(), v.y(), v.z()]).collect()
+        })
+    }
+
+    pub fn vector3(self, name: &str, v: Vector3f) -> Self {
+        self.add("vector3", name, |p| p.floats = vec![v.x(), v.y(), v.z()])
+    }
+
+    pub fn normals(self, name: &str, v: &[Normal3f]) -> Self {
+        self.add("normal", name, |p| {
+            p.floats = v.iter().flat_map(|v| [v.x(), v.y(), v.z()]).collect()
+        })
+    }
+
+    pub fn rgb(self, name: &str, v: RGB) -> Self {
+        self.add("rgb", name, |p| p.floats = vec![v.r, v.g, v.b])
+    }
+
+    /// The emission of a blackbody at `temperature` kelvin.
+    pub fn blackbody(self, name: &str, temperature: Float) -> Self {
+        self.add("blackbody", name, |p| p.floats = vec![temperature])
+    }
+
+    /// One of the named spectra, such as "metal-Cu-eta".
+    pub fn named_spectrum(self, name: &str, spectrum: &str) -> Self {
+        self.add("spectrum", name, |p| p.strings = vec![spectrum.to_string()])
+    }
+
+    /// The texture called `texture`, declared earlier.
+    pub fn texture(self, name: &str, texture: &str) -> Self {
+        self.add("texture", name, |p| p.strings = vec![texture.to_string()])
+    }
+
+    fn with_loc(mut self, loc: &FileLoc) -> ParsedParameterVector {
+        for p in &mut self.0 {
+            p.loc = loc.clone();
+        }
+        self.0
+    }
+}
+
+/// The buffers of a "trianglemesh" shape. Normals and texture coordinates
+/// may be left empty.
+#[derive(Clone, Default)]
+pub struct TriangleMesh {
+    /// Three for each triangle, into the other buffers.
+    pub indices: Vec<i32>,
+    pub positions: Vec<Point3f>,
+    pub normals: Vec<Normal3f>,
+    pub uvs: Vec<Point2f>,
+}
+
+/// Builds a `BasicScene` with a method for each directive of the scene
+/// description format.
+pub struct SceneBuilder {
+    builder: BasicSceneBuilder,
+    loc: FileLoc,
+}
+
+impl SceneBuilder {
+    pub fn new(scene: Arc<BasicScene>) -> Self {
+        Self {
+            builder: BasicSceneBuilder::new(scene),
+            loc: FileLoc::default(),
+        }
+    }
+
+    /// Sets the location that entities added from now on are recorded
+    /// with and that errors about them report. By default there is none.
+    pub fn set_loc(&mut self, loc: FileLoc) {
+        self.loc = loc;
+    }
+
+    /// Finishes the scene, returning every error found in it.
+    pub fn finish(mut self) -> Result<(), Vec<ParseError>> {
+        self.builder.end_of_files()
+    }
+
+    fn loc(&self) -> FileLoc {
+        self.loc.clone()
+    }
+
+    // Transformations
+
+    pub fn identity(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.identity(loc)
+    }
+
+    pub fn translate(&mut self, delta: Vector3f) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.translate(delta.x(), delta.y(), delta.z(), loc)
+    }
+
+    pub fn scale(&mut self, sx: Float, sy: Float, sz: Float) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.scale(sx, sy, sz, loc)
+    }
+
+    /// Rotates by `angle` degrees about `axis`.
+    pub fn rotate(&mut self, angle: Float, axis: Vector3f) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .rotate(angle, axis.x(), axis.y(), axis.z(), loc)
+    }
+
+    pub fn look_at(&mut self, eye: Point3f, look: Point3f, up: Vector3f) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.look_at(
+            eye.x(),
+            eye.y(),
+            eye.z(),
+            look.x(),
+            look.y(),
+            look.z(),
+            up.x(),
+            up.y(),
+            up.z(),
+            loc,
+        )
+    }
+
+    pub fn concat_transform(&mut self, t: &Transform) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .concat_transform(transform::directive_values(t), loc)
+    }
+
+    pub fn transform(&mut self, t: &Transform) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.transform(transform::directive_values(t), loc)
+    }
+
+    pub fn coordinate_system(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.coordinate_system(name, loc)
+    }
+
+    pub fn coord_sys_transform(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.coord_sys_transform(name, loc)
+    }
+
+    pub fn active_transform_all(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.active_transform_all(loc)
+    }
+
+    pub fn active_transform_start_time(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.active_transform_start_time(loc)
+    }
+
+    pub fn active_transform_end_time(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.active_transform_end_time(loc)
+    }
+
+    pub fn transform_times(&mut self, start: Float, end: Float) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.transform_times(start, end, loc)
+    }
+
+    pub fn reverse_orientation(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.reverse_orientation(loc)
+    }
+
+    // Rendering options
+
+    pub fn option(&mut self, name: &str, value: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.option(name, value, loc)
+    }
+
+    pub fn color_space(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.color_space(name, loc)
+    }
+
+    pub fn camera(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.camera(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn film(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.film(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn sampler(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.sampler(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn pixel_filter(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.pixel_filter(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn integrator(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.integrator(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn accelerator(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.accelerator(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn world_begin(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.world_begin(loc)
+    }
+
+    // The scene
+
+    pub fn attribute_begin(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.attribute_begin(loc)
+    }
+
+    pub fn attribute_end(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.attribute_end(loc)
+    }
+
+    /// Sets parameters for every entity of the kind `target` names:
+    /// "shape", "light", "material", "medium" or "texture".
+    pub fn attribute(&mut self, target: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.attribute(target, params.with_loc(&loc), loc)
+    }
+
+    pub fn float_texture(
+        &mut self,
+        name: &str,
+        texname: &str,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .texture(name, "float", texname, params.with_loc(&loc), loc)
+    }
+
+    pub fn spectrum_texture(
+        &mut self,
+        name: &str,
+        texname: &str,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .texture(name, "spectrum", texname, params.with_loc(&loc), loc)
+    }
+
+    pub fn material(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.material(name, params.with_loc(&loc), loc)
+    }
+
+    /// Defines a material for `named_material` to use. Its type is given
+    /// by its "type" parameter.
+    pub fn make_named_material(
+        &mut self,
+        name: &str,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .make_named_material(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn named_material(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.named_material(name, loc)
+    }
+
+    pub fn make_named_medium(
+        &mut self,
+        name: &str,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .make_named_medium(name, params.with_loc(&loc), loc)
+    }
+
+    /// Sets the media inside and outside the shapes that follow; an empty
+    /// name means no medium.
+    pub fn medium_interface(&mut self, inside: &str, outside: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.medium_interface(inside, outside, loc)
+    }
+
+    pub fn light_source(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.light_source(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn area_light_source(
+        &mut self,
+        name: &str,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder
+            .area_light_source(name, params.with_loc(&loc), loc)
+    }
+
+    pub fn shape(&mut self, name: &str, params: ParameterList) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.shape(name, params.with_loc(&loc), loc)
+    }
+
+    /// Adds a "trianglemesh" shape from its buffers, followed by any other
+    /// parameters it takes.
+    pub fn triangle_mesh(
+        &mut self,
+        mesh: &TriangleMesh,
+        params: ParameterList,
+    ) -> Result<(), ParseError> {
+        let mut buffers = ParameterList::new()
+            .ints("indices", &mesh.indices)
+            .point3s("P", &mesh.positions);
+        if !mesh.normals.is_empty() {
+            buffers = buffers.normals("N", &mesh.normals);
+        }
+        if !mesh.uvs.is_empty() {
+            buffers = buffers.point2s("uv", &mesh.uvs);
+        }
+        buffers.0.extend(params.0);
+        self.shape("trianglemesh", buffers)
+    }
+
+    pub fn object_begin(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.object_begin(name, loc)
+    }
+
+    pub fn object_end(&mut self) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.object_end(loc)
+    }
+
+    pub fn object_instance(&mut self, name: &str) -> Result<(), ParseError> {
+        let loc = self.loc();
+        self.builder.object_instance(name, loc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paramdict::SpectrumType;
+    use crate::parser::{parse, Tokenizer};
+
+    fn parse_scene(text: &str) -> Arc<BasicScene> {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse(
+            &mut builder,
+            Tokenizer::new("test", text.as_bytes().to_vec()),
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        scene
+    }
+
+    #[test]
+    fn same_scene_as_parsing() {
+        let parsed = parse_scene(
+            "LookAt 0 0 -5  0 0 0  0 1 0\n\
+             Camera \"perspective\" \"float fov\" 45\n\
+             Film \"rgb\" \"string filename\" \"out.exr\"\n\
+             WorldBegin\n\
+             LightSource \"point\" \"blackbody I\" 5500\n\
+             MakeNamedMaterial \"red\" \"string type\" \"diffuse\" \"rgb reflectance\" [0.8 0.1 0.1]\n\
+             AttributeBegin\n\
+               NamedMaterial \"red\"\n\
+               Translate 1 0 0\n\
+               ConcatTransform [2 0 0 0  0 1 0 0  0 0 1 0  0 3 0 1]\n\
+               Shape \"trianglemesh\" \"integer indices\" [0 1 2]\n\
+                 \"point3 P\" [0 0 0  1 0 0  0 1 0]\n\
+             AttributeEnd\n",
+        );
+
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = SceneBuilder::new(scene.clone());
+        builder
+            .look_at(
+                Point3f::new(0.0, 0.0, -5.0),
+                Point3f::new(0.0, 0.0, 0.0),
+                Vector3f::new(0.0, 1.0, 0.0),
+            )
+            .unwrap();
+        builder
+            .camera("perspective", ParameterList::new().float("fov", 45.0))
+            .unwrap();
+        builder
+            .film("rgb", ParameterList::new().string("filename", "out.exr"))
+            .unwrap();
+        builder.world_begin().unwrap();
+        builder
+            .light_source("point", ParameterList::new().blackbody("I", 5500.0))
+            .unwrap();
+        let red = ParameterList::new()
+            .string("type", "diffuse")
+            .rgb("reflectance", RGB::new(0.8, 0.1, 0.1));
+        builder.make_named_material("red", red).unwrap();
+        builder.attribute_begin().unwrap();
+        builder.named_material("red").unwrap();
+        builder.translate(Vector3f::new(1.0, 0.0, 0.0)).unwrap();
+        let mut m = [0.0; 16];
+        m[0] = 2.0;
+        m[5] = 1.0;
+        m[10] = 1.0;
+        m[13] = 3.0;
+        m[15] = 1.0;
+        builder
+            .concat_transform(&transform::transpose(&Transform::from_array(&m)))
+            .unwrap();
+        let mesh = TriangleMesh {
+            indices: vec![0, 1, 2],
+            positions: vec![
+                Point3f::new(0.0, 0.0, 0.0),
+                Point3f::new(1.0, 0.0, 0.0),
+                Point3f::new(0.0, 1.0, 0.0),
+            ],
+            ..Default::default()
+        };
+        builder.triangle_mesh(&mesh, ParameterList::new()).unwrap();
+        builder.attribute_end().unwrap();
+        builder.finish().unwrap();
+
+        let (a, b) = (parsed.options.get().unwrap(), scene.options.get().unwrap());
+        assert_eq!(a.camera.scene_entity.name, b.camera.scene_entity.name);
+        assert!(
+            a.camera
+                .camera_transform
+                .render_from_camera()
+                .start_transform
+                == b.camera
+                    .camera_transform
+                    .render_from_camera()
+                    .start_transform
+        );
+        let fov = |o: &crate::scene::SceneOptions| {
+            o.camera
+                .scene_entity
+                .parameters
+                .get_one_float("fov", 90.0)
+                .unwrap()
+        };
+        assert_eq!(fov(a), fov(b));
+        let filename = |o: &crate::scene::SceneOptions| {
+            o.film.parameters.get_one_string("filename", "").unwrap()
+        };
+        assert_eq!(filename(a), filename(b));
+
+        let (a, b) = (parsed.lights.lock().unwrap(), scene.lights.lock().unwrap());
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].scene_entity.name, b[0].scene_entity.name);
+        assert!(a[0].render_from_object.start_transform == b[0].render_from_object.start_transform);
+
+        let (a, b) = (
+            parsed.named_materials.lock().unwrap(),
+            scene.named_materials.lock().unwrap(),
+        );
+        assert_eq!(a[0].0, b[0].0);
+        let reflectance = |e: &crate::scene::SceneEntity| {
+            let s = e
+                .parameters
+                .get_one_spectrum("reflectance", None, SpectrumType::Albedo);
+            s.unwrap().unwrap().evaluate(550.0)
+        };
+        assert_eq!(reflectance(&a[0].1), reflectance(&b[0].1));
+
+        let (a, b) = (parsed.shapes.lock().unwrap(), scene.shapes.lock().unwrap());
+        assert_eq!(a.len(), 1);
+        assert_eq!(b.len(), 1);
+        let (a, b) = (&a[0], &b[0]);
+        assert_eq!(a.scene_entity.name, b.scene_entity.name);
+        assert_eq!(a.material_name, b.material_name);
+        assert!(*a.render_from_object == *b.render_from_object);
+        let p = |s: &crate::scene::ShapeSceneEntity| {
+            s.scene_entity.parameters.get_point3f_array("P").unwrap()
+        };
+        assert_eq!(p(a), p(b));
+        let indices = |s: &crate::scene::ShapeSceneEntity| {
+            s.scene_entity.parameters.get_int_array("indices").unwrap()
+        };
+        assert_eq!(indices(a), indices(b));
+    }
+
+    #[test]
+    fn errors_are_the_same_as_parsing() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = SceneBuilder::new(scene);
+        builder.world_begin().unwrap();
+        builder.color_space("adobe").unwrap();
+        builder.set_loc(FileLoc::new("generator.rs".to_string()));
+        builder.named_material("missing").unwrap();
+        builder.shape("sphere", ParameterList::new()).unwrap();
+        let errors = builder.finish().unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.loc.filename.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("", "adobe: color space unknown"),
+                ("generator.rs", "missing: named material not defined."),
+            ]
+        );
+    }
+}
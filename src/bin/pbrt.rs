@@ -1,45 +1,165 @@
-use std::{env, rc::Rc};
+use std::{env, sync::Arc};
 
 use rpbrt::{
     cpu::render::render_cpu,
-    parser::parse_files,
-    pbrt::init_pbrt,
+    parser::{parse_files, ErrorMode, FormattingParserTarget, ParseError},
+    pbrt::{self, init_pbrt, PbrtOptions},
+    report::scene_report,
     scene::{BasicScene, BasicSceneBuilder},
+    util::{file::set_search_directories, stats::print_stats},
 };
 // use std::fs::File;
 // use std::io::{BufWriter, Write};
 // let mut out = BufWriter::new(File::create("image.ppm")?);
 
+fn usage(message: &str) -> ! {
+    eprintln!("pbrt: {}", message);
+    eprintln!("usage: pbrt [<options>] <filename.pbrt...>");
+    eprintln!("Options given here override those set by the scene's \"Option\" directive.");
+    eprintln!("  --cropwindow <x0,x1,y0,y1>");
+    eprintln!("              Render only the given region of the image, in [0,1].");
+    eprintln!("  --disable-pixel-jitter");
+    eprintln!("              Always sample pixels at their centers.");
+    eprintln!("  --disable-texture-filtering");
+    eprintln!("              Point-sample all textures.");
+    eprintln!("  --disable-wavelength-jitter");
+    eprintln!("              Always sample the same wavelengths of light.");
+    eprintln!("  --displacement-edge-scale <s>");
+    eprintln!("              Scale the target edge length for displacement mapping");
+    eprintln!("              (not yet implemented).");
+    eprintln!("  --force-diffuse");
+    eprintln!("              Convert all materials to be diffuse.");
+    eprintln!("  --format    Print a reformatted version of the input file(s) to");
+    eprintln!("              standard output. Does not render an image.");
+    eprintln!("  --lenient   Warn about unused parameters instead of stopping.");
+    eprintln!("  --mse-reference-image <filename>");
+    eprintln!("              Compute the mean squared error against a reference image");
+    eprintln!("              (not yet implemented).");
+    eprintln!("  --mse-reference-out <filename>");
+    eprintln!("              File to write the mean squared error after each pass to");
+    eprintln!("              (not yet implemented).");
+    eprintln!("  --nthreads <num>");
+    eprintln!("              Use the given number of threads (default: one per core).");
+    eprintln!("  --outfile <filename>");
+    eprintln!("              Write the final image to the given filename.");
+    eprintln!("  --pixelbounds <x0,x1,y0,y1>");
+    eprintln!("              Render only the given pixels of the image.");
+    eprintln!("  --pixelstats");
+    eprintln!("              Record statistics about the samples of each pixel");
+    eprintln!("              (not yet implemented).");
+    eprintln!("  --quick     Reduce the quality of the render for a quick preview.");
+    eprintln!("  --render-coord-sys <name>");
+    eprintln!("              Coordinate system to render in: \"camera\", \"cameraworld\"");
+    eprintln!("              (the default) or \"world\".");
+    eprintln!("  --search-path <dirs>");
+    eprintln!("              Look for the files the scene refers to in these directories");
+    eprintln!("              (separated as in PATH) when they aren't next to the scene.");
+    eprintln!("  --seed <n>  Seed the random number generators with n.");
+    eprintln!("  --spp <n>   Override the number of samples per pixel.");
+    eprintln!("  --stats     Print various statistics after rendering.");
+    eprintln!("  --stats-only");
+    eprintln!("              Print counts of the scene's shapes, lights, textures and");
+    eprintln!("              so forth, and warn about unused ones. Does not render an image.");
+    eprintln!("  --upgrade   Upgrade a pbrt-v3 file to pbrt-v4's format, printing the");
+    eprintln!("              result like --format does.");
+    std::process::exit(1);
+}
+
+fn exit_on_errors(result: Result<(), Vec<ParseError>>) {
+    if let Err(errors) = result {
+        for e in &errors {
+            e.report();
+        }
+        std::process::exit(1);
+    }
+}
+
 fn main() -> Result<(), String> {
     // Convert command-line arguments to vector of strings
-    let args = env::args();
+    let mut args = env::args().skip(1);
 
-    // TODO: Declare variables for parsed command line
+    // Declare variables for parsed command line
+    let mut options = PbrtOptions::default();
 
     // Process command-line arguments
     let mut filenames: Vec<String> = Vec::new();
-    for (idx, arg) in args.enumerate() {
-        if idx == 0 {
+    let mut format = false;
+    let mut upgrade = false;
+    let mut stats_only = false;
+    while let Some(arg) = args.next() {
+        // A lone "-" names standard input rather than an option.
+        if arg == "-" || !arg.starts_with('-') {
+            filenames.push(arg);
             continue;
         }
-        if arg.as_bytes()[0] != b'-' {
-            filenames.push(arg);
+        // Options that take a value accept it either as "--name=value" or
+        // as the next argument.
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .unwrap_or_else(|| usage(&format!("missing value after {}", name)))
+        };
+        match name {
+            "--format" => format = true,
+            "--upgrade" => upgrade = true,
+            "--stats-only" => stats_only = true,
+            "--search-path" => set_search_directories(env::split_paths(&value()).collect()),
+            // Everything else sets one of pbrt's options.
+            _ => {
+                let value = if PbrtOptions::is_switch(name) {
+                    inline_value.unwrap_or_else(|| "true".to_string())
+                } else {
+                    value()
+                };
+                options
+                    .set_from_command_line(name, &value)
+                    .unwrap_or_else(|e| usage(&e));
+            }
         }
-        // TODO: parse_args
+    }
+
+    // Nothing is rendered when only checking or rewriting the scene, so
+    // every error in it might as well be reported at once.
+    let error_mode = if format || upgrade || stats_only {
+        ErrorMode::CollectAll
+    } else {
+        ErrorMode::StopAtFirst
+    };
+
+    if format || upgrade {
+        let mut formatter = FormattingParserTarget::new(upgrade);
+        exit_on_errors(parse_files(&mut formatter, filenames, error_mode));
+        print!("{}", formatter.output());
+        return Ok(());
     }
 
     // Initialize pbrt
-    init_pbrt();
+    init_pbrt(options);
 
     // Parse provided scene description files
-    let scene = Rc::new(BasicScene::new());
+    let scene = Arc::new(BasicScene::new());
     let mut builder = BasicSceneBuilder::new(scene.clone());
-    parse_files(&mut builder, filenames).unwrap();
+    exit_on_errors(parse_files(&mut builder, filenames, error_mode));
+
+    if stats_only {
+        let report = scene_report(&scene);
+        report.report_warnings();
+        print!("{}", report);
+        return Ok(());
+    }
 
     // Render the scene
-    render_cpu(scene);
+    exit_on_errors(render_cpu(scene));
 
     // Clean up after rendering the scene
+    if pbrt::options().print_statistics {
+        print_stats(&mut std::io::stdout()).map_err(|e| e.to_string())?;
+    }
 
     Ok(())
 }
@@ -1,15 +1,284 @@
 use super::ray::*;
+use crate::paramdict::ParameterDictionary;
+use crate::pbrt::RenderingCoordinateSystem;
+use crate::util::error::{warning, FileLoc, ParseError};
+use crate::util::math::lerp;
+use crate::util::transform::{self, AnimatedTransform, Transform};
+use crate::util::vecmath::{Point3f, Tuple3, Vector3f};
 use crate::*;
 
-trait Camera {
-    fn generate_ray() -> Option<CameraRay>;
+pub trait Camera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<CameraRay>;
 }
 
-struct CameraSample {
-    p_film: Point2f,
+pub struct CameraSample {
+    pub p_film: Point2f,
+    // In [0, 1), mapped to the shutter interval.
+    pub time: Float,
 }
 
-struct CameraRay {
-    ray: Ray,
+pub struct CameraRay {
+    pub ray: Ray,
     // weight: SampledSpectrum,
 }
+
+/// Where the camera is, relative to the space rendering happens in. A
+/// moving camera's rendering space is where it is halfway through the
+/// shutter interval.
+#[derive(Clone, Debug)]
+pub struct CameraTransform {
+    render_from_camera: AnimatedTransform,
+    world_from_render: Transform,
+}
+
+impl Default for CameraTransform {
+    fn default() -> Self {
+        Self {
+            render_from_camera: AnimatedTransform::from_transform(&Transform::identity()),
+            world_from_render: Transform::identity(),
+        }
+    }
+}
+
+impl CameraTransform {
+    pub fn new(
+        world_from_camera: &AnimatedTransform,
+        rendering_space: RenderingCoordinateSystem,
+    ) -> Self {
+        let t_mid = (world_from_camera.start_time + world_from_camera.end_time) / 2.0;
+        let world_from_render = match rendering_space {
+            RenderingCoordinateSystem::Camera => world_from_camera.interpolate(t_mid),
+            RenderingCoordinateSystem::CameraWorld => {
+                let p_camera = world_from_camera.apply_point(Point3f::new(0.0, 0.0, 0.0), t_mid);
+                transform::translate(Vector3f::new(p_camera.x(), p_camera.y(), p_camera.z()))
+            }
+            RenderingCoordinateSystem::World => Transform::identity(),
+        };
+        let render_from_world = world_from_render.inverse();
+        Self {
+            render_from_camera: AnimatedTransform::new(
+                &(&render_from_world * &world_from_camera.start_transform),
+                world_from_camera.start_time,
+                &(&render_from_world * &world_from_camera.end_transform),
+                world_from_camera.end_time,
+            ),
+            world_from_render,
+        }
+    }
+
+    pub fn render_from_camera(&self) -> &AnimatedTransform {
+        &self.render_from_camera
+    }
+
+    pub fn camera_from_render(&self, time: Float) -> Transform {
+        self.render_from_camera.interpolate(time).inverse()
+    }
+
+    pub fn world_from_render(&self) -> &Transform {
+        &self.world_from_render
+    }
+
+    pub fn render_from_world(&self) -> Transform {
+        self.world_from_render.inverse()
+    }
+}
+
+/// What every camera reads from its parameters, whatever its projection.
+pub struct CameraBaseParameters {
+    camera_transform: CameraTransform,
+    shutter_open: Float,
+    shutter_close: Float,
+}
+
+impl CameraBaseParameters {
+    pub fn new(
+        camera_transform: CameraTransform,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Self, ParseError> {
+        let mut shutter_open = parameters.get_one_float("shutteropen", 0.0)?;
+        let mut shutter_close = parameters.get_one_float("shutterclose", 1.0)?;
+        if shutter_close < shutter_open {
+            warning(
+                loc,
+                &format!(
+                    "Shutter close time {} < shutter open {}. Swapping them.",
+                    shutter_close, shutter_open
+                ),
+            );
+            std::mem::swap(&mut shutter_open, &mut shutter_close);
+        }
+        Ok(Self {
+            camera_transform,
+            shutter_open,
+            shutter_close,
+        })
+    }
+
+    /// The time a ray leaves the camera, for a sample `u` in [0, 1).
+    pub fn sample_time(&self, u: Float) -> Float {
+        lerp(u, self.shutter_open, self.shutter_close)
+    }
+
+    /// The ray from `o` in direction `d`, both in camera space, moved into
+    /// rendering space at the time `sample` leaves the camera.
+    pub fn render_from_camera(&self, o: Point3f, d: Vector3f, sample: &CameraSample) -> Ray {
+        let time = self.sample_time(sample.time);
+        let render_from_camera = self.camera_transform.render_from_camera();
+        Ray::new(
+            render_from_camera.apply_point(o, time),
+            render_from_camera.apply_vector(d, time),
+            time,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_point_eq(p: Point3f, x: Float, y: Float, z: Float) {
+        let d = (p.x() - x).abs() + (p.y() - y).abs() + (p.z() - z).abs();
+        assert!(d < 1e-5, "{:?} != ({}, {}, {})", p, x, y, z);
+    }
+
+    #[test]
+    fn render_spaces() {
+        // A camera at (10, 0, 0) looking down the x axis.
+        let world_from_camera = transform::look_at(
+            Point3f::new(10.0, 0.0, 0.0),
+            Point3f::new(11.0, 0.0, 0.0),
+            Vector3f::new(0.0, 1.0, 0.0),
+        )
+        .unwrap()
+        .inverse();
+        let world_from_camera = AnimatedTransform::from_transform(&world_from_camera);
+        let origin = Point3f::new(0.0, 0.0, 0.0);
+        let ahead = Point3f::new(11.0, 0.0, 0.0);
+
+        let ct = CameraTransform::new(&world_from_camera, RenderingCoordinateSystem::Camera);
+        assert_point_eq(
+            ct.render_from_camera().apply_point(origin, 0.0),
+            0.0,
+            0.0,
+            0.0,
+        );
+        assert_point_eq(ct.render_from_world().apply_point(ahead), 0.0, 0.0, 1.0);
+
+        let ct = CameraTransform::new(&world_from_camera, RenderingCoordinateSystem::CameraWorld);
+        assert_point_eq(
+            ct.render_from_camera().apply_point(origin, 0.0),
+            0.0,
+            0.0,
+            0.0,
+        );
+        assert_point_eq(ct.render_from_world().apply_point(ahead), 1.0, 0.0, 0.0);
+
+        let ct = CameraTransform::new(&world_from_camera, RenderingCoordinateSystem::World);
+        assert_point_eq(
+            ct.render_from_camera().apply_point(origin, 0.0),
+            10.0,
+            0.0,
+            0.0,
+        );
+        assert_point_eq(ct.render_from_world().apply_point(ahead), 11.0, 0.0, 0.0);
+        let p = ct
+            .camera_from_render(0.0)
+            .apply_point(Point3f::new(12.0, 0.0, 0.0));
+        assert_point_eq(p, 0.0, 0.0, 2.0);
+    }
+    #[test]
+    fn moving_camera_renders_from_its_midpoint() {
+        let world_from_camera = AnimatedTransform::new(
+            &Transform::identity(),
+            0.0,
+            &transform::translate(Vector3f::new(4.0, 0.0, 0.0)),
+            1.0,
+        );
+        let ct = CameraTransform::new(&world_from_camera, RenderingCoordinateSystem::CameraWorld);
+        let origin = Point3f::new(0.0, 0.0, 0.0);
+        assert_point_eq(ct.render_from_world().apply_point(origin), -2.0, 0.0, 0.0);
+        assert_point_eq(
+            ct.render_from_camera().apply_point(origin, 0.0),
+            -2.0,
+            0.0,
+            0.0,
+        );
+        assert_point_eq(
+            ct.render_from_camera().apply_point(origin, 1.0),
+            2.0,
+            0.0,
+            0.0,
+        );
+        assert_point_eq(
+            ct.camera_from_render(0.75).apply_point(origin),
+            -1.0,
+            0.0,
+            0.0,
+        );
+    }
+
+    #[test]
+    fn shutter_interval() {
+        use crate::paramdict::ParsedParameter;
+        use crate::scene::INTERNED_STRINGS;
+
+        let param = |name: &str, value: Float| {
+            let mut p = ParsedParameter::new();
+            p.type_name = INTERNED_STRINGS.lookup(&String::from("float"));
+            p.name = INTERNED_STRINGS.lookup(&name.to_string());
+            p.floats.push(value);
+            p
+        };
+        let params = ParameterDictionary::new(
+            vec![param("shutteropen", 0.5), param("shutterclose", 0.25)],
+            &crate::util::colorspace::sRGB,
+        );
+        let base =
+            CameraBaseParameters::new(CameraTransform::default(), &params, &FileLoc::default())
+                .unwrap();
+        assert_eq!((base.shutter_open, base.shutter_close), (0.25, 0.5));
+        assert_eq!(base.sample_time(0.5), 0.375);
+    }
+
+    #[test]
+    fn ray_time_is_in_the_shutter_interval() {
+        use crate::paramdict::ParsedParameter;
+        use crate::scene::INTERNED_STRINGS;
+        use crate::util::vecmath::Tuple2;
+
+        let param = |name: &str, value: Float| {
+            let mut p = ParsedParameter::new();
+            p.type_name = INTERNED_STRINGS.lookup(&String::from("float"));
+            p.name = INTERNED_STRINGS.lookup(&name.to_string());
+            p.floats.push(value);
+            p
+        };
+        let params = ParameterDictionary::new(
+            vec![param("shutteropen", 1.0), param("shutterclose", 3.0)],
+            &crate::util::colorspace::sRGB,
+        );
+        // A camera moving from x = 0 at t = 0 to x = 4 at t = 4.
+        let world_from_camera = AnimatedTransform::new(
+            &Transform::identity(),
+            0.0,
+            &transform::translate(Vector3f::new(4.0, 0.0, 0.0)),
+            4.0,
+        );
+        let ct = CameraTransform::new(&world_from_camera, RenderingCoordinateSystem::World);
+        let base = CameraBaseParameters::new(ct, &params, &FileLoc::default()).unwrap();
+        let origin = Point3f::new(0.0, 0.0, 0.0);
+        let d = Vector3f::new(0.0, 0.0, 1.0);
+        for (u, time) in [(0.0, 1.0), (0.25, 1.5), (0.5, 2.0), (0.75, 2.5)] {
+            let sample = CameraSample {
+                p_film: Point2f::new(0.0, 0.0),
+                time: u,
+            };
+            let ray = base.render_from_camera(origin, d, &sample);
+            assert_eq!(ray.time, time);
+            assert!((base.shutter_open..=base.shutter_close).contains(&ray.time));
+            assert_point_eq(ray.o, time, 0.0, 0.0);
+            assert_point_eq(ray.at(2.0), time, 0.0, 2.0);
+        }
+    }
+}
@@ -1,8 +1,27 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::scene::BasicScene;
+use crate::util::error::{sort_errors, ParseError};
 
-pub fn render_cpu(parsed_scene: Rc<BasicScene>) {
+pub fn render_cpu(parsed_scene: Arc<BasicScene>) -> Result<(), Vec<ParseError>> {
+    let textures = parsed_scene.create_textures()?;
+    // The camera, lights and shapes are all created before any errors are
+    // reported, so that those of one don't hide those of the others.
+    let camera = parsed_scene.create_camera_base();
+    let lights = parsed_scene.create_lights();
+    let shapes = parsed_scene.create_shapes(&textures);
+    let (_camera, _lights, _shapes) = match (camera, lights, shapes) {
+        (Ok(camera), Ok(lights), Ok(shapes)) => (camera, lights, shapes),
+        (camera, lights, shapes) => {
+            let mut errors: Vec<ParseError> = (camera.err().into_iter())
+                .chain(lights.err().into_iter().flatten())
+                .chain(shapes.err().into_iter().flatten())
+                .collect();
+            sort_errors(&mut errors);
+            return Err(errors);
+        }
+    };
     // let integrator = parsed_scene.create_integrator();
     // integrator.render();
+    Ok(())
 }
@@ -1,11 +1,17 @@
-mod camera;
+pub mod api;
+pub mod camera;
 pub mod cpu;
+pub mod light;
 pub mod paramdict;
 pub mod parser;
 pub mod pbrt;
-mod ray;
+pub mod ray;
+pub mod report;
 pub mod scene;
-mod util;
+pub mod shape;
+pub mod texture;
+pub mod util;
+pub mod writer;
 
 pub type Float = f32;
 use crate::util::float::Num;
@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use crate::paramdict::{ParameterDictionary, SpectrumType};
+use crate::shape::Shape;
+use crate::util::error::{FileLoc, ParseError};
+use crate::util::math::{radians, PI};
+use crate::util::spectrum::{spectrum_to_photometric, Spectrum};
+use crate::util::transform::{from_frame, translate, Transform};
+use crate::util::vecmath::vector::{coordinate_system, normalize};
+use crate::util::vecmath::{Point3f, Tuple3, Vector3f};
+use crate::Float;
+
+/// A light as the LightSource and AreaLightSource directives describe it.
+/// The spectra are kept as given, with the scale that makes them emit the
+/// power, illuminance or radiance asked for.
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Distant(DistantLight),
+    UniformInfinite(UniformInfiniteLight),
+    DiffuseArea(DiffuseAreaLight),
+}
+
+impl Light {
+    /// Creates the light of the type called `name`, reporting the
+    /// parameters it didn't use.
+    pub fn create(
+        name: &str,
+        parameters: &ParameterDictionary,
+        render_from_light: &Transform,
+        loc: &FileLoc,
+    ) -> Result<Light, ParseError> {
+        let light = match name {
+            "point" => Light::Point(PointLight::create(render_from_light, parameters)?),
+            "spot" => Light::Spot(SpotLight::create(render_from_light, parameters)?),
+            "distant" => Light::Distant(DistantLight::create(render_from_light, parameters)?),
+            "infinite" => Light::UniformInfinite(UniformInfiniteLight::create(
+                render_from_light,
+                parameters,
+                loc,
+            )?),
+            "goniometric" | "projection" => {
+                return Err(ParseError::new(
+                    loc,
+                    format!("{}: light type not supported yet.", name),
+                ))
+            }
+            _ => {
+                return Err(ParseError::new(
+                    loc,
+                    format!("{}: light type unknown.", name),
+                ))
+            }
+        };
+        parameters.report_unused()?;
+        Ok(light)
+    }
+
+    /// Creates the area light of the type called `name` that `shape`
+    /// emits.
+    pub fn create_area(
+        name: &str,
+        parameters: &ParameterDictionary,
+        render_from_light: &Transform,
+        shape: &Shape,
+        loc: &FileLoc,
+    ) -> Result<Light, ParseError> {
+        if name != "diffuse" {
+            return Err(ParseError::new(
+                loc,
+                format!("{}: area light type unknown.", name),
+            ));
+        }
+        let light = DiffuseAreaLight::create(render_from_light, parameters, shape, loc)?;
+        parameters.report_unused()?;
+        Ok(Light::DiffuseArea(light))
+    }
+}
+
+// The spectrum of the parameter called `name`, or the illuminant of the
+// color space if it isn't given.
+fn illuminant_or_default(
+    parameters: &ParameterDictionary,
+    name: &str,
+) -> Result<Arc<dyn Spectrum>, ParseError> {
+    let default = parameters.color_space().illuminant.clone();
+    Ok(parameters
+        .get_one_spectrum(name, Some(default), SpectrumType::Illuminant)?
+        .unwrap())
+}
+
+fn translate_to(p: Point3f) -> Transform {
+    translate(Vector3f::new(p.x(), p.y(), p.z()))
+}
+
+pub struct PointLight {
+    pub render_from_light: Transform,
+    pub i: Arc<dyn Spectrum>,
+    pub scale: Float,
+}
+
+impl PointLight {
+    fn create(
+        render_from_light: &Transform,
+        parameters: &ParameterDictionary,
+    ) -> Result<Self, ParseError> {
+        let i = illuminant_or_default(parameters, "I")?;
+        let mut scale = parameters.get_one_float("scale", 1.0)?;
+        let power = parameters.get_one_float("power", -1.0)?;
+        scale /= spectrum_to_photometric(&*i);
+        if power > 0.0 {
+            scale *= power / (4.0 * PI);
+        }
+        let from = parameters.get_one_point3f("from", Point3f::new(0.0, 0.0, 0.0))?;
+        Ok(Self {
+            render_from_light: render_from_light * &translate_to(from),
+            i,
+            scale,
+        })
+    }
+}
+
+pub struct SpotLight {
+    pub render_from_light: Transform,
+    pub i: Arc<dyn Spectrum>,
+    pub scale: Float,
+    pub cos_falloff_start: Float,
+    pub cos_falloff_end: Float,
+}
+
+impl SpotLight {
+    fn create(
+        render_from_light: &Transform,
+        parameters: &ParameterDictionary,
+    ) -> Result<Self, ParseError> {
+        let i = illuminant_or_default(parameters, "I")?;
+        let mut scale = parameters.get_one_float("scale", 1.0)?;
+        let power = parameters.get_one_float("power", -1.0)?;
+        let cone_angle = parameters.get_one_float("coneangle", 30.0)?;
+        let cone_delta = parameters.get_one_float("conedelta", 5.0)?;
+        let from = parameters.get_one_point3f("from", Point3f::new(0.0, 0.0, 0.0))?;
+        let to = parameters.get_one_point3f("to", Point3f::new(0.0, 0.0, 1.0))?;
+
+        // The light shines down its z axis.
+        let dir = normalize(to - from);
+        let (x, y) = coordinate_system(dir);
+        let light_from_spot = &translate_to(from) * &from_frame(x, y, dir).inverse();
+
+        let cos_falloff_end = radians(cone_angle).cos();
+        let cos_falloff_start = radians(cone_angle - cone_delta).cos();
+        scale /= spectrum_to_photometric(&*i);
+        if power > 0.0 {
+            let k_e = 2.0
+                * PI
+                * ((1.0 - cos_falloff_start) + (cos_falloff_start - cos_falloff_end) / 2.0);
+            scale *= power / k_e;
+        }
+        Ok(Self {
+            render_from_light: render_from_light * &light_from_spot,
+            i,
+            scale,
+            cos_falloff_start,
+            cos_falloff_end,
+        })
+    }
+}
+
+pub struct DistantLight {
+    pub render_from_light: Transform,
+    pub l_emit: Arc<dyn Spectrum>,
+    pub scale: Float,
+}
+
+impl DistantLight {
+    fn create(
+        render_from_light: &Transform,
+        parameters: &ParameterDictionary,
+    ) -> Result<Self, ParseError> {
+        let l_emit = illuminant_or_default(parameters, "L")?;
+        let mut scale = parameters.get_one_float("scale", 1.0)?;
+        let from = parameters.get_one_point3f("from", Point3f::new(0.0, 0.0, 0.0))?;
+        let to = parameters.get_one_point3f("to", Point3f::new(0.0, 0.0, 1.0))?;
+
+        // The light arrives from along its z axis.
+        let w = normalize(from - to);
+        let (v1, v2) = coordinate_system(w);
+        let light_from_distant = from_frame(v1, v2, w).inverse();
+
+        scale /= spectrum_to_photometric(&*l_emit);
+        let illuminance = parameters.get_one_float("illuminance", -1.0)?;
+        if illuminance > 0.0 {
+            scale *= illuminance;
+        }
+        Ok(Self {
+            render_from_light: render_from_light * &light_from_distant,
+            l_emit,
+            scale,
+        })
+    }
+}
+
+/// An infinite light that is the same from every direction.
+pub struct UniformInfiniteLight {
+    pub render_from_light: Transform,
+    pub l_emit: Arc<dyn Spectrum>,
+    pub scale: Float,
+}
+
+impl UniformInfiniteLight {
+    fn create(
+        render_from_light: &Transform,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Self, ParseError> {
+        let l = parameters.get_spectrum_array("L", SpectrumType::Illuminant)?;
+        let mut scale = parameters.get_one_float("scale", 1.0)?;
+        let portal = parameters.get_point3f_array("portal")?;
+        let filename = parameters.get_one_filename("filename")?;
+        let illuminance = parameters.get_one_float("illuminance", -1.0)?;
+
+        if !l.is_empty() && !filename.is_empty() {
+            return Err(ParseError::new(
+                loc,
+                "Can't specify both \"L\" and \"filename\" with infinite area light.".to_string(),
+            ));
+        }
+        if !filename.is_empty() || !portal.is_empty() {
+            return Err(ParseError::new(
+                loc,
+                "infinite: image and portal lights are not supported yet.".to_string(),
+            ));
+        }
+        let l_emit = match l.into_iter().next() {
+            Some(l) => l,
+            None => parameters.color_space().illuminant.clone(),
+        };
+        // Scaled to be equivalent to 1 nit.
+        scale /= spectrum_to_photometric(&*l_emit);
+        if illuminance > 0.0 {
+            // The illuminance of the upper hemisphere is pi times the
+            // radiance.
+            scale *= illuminance / PI;
+        }
+        Ok(Self {
+            render_from_light: render_from_light.clone(),
+            l_emit,
+            scale,
+        })
+    }
+}
+
+/// The light a shape emits evenly over its surface, from one side or both.
+pub struct DiffuseAreaLight {
+    pub render_from_light: Transform,
+    pub l_emit: Arc<dyn Spectrum>,
+    pub scale: Float,
+    pub two_sided: bool,
+    pub area: Float,
+}
+
+impl DiffuseAreaLight {
+    fn create(
+        render_from_light: &Transform,
+        parameters: &ParameterDictionary,
+        shape: &Shape,
+        loc: &FileLoc,
+    ) -> Result<Self, ParseError> {
+        let l_emit = illuminant_or_default(parameters, "L")?;
+        let mut scale = parameters.get_one_float("scale", 1.0)?;
+        let two_sided = parameters.get_one_bool("twosided", false)?;
+        if !parameters.get_one_filename("filename")?.is_empty() {
+            return Err(ParseError::new(
+                loc,
+                "diffuse: image area lights are not supported yet.".to_string(),
+            ));
+        }
+        let area = shape.area();
+        scale /= spectrum_to_photometric(&*l_emit);
+        let power = parameters.get_one_float("power", -1.0)?;
+        if power > 0.0 {
+            let k_e = if two_sided { 2.0 } else { 1.0 } * area * PI;
+            scale *= power / k_e;
+        }
+        Ok(Self {
+            render_from_light: render_from_light.clone(),
+            l_emit,
+            scale,
+            two_sided,
+            area,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paramdict::ParsedParameter;
+    use crate::scene::INTERNED_STRINGS;
+    use crate::util::colorspace::sRGB;
+
+    fn param(type_name: &str, name: &str, floats: &[Float]) -> ParsedParameter {
+        let mut p = ParsedParameter::new();
+        p.type_name = INTERNED_STRINGS.lookup(&type_name.to_string());
+        p.name = INTERNED_STRINGS.lookup(&name.to_string());
+        p.floats = floats.to_vec();
+        p
+    }
+
+    fn create(name: &str, params: Vec<ParsedParameter>) -> Result<Light, ParseError> {
+        Light::create(
+            name,
+            &ParameterDictionary::new(params, &sRGB),
+            &Transform::identity(),
+            &FileLoc::default(),
+        )
+    }
+
+    fn assert_near(a: Float, b: Float) {
+        assert!((a - b).abs() < 1e-4 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn lights_are_scaled() {
+        let unit = match create("point", vec![]).unwrap() {
+            Light::Point(light) => light.scale,
+            _ => panic!("not a point light"),
+        };
+        let light = create(
+            "point",
+            vec![
+                param("float", "power", &[8.0]),
+                param("point3", "from", &[1.0, 2.0, 3.0]),
+            ],
+        )
+        .unwrap();
+        let Light::Point(light) = light else {
+            panic!("not a point light")
+        };
+        assert_near(light.scale, unit * 8.0 / (4.0 * PI));
+        assert_eq!(
+            light
+                .render_from_light
+                .apply_point(Point3f::new(0.0, 0.0, 0.0)),
+            Point3f::new(1.0, 2.0, 3.0)
+        );
+
+        let Light::Spot(spot) = create("spot", vec![param("float", "coneangle", &[60.0])]).unwrap()
+        else {
+            panic!("not a spot light")
+        };
+        assert_near(spot.cos_falloff_end, 0.5);
+        let Light::Distant(distant) =
+            create("distant", vec![param("float", "illuminance", &[2.0])]).unwrap()
+        else {
+            panic!("not a distant light")
+        };
+        assert_near(distant.scale, unit * 2.0);
+        let Light::UniformInfinite(infinite) = create("infinite", vec![]).unwrap() else {
+            panic!("not an infinite light")
+        };
+        assert_near(infinite.scale, unit);
+    }
+
+    #[test]
+    fn unsupported_lights_are_errors() {
+        let e = create("goniometric", vec![]).err().unwrap();
+        assert_eq!(e.message, "goniometric: light type not supported yet.");
+        let e = create("laser", vec![]).err().unwrap();
+        assert_eq!(e.message, "laser: light type unknown.");
+        let e = create("point", vec![param("float", "radius", &[1.0])])
+            .err()
+            .unwrap();
+        assert_eq!(e.token.as_deref(), Some("float radius"));
+    }
+}
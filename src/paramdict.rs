@@ -1,11 +1,24 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use crate::pbrt::options;
 use crate::scene::INTERNED_STRINGS;
-use crate::util::colorspace::RGBColorSpace;
-use crate::util::spectrum::Spectrum;
+use crate::texture::{
+    FloatConstantTexture, FloatTexture, NamedTextures, SpectrumConstantTexture, SpectrumTexture,
+};
+use crate::util::color::RGB;
+use crate::util::colorspace::{sRGB, RGBColorSpace};
+use crate::util::file::{read_float_file, resolve_filename};
+use crate::util::spectrum::{
+    get_named_spectrum, BlackbodySpectrum, PiecewiseLinearSpectrum, RGBAlbedoSpectrum,
+    RGBIlluminantSpectrum, RGBUnboundedSpectrum, Spectrum,
+};
 use crate::util::string::InternedString;
-use crate::util::vecmath::{Point2f, Point3f, Tuple2, Tuple3, Vector3f};
-use crate::{util::error::FileLoc, Float};
+use crate::util::vecmath::{Normal3f, Point2f, Point3f, Tuple2, Tuple3, Vector3f};
+use crate::{
+    util::error::{warning, FileLoc, ParseError},
+    Float,
+};
 
 pub struct ParsedParameter {
     pub type_name: InternedString,
@@ -15,7 +28,15 @@ pub struct ParsedParameter {
     pub ints: Vec<i32>,
     pub strings: Vec<String>,
     pub bools: Vec<u8>,
-    looked_up: bool,
+    /// Set for parameters given with the Attribute directive, which apply
+    /// to every object of a kind whether or not it uses them.
+    pub may_be_unused: bool,
+    /// The color space in effect where the parameter was given, if it
+    /// differs from the one of the directive it ends up in.
+    pub color_space: Option<&'static RGBColorSpace>,
+    // Set once the parameter's value has been asked for, so that parameters
+    // nothing asked for, usually because of a typo, can be reported.
+    looked_up: AtomicBool,
 }
 
 impl ParsedParameter {
@@ -28,16 +49,47 @@ impl ParsedParameter {
             ints: Vec::new(),
             strings: Vec::new(),
             bools: Vec::new(),
-            looked_up: false,
+            may_be_unused: false,
+            color_space: None,
+            looked_up: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Default for ParsedParameter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for ParsedParameter {
+    fn clone(&self) -> Self {
+        Self {
+            type_name: self.type_name.clone(),
+            name: self.name.clone(),
+            loc: self.loc.clone(),
+            floats: self.floats.clone(),
+            ints: self.ints.clone(),
+            strings: self.strings.clone(),
+            bools: self.bools.clone(),
+            may_be_unused: self.may_be_unused,
+            color_space: self.color_space,
+            looked_up: AtomicBool::new(self.looked_up.load(Ordering::Relaxed)),
         }
     }
 }
 
 pub type ParsedParameterVector = Vec<ParsedParameter>;
 
-enum SpectrumType {
+/// How an RGB value given for a spectrum parameter is turned into a
+/// spectrum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpectrumType {
+    /// Emission, which is relative to the color space's illuminant.
     Illuminant,
+    /// A reflectance or transmittance, which must be in [0, 1].
     Albedo,
+    /// Any other non-negative quantity.
     Unbounded,
 }
 
@@ -49,6 +101,12 @@ pub trait ParameterTypeTraits {
     where
         Self: Sized;
     fn convert(v: &[Self::ValueType]) -> Self;
+
+    /// Whether a parameter declared with `type_name` holds this type.
+    #[inline]
+    fn matches_type(type_name: &str) -> bool {
+        type_name == Self::TYPE_NAME
+    }
 }
 
 impl ParameterTypeTraits for bool {
@@ -63,10 +121,7 @@ impl ParameterTypeTraits for bool {
     }
 
     fn convert(v: &[Self::ValueType]) -> Self {
-        match v[0] {
-            0 => false,
-            _ => true,
-        }
+        v[0] != 0
     }
 }
 
@@ -90,7 +145,7 @@ impl ParameterTypeTraits for Float {
 }
 
 impl ParameterTypeTraits for i32 {
-    const TYPE_NAME: &'static str = "int";
+    const TYPE_NAME: &'static str = "integer";
 
     const N_PER_ITEM: usize = 1;
 
@@ -165,6 +220,31 @@ impl ParameterTypeTraits for Vector3f {
     }
 }
 
+impl ParameterTypeTraits for Normal3f {
+    const TYPE_NAME: &'static str = "normal";
+
+    const N_PER_ITEM: usize = 3;
+
+    type ValueType = Float;
+
+    fn get_values(param: &ParsedParameter) -> &[Self::ValueType]
+    where
+        Self: Sized,
+    {
+        &param.floats
+    }
+
+    fn convert(v: &[Self::ValueType]) -> Self {
+        Normal3f::new(v[0], v[1], v[2])
+    }
+
+    // Scenes use both spellings.
+    #[inline]
+    fn matches_type(type_name: &str) -> bool {
+        type_name == "normal" || type_name == "normal3"
+    }
+}
+
 impl ParameterTypeTraits for String {
     const TYPE_NAME: &'static str = "string";
 
@@ -184,124 +264,928 @@ impl ParameterTypeTraits for String {
     }
 }
 
+#[derive(Default, Clone)]
 pub struct ParameterDictionary {
     params: ParsedParameterVector,
     color_space: Option<&'static RGBColorSpace>,
 }
 
-impl Default for ParameterDictionary {
-    fn default() -> Self {
-        Self {
-            params: Default::default(),
-            color_space: None,
-        }
-    }
-}
-
-pub fn return_array<R, V>(
-    values: &[V],
+// The values of a parameter already known to be of type `T`. Values of
+// another kind, such as strings given for a "float" parameter, don't count.
+fn get_values<T: ParameterTypeTraits>(
     param: &ParsedParameter,
-    n_per_item: usize,
-    convert: fn(&[V]) -> R,
-) -> Vec<R> {
+) -> Result<&[T::ValueType], ParseError> {
+    let values = T::get_values(param);
     if values.is_empty() {
-        panic!("No values provided");
-    }
-    if values.len() % n_per_item != 0 {
-        panic!("Number of values provided not a multiple.");
+        return Err(ParseError::new(
+            &param.loc,
+            format!(
+                "no {} values provided for parameter \"{}\"",
+                T::TYPE_NAME,
+                param.name
+            ),
+        ));
     }
-    let n = values.len() / n_per_item;
-    let mut v = Vec::with_capacity(n);
-    for i in 0..n {
-        v[i] = convert(&values[n_per_item * i..n_per_item * (i + 1)])
+    Ok(values)
+}
+
+fn return_array<T: ParameterTypeTraits>(param: &ParsedParameter) -> Result<Vec<T>, ParseError> {
+    let values = get_values::<T>(param)?;
+    if values.len() % T::N_PER_ITEM != 0 {
+        return Err(ParseError::new(
+            &param.loc,
+            format!(
+                "number of values provided for parameter \"{}\" is not a multiple of {}",
+                param.name,
+                T::N_PER_ITEM
+            ),
+        ));
     }
-    v
+    Ok(values.chunks(T::N_PER_ITEM).map(T::convert).collect())
+}
+
+fn type_mismatch<T: ParameterTypeTraits>(param: &ParsedParameter) -> ParseError {
+    ParseError::new(
+        &param.loc,
+        format!(
+            "parameter \"{}\" has type \"{}\", but \"{}\" was expected",
+            param.name,
+            param.type_name,
+            T::TYPE_NAME
+        ),
+    )
 }
 
 impl ParameterDictionary {
     pub fn new(params: ParsedParameterVector, color_space: &'static RGBColorSpace) -> Self {
         Self {
-            params: params,
+            params,
             color_space: Some(color_space),
         }
     }
 
-    fn get_one_float(&self, name: &str, default: Float) -> Float {
+    /// Like `new`, followed by the parameters set with the Attribute
+    /// directive. Those come last so that the directive's own parameters
+    /// take precedence.
+    pub fn new_with_attributes(
+        mut params: ParsedParameterVector,
+        attributes: &[ParsedParameter],
+        color_space: &'static RGBColorSpace,
+    ) -> Self {
+        params.extend(attributes.iter().cloned());
+        Self::new(params, color_space)
+    }
+
+    /// The parameters in the order they were given, followed by those from
+    /// the Attribute directive.
+    pub fn params(&self) -> &[ParsedParameter] {
+        &self.params
+    }
+
+    pub fn color_space(&self) -> &'static RGBColorSpace {
+        self.color_space.unwrap_or(&sRGB)
+    }
+
+    /// Whether any parameter names a texture, in which case the object
+    /// can't be created until that texture has been.
+    pub fn has_texture_parameters(&self) -> bool {
+        self.params.iter().any(|p| *p.type_name == "texture")
+    }
+
+    pub fn get_one_float(&self, name: &str, default: Float) -> Result<Float, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_int(&self, name: &str, default: i32) -> i32 {
+    pub fn get_one_int(&self, name: &str, default: i32) -> Result<i32, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_bool(&self, name: &str, default: bool) -> bool {
+    pub fn get_one_bool(&self, name: &str, default: bool) -> Result<bool, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_point2f(&self, name: &str, default: Point2f) -> Point2f {
+    pub fn get_one_point2f(&self, name: &str, default: Point2f) -> Result<Point2f, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_vector3f(&self, name: &str, default: Vector3f) -> Vector3f {
+    pub fn get_one_point3f(&self, name: &str, default: Point3f) -> Result<Point3f, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_string(&self, name: &str, default: String) -> String {
-        // TODO: default &String
+    pub fn get_one_vector3f(&self, name: &str, default: Vector3f) -> Result<Vector3f, ParseError> {
         self.lookup_single(name, default)
     }
-    fn get_one_spectrum<T: Spectrum>(
+    pub fn get_one_normal3f(&self, name: &str, default: Normal3f) -> Result<Normal3f, ParseError> {
+        self.lookup_single(name, default)
+    }
+    pub fn get_one_string(&self, name: &str, default: &str) -> Result<String, ParseError> {
+        self.lookup_single(name, default.to_string())
+    }
+    /// Returns the file named by the string parameter called `name`,
+    /// resolved relative to the scene file it was given in, or an empty
+    /// string if it isn't given.
+    pub fn get_one_filename(&self, name: &str) -> Result<String, ParseError> {
+        let filename = self.get_one_string(name, "")?;
+        Ok(match self.lookup::<String>(name)? {
+            Some(p) if !filename.is_empty() => resolve_filename(&filename, &p.loc.filename),
+            _ => filename,
+        })
+    }
+    /// Returns the spectrum given by the parameter called `name`, whether
+    /// it was written as "rgb", "blackbody" or "spectrum".
+    pub fn get_one_spectrum(
         &self,
         name: &str,
-        default: T,
+        default: Option<Arc<dyn Spectrum>>,
         spectrum_type: SpectrumType,
-    ) -> T {
+    ) -> Result<Option<Arc<dyn Spectrum>>, ParseError> {
         for p in &self.params {
             if *p.name != name {
                 continue;
             }
+            let mut s = self.extract_spectrum_array(p, spectrum_type)?;
+            if s.is_empty() {
+                continue;
+            }
+            if s.len() > 1 {
+                return Err(ParseError::new(
+                    &p.loc,
+                    format!("more than one value provided for parameter \"{}\"", p.name),
+                ));
+            }
+            return Ok(s.pop());
         }
-        default
+        Ok(default)
     }
 
-    fn get_float_array(&self, name: &str) -> Vec<Float> {
-        self.lookup_array::<Float>(name)
+    pub fn get_float_array(&self, name: &str) -> Result<Vec<Float>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_int_array(&self, name: &str) -> Result<Vec<i32>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_bool_array(&self, name: &str) -> Result<Vec<bool>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_point2f_array(&self, name: &str) -> Result<Vec<Point2f>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_point3f_array(&self, name: &str) -> Result<Vec<Point3f>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_vector3f_array(&self, name: &str) -> Result<Vec<Vector3f>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_normal3f_array(&self, name: &str) -> Result<Vec<Normal3f>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_string_array(&self, name: &str) -> Result<Vec<String>, ParseError> {
+        self.lookup_array(name)
+    }
+    pub fn get_spectrum_array(
+        &self,
+        name: &str,
+        spectrum_type: SpectrumType,
+    ) -> Result<Vec<Arc<dyn Spectrum>>, ParseError> {
+        for p in &self.params {
+            if *p.name != name {
+                continue;
+            }
+            let s = self.extract_spectrum_array(p, spectrum_type)?;
+            if !s.is_empty() {
+                return Ok(s);
+            }
+        }
+        Ok(vec![])
     }
 
-    fn lookup_array<T: ParameterTypeTraits>(&self, name: &str) -> Vec<T> {
+    // Finds the parameter called `name` that holds a `T`. One with the right
+    // name but another type is an error, as long as no parameter of the
+    // right type shadows it.
+    fn lookup<T: ParameterTypeTraits>(
+        &self,
+        name: &str,
+    ) -> Result<Option<&ParsedParameter>, ParseError> {
+        let mut mismatch = None;
         for p in &self.params {
-            if *p.name == name && *p.type_name == T::TYPE_NAME {
-                return return_array(T::get_values(p), p, T::N_PER_ITEM, T::convert);
+            if *p.name != name {
+                continue;
+            }
+            if T::matches_type(&p.type_name) {
+                p.looked_up.store(true, Ordering::Relaxed);
+                return Ok(Some(p));
             }
+            mismatch.get_or_insert(p);
+        }
+        match mismatch {
+            Some(p) => Err(type_mismatch::<T>(p)),
+            None => Ok(None),
         }
-        vec![]
     }
 
-    fn lookup_single<T>(&self, name: &str, default: T) -> T
+    /// Reports the first parameter that nothing has looked up, which is
+    /// most likely misspelled or not supported by the object being created.
+    /// Every object creator calls this once it has read its parameters.
+    /// With the lenient option, all of them are reported as warnings instead.
+    pub fn report_unused(&self) -> Result<(), ParseError> {
+        for p in self.unused() {
+            let e = ParseError::with_token(
+                &p.loc,
+                format!("{} {}", p.type_name, p.name).as_bytes(),
+                "unused parameter",
+            );
+            if !options().lenient {
+                return Err(e);
+            }
+            warning(&e.loc, &format!("\"{}\": {}", e.token.unwrap(), e.message));
+        }
+        Ok(())
+    }
+
+    // Parameters that weren't looked up. Repeats of a parameter are left
+    // out, since only the first one is ever looked up.
+    fn unused(&self) -> impl Iterator<Item = &ParsedParameter> {
+        self.params.iter().enumerate().filter_map(|(i, p)| {
+            let repeated = self.params[..i]
+                .iter()
+                .any(|q| q.name == p.name && q.type_name == p.type_name);
+            (!p.looked_up.load(Ordering::Relaxed) && !p.may_be_unused && !repeated).then_some(p)
+        })
+    }
+
+    fn lookup_array<T: ParameterTypeTraits>(&self, name: &str) -> Result<Vec<T>, ParseError> {
+        match self.lookup::<T>(name)? {
+            Some(p) => return_array(p),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn lookup_single<T>(&self, name: &str, default: T) -> Result<T, ParseError>
     where
         T: ParameterTypeTraits,
     {
-        for p in &self.params {
-            if *p.name != name && *p.type_name != T::TYPE_NAME {
-                continue;
+        let Some(p) = self.lookup::<T>(name)? else {
+            return Ok(default);
+        };
+        let values = get_values::<T>(p)?;
+        if values.len() != T::N_PER_ITEM {
+            return Err(ParseError::new(
+                &p.loc,
+                format!(
+                    "expected {} values for parameter \"{}\"",
+                    T::N_PER_ITEM,
+                    p.name
+                ),
+            ));
+        }
+        Ok(T::convert(values))
+    }
+
+    // The spectra a parameter describes, or none if it isn't spectral.
+    fn extract_spectrum_array(
+        &self,
+        param: &ParsedParameter,
+        spectrum_type: SpectrumType,
+    ) -> Result<Vec<Arc<dyn Spectrum>>, ParseError> {
+        let spectra: Vec<Arc<dyn Spectrum>> = match param.type_name.as_str() {
+            "rgb" => {
+                let cs = param.color_space.or(self.color_space).unwrap_or(&sRGB);
+                float_chunks(param, 3)?
+                    .map(|v| rgb_spectrum(param, cs, RGB::new(v[0], v[1], v[2]), spectrum_type))
+                    .collect::<Result<_, _>>()?
             }
-            let values = T::get_values(p);
-            if values.is_empty() {
-                panic!("No values!");
+            "blackbody" => float_chunks(param, 1)?
+                .map(|v| Arc::new(BlackbodySpectrum::new(v[0])) as Arc<dyn Spectrum>)
+                .collect(),
+            "spectrum" if !param.floats.is_empty() => {
+                check_interleaved(&param.floats).map_err(|e| {
+                    ParseError::new(&param.loc, format!("parameter \"{}\": {}", param.name, e))
+                })?;
+                vec![Arc::new(PiecewiseLinearSpectrum::from_interleaved(
+                    &param.floats,
+                    false,
+                ))]
             }
-            if values.len() != T::N_PER_ITEM {
-                panic!("Expected {} values", T::N_PER_ITEM);
+            "spectrum" if !param.strings.is_empty() => param
+                .strings
+                .iter()
+                .map(|name| named_or_file_spectrum(param, name))
+                .collect::<Result<_, _>>()?,
+            "spectrum" => {
+                return Err(ParseError::new(
+                    &param.loc,
+                    format!("no values provided for parameter \"{}\"", param.name),
+                ))
+            }
+            _ => return Ok(vec![]),
+        };
+        param.looked_up.store(true, Ordering::Relaxed);
+        Ok(spectra)
+    }
+}
+
+/// A parameter dictionary for creating materials, whose parameters may name
+/// a texture instead of giving a value.
+pub struct TextureParameterDictionary<'a> {
+    dict: &'a ParameterDictionary,
+    textures: &'a NamedTextures,
+}
+
+impl<'a> TextureParameterDictionary<'a> {
+    pub fn new(dict: &'a ParameterDictionary, textures: &'a NamedTextures) -> Self {
+        Self { dict, textures }
+    }
+
+    pub fn dict(&self) -> &'a ParameterDictionary {
+        self.dict
+    }
+
+    /// Returns the texture the parameter called `name` refers to, or a
+    /// constant texture of its value, or of `default` if it isn't given.
+    pub fn get_float_texture(
+        &self,
+        name: &str,
+        default: Float,
+    ) -> Result<Arc<dyn FloatTexture>, ParseError> {
+        Ok(self
+            .get_float_texture_or_null(name)?
+            .unwrap_or_else(|| Arc::new(FloatConstantTexture::new(default))))
+    }
+
+    pub fn get_float_texture_or_null(
+        &self,
+        name: &str,
+    ) -> Result<Option<Arc<dyn FloatTexture>>, ParseError> {
+        for p in &self.dict.params {
+            if *p.name != name {
+                continue;
+            }
+            match p.type_name.as_str() {
+                "texture" => {
+                    let texname = texture_name(p)?;
+                    if let Some(tex) = self.textures.float_textures.get(texname) {
+                        return Ok(Some(tex.clone()));
+                    }
+                    let kind = self
+                        .textures
+                        .albedo_spectrum_textures
+                        .contains_key(texname)
+                        .then_some("spectrum");
+                    return Err(unknown_texture(p, texname, "float", kind));
+                }
+                "float" => {
+                    let v = self.dict.get_one_float(name, 0.0)?;
+                    return Ok(Some(Arc::new(FloatConstantTexture::new(v))));
+                }
+                _ => {}
             }
-            return T::convert(values);
         }
-        default
+        Ok(None)
     }
 
-    fn extract_spectrum_array<T: Spectrum>(
+    /// Returns the texture the parameter called `name` refers to, or a
+    /// constant texture of the spectrum it gives, or of `default`.
+    pub fn get_spectrum_texture(
         &self,
-        param: &ParsedParameter,
+        name: &str,
+        default: Option<Arc<dyn Spectrum>>,
+        spectrum_type: SpectrumType,
+    ) -> Result<Option<Arc<dyn SpectrumTexture>>, ParseError> {
+        if let Some(tex) = self.get_spectrum_texture_or_null(name, spectrum_type)? {
+            return Ok(Some(tex));
+        }
+        Ok(default.map(|s| Arc::new(SpectrumConstantTexture::new(s)) as Arc<dyn SpectrumTexture>))
+    }
+
+    pub fn get_spectrum_texture_or_null(
+        &self,
+        name: &str,
         spectrum_type: SpectrumType,
-    ) -> &[T] {
-        if *param.type_name == "rgb" {
-        } else if *param.type_name == "blackbody" {
-        } else if *param.type_name == "spectrum" && !param.floats.is_empty() {
-        } else if *param.type_name == "spectrum" && !param.strings.is_empty() {
+    ) -> Result<Option<Arc<dyn SpectrumTexture>>, ParseError> {
+        let textures = self.textures.spectrum_textures(spectrum_type);
+        for p in &self.dict.params {
+            if *p.name != name {
+                continue;
+            }
+            match p.type_name.as_str() {
+                "texture" => {
+                    let texname = texture_name(p)?;
+                    if let Some(tex) = textures.get(texname) {
+                        return Ok(Some(tex.clone()));
+                    }
+                    let kind = self
+                        .textures
+                        .float_textures
+                        .contains_key(texname)
+                        .then_some("float");
+                    return Err(unknown_texture(p, texname, "spectrum", kind));
+                }
+                "rgb" | "spectrum" | "blackbody" => {
+                    let s = self.dict.get_one_spectrum(name, None, spectrum_type)?;
+                    return Ok(s.map(|s| {
+                        Arc::new(SpectrumConstantTexture::new(s)) as Arc<dyn SpectrumTexture>
+                    }));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The name of the texture a "texture" parameter refers to.
+fn texture_name(p: &ParsedParameter) -> Result<&str, ParseError> {
+    if p.strings.len() != 1 {
+        return Err(ParseError::new(
+            &p.loc,
+            format!(
+                "expected a single texture name for parameter \"{}\"",
+                p.name
+            ),
+        ));
+    }
+    p.looked_up.store(true, Ordering::Relaxed);
+    Ok(&p.strings[0])
+}
+
+fn unknown_texture(
+    p: &ParsedParameter,
+    texname: &str,
+    expected: &str,
+    found: Option<&str>,
+) -> ParseError {
+    let message = match found {
+        Some(found) => format!(
+            "parameter \"{}\" needs a {} texture, but \"{}\" is a {} texture",
+            p.name, expected, texname, found
+        ),
+        None => format!(
+            "couldn't find {} texture named \"{}\" for parameter \"{}\"",
+            expected, texname, p.name
+        ),
+    };
+    ParseError::new(&p.loc, message)
+}
+
+// Splits a parameter's floats into groups of `n`, one per value.
+fn float_chunks(
+    param: &ParsedParameter,
+    n: usize,
+) -> Result<std::slice::Chunks<'_, Float>, ParseError> {
+    if param.floats.is_empty() {
+        return Err(ParseError::new(
+            &param.loc,
+            format!("no float values provided for parameter \"{}\"", param.name),
+        ));
+    }
+    if !param.floats.len().is_multiple_of(n) {
+        return Err(ParseError::new(
+            &param.loc,
+            format!(
+                "number of values provided for parameter \"{}\" is not a multiple of {}",
+                param.name, n
+            ),
+        ));
+    }
+    Ok(param.floats.chunks(n))
+}
+
+fn rgb_spectrum(
+    param: &ParsedParameter,
+    cs: &RGBColorSpace,
+    rgb: RGB,
+    spectrum_type: SpectrumType,
+) -> Result<Arc<dyn Spectrum>, ParseError> {
+    if rgb.min_component() < 0.0 {
+        return Err(ParseError::new(
+            &param.loc,
+            format!("RGB parameter \"{}\" has a negative component", param.name),
+        ));
+    }
+    Ok(match spectrum_type {
+        SpectrumType::Albedo => {
+            if rgb.max_component() > 1.0 {
+                return Err(ParseError::new(
+                    &param.loc,
+                    format!(
+                        "RGB albedo value ({}, {}, {}) of parameter \"{}\" has a component greater than one",
+                        rgb.r, rgb.g, rgb.b, param.name
+                    ),
+                ));
+            }
+            Arc::new(RGBAlbedoSpectrum::new(cs, rgb))
+        }
+        SpectrumType::Unbounded => Arc::new(RGBUnboundedSpectrum::new(cs, rgb)),
+        SpectrumType::Illuminant => Arc::new(RGBIlluminantSpectrum::new(cs, rgb)),
+    })
+}
+
+// Looks `name` up among the built-in spectra, and otherwise reads it as a
+// file of wavelength/value pairs next to the scene file that names it.
+fn named_or_file_spectrum(
+    param: &ParsedParameter,
+    name: &str,
+) -> Result<Arc<dyn Spectrum>, ParseError> {
+    if let Some(s) = get_named_spectrum(name) {
+        return Ok(s);
+    }
+    let filename = resolve_filename(name, &param.loc.filename);
+    let values = read_float_file(&filename)
+        .and_then(|values| check_interleaved(&values).map(|_| values))
+        .map_err(|e| {
+            ParseError::new(
+                &param.loc,
+                format!("unable to read spectrum file \"{}\": {}", filename, e),
+            )
+        })?;
+    Ok(Arc::new(PiecewiseLinearSpectrum::from_interleaved(
+        &values, false,
+    )))
+}
+
+// Checks that `values` are (wavelength, value) pairs with increasing
+// wavelengths.
+fn check_interleaved(values: &[Float]) -> Result<(), String> {
+    if !values.len().is_multiple_of(2) {
+        return Err(String::from(
+            "found an odd number of values; expected wavelength/value pairs",
+        ));
+    }
+    for (i, pair) in values.chunks(2).collect::<Vec<_>>().windows(2).enumerate() {
+        if pair[0][0] >= pair[1][0] {
+            return Err(format!(
+                "wavelengths aren't increasing at entry {}: {} >= {}",
+                i + 1,
+                pair[0][0],
+                pair[1][0]
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn param(type_name: &str, name: &str, line: i32) -> ParsedParameter {
+        let mut p = ParsedParameter::new();
+        p.type_name = INTERNED_STRINGS.lookup(&type_name.to_string());
+        p.name = INTERNED_STRINGS.lookup(&name.to_string());
+        p.loc = FileLoc {
+            filename: String::from("test"),
+            line,
+            column: 1,
+        };
+        p
+    }
+
+    fn floats(type_name: &str, name: &str, values: &[Float]) -> ParsedParameter {
+        let mut p = param(type_name, name, 1);
+        p.floats = values.to_vec();
+        p
+    }
+
+    fn dict(params: ParsedParameterVector) -> ParameterDictionary {
+        ParameterDictionary {
+            params,
+            color_space: None,
+        }
+    }
+
+    #[test]
+    fn get_one_matches_name_and_type() {
+        let mut ints = param("integer", "maxdepth", 1);
+        ints.ints = vec![7];
+        let mut bools = param("bool", "twosided", 2);
+        bools.bools = vec![1];
+        let mut strings = param("string", "filename", 3);
+        strings.strings = vec![String::from("a.exr")];
+        let d = dict(vec![
+            floats("float", "fov", &[45.]),
+            floats("float", "radius", &[2.]),
+            ints,
+            bools,
+            strings,
+            floats("point2", "center", &[1., 2.]),
+            floats("point3", "from", &[1., 2., 3.]),
+            floats("vector3", "dir", &[0., 0., 1.]),
+            floats("normal", "n", &[0., 1., 0.]),
+            floats("normal3", "n3", &[1., 0., 0.]),
+        ]);
+
+        assert_eq!(d.get_one_float("radius", 1.), Ok(2.));
+        assert_eq!(d.get_one_float("zmin", -1.), Ok(-1.));
+        assert_eq!(d.get_one_int("maxdepth", 5), Ok(7));
+        assert_eq!(d.get_one_int("pixelsamples", 16), Ok(16));
+        assert_eq!(d.get_one_bool("twosided", false), Ok(true));
+        assert_eq!(d.get_one_string("filename", "pbrt.exr").unwrap(), "a.exr");
+        assert_eq!(d.get_one_string("mapname", "").unwrap(), "");
+        assert_eq!(
+            d.get_one_point2f("center", Point2f::new(0., 0.)),
+            Ok(Point2f::new(1., 2.))
+        );
+        assert_eq!(
+            d.get_one_point3f("from", Point3f::new(0., 0., 0.)),
+            Ok(Point3f::new(1., 2., 3.))
+        );
+        assert_eq!(
+            d.get_one_vector3f("dir", Vector3f::new(0., 0., 0.)),
+            Ok(Vector3f::new(0., 0., 1.))
+        );
+        assert_eq!(
+            d.get_one_normal3f("n", Normal3f::new(0., 0., 0.)),
+            Ok(Normal3f::new(0., 1., 0.))
+        );
+        assert_eq!(
+            d.get_one_normal3f("n3", Normal3f::new(0., 0., 0.)),
+            Ok(Normal3f::new(1., 0., 0.))
+        );
+    }
+
+    #[test]
+    fn type_mismatch_is_an_error() {
+        let mut radius = param("integer", "radius", 4);
+        radius.ints = vec![2];
+        let d = dict(vec![radius]);
+        let e = d.get_one_float("radius", 1.).unwrap_err();
+        assert_eq!(e.loc.line, 4);
+        assert_eq!(
+            e.message,
+            "parameter \"radius\" has type \"integer\", but \"float\" was expected"
+        );
+        assert!(d.get_float_array("radius").is_err());
+
+        // A parameter of the right type wins over one with the same name.
+        let d = dict(vec![
+            param("integer", "radius", 4),
+            floats("float", "radius", &[3.]),
+        ]);
+        assert_eq!(d.get_one_float("radius", 1.), Ok(3.));
+    }
+
+    #[test]
+    fn wrong_number_of_values_is_an_error() {
+        let mut strings = param("float", "fov", 2);
+        strings.strings = vec![String::from("wide")];
+        let d = dict(vec![
+            floats("point3", "from", &[1., 2.]),
+            floats("point3", "P", &[0., 0., 0., 1., 1.]),
+            strings,
+        ]);
+        let e = d
+            .get_one_point3f("from", Point3f::new(0., 0., 0.))
+            .unwrap_err();
+        assert_eq!(e.message, "expected 3 values for parameter \"from\"");
+        let e = d.get_point3f_array("P").unwrap_err();
+        assert_eq!(
+            e.message,
+            "number of values provided for parameter \"P\" is not a multiple of 3"
+        );
+        let e = d.get_one_float("fov", 90.).unwrap_err();
+        assert_eq!(
+            (e.loc.line, e.message.as_str()),
+            (2, "no float values provided for parameter \"fov\"")
+        );
+    }
+
+    #[test]
+    fn get_arrays() {
+        let mut indices = param("integer", "indices", 1);
+        indices.ints = vec![0, 1, 2, 2, 3, 0];
+        let mut names = param("string", "materials", 1);
+        names.strings = vec![String::from("a"), String::from("b")];
+        let mut bools = param("bool", "flags", 1);
+        bools.bools = vec![1, 0];
+        let d = dict(vec![
+            indices,
+            names,
+            bools,
+            floats("float", "radii", &[0.5, 1.5]),
+            floats("point2", "uv", &[0., 0., 1., 1.]),
+            floats("point3", "P", &[0., 0., 0., 1., 2., 3.]),
+            floats("vector3", "S", &[1., 0., 0.]),
+            floats("normal", "N", &[0., 0., 1., 0., 1., 0.]),
+        ]);
+
+        assert_eq!(d.get_int_array("indices").unwrap(), vec![0, 1, 2, 2, 3, 0]);
+        assert_eq!(d.get_string_array("materials").unwrap(), vec!["a", "b"]);
+        assert_eq!(d.get_bool_array("flags").unwrap(), vec![true, false]);
+        assert_eq!(d.get_float_array("radii").unwrap(), vec![0.5, 1.5]);
+        assert_eq!(
+            d.get_point2f_array("uv").unwrap(),
+            vec![Point2f::new(0., 0.), Point2f::new(1., 1.)]
+        );
+        assert_eq!(
+            d.get_point3f_array("P").unwrap(),
+            vec![Point3f::new(0., 0., 0.), Point3f::new(1., 2., 3.)]
+        );
+        assert_eq!(
+            d.get_vector3f_array("S").unwrap(),
+            vec![Vector3f::new(1., 0., 0.)]
+        );
+        assert_eq!(
+            d.get_normal3f_array("N").unwrap(),
+            vec![Normal3f::new(0., 0., 1.), Normal3f::new(0., 1., 0.)]
+        );
+        assert!(d.get_float_array("missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn report_unused_names_the_parameter() {
+        let mut typo = param("interger", "pixelsamples", 3);
+        typo.ints = vec![16];
+        let d = dict(vec![floats("float", "fov", &[45.]), typo]);
+        assert_eq!(d.get_one_float("fov", 90.), Ok(45.));
+        let e = d.report_unused().unwrap_err();
+        assert_eq!(e.loc.line, 3);
+        assert_eq!(e.token.as_deref(), Some("interger pixelsamples"));
+        assert_eq!(e.message, "unused parameter");
+    }
+
+    #[test]
+    fn looked_up_parameters_are_not_reported() {
+        let d = dict(vec![
+            floats("float", "radius", &[1.]),
+            floats("point3", "P", &[0., 0., 0.]),
+            // Only the first of a repeated parameter is ever looked up.
+            floats("float", "radius", &[2.]),
+        ]);
+        assert_eq!(d.unused().count(), 2);
+        d.get_one_float("radius", 0.).unwrap();
+        d.get_point3f_array("P").unwrap();
+        assert_eq!(d.unused().count(), 0);
+        assert_eq!(d.report_unused(), Ok(()));
+
+        // Defaults don't mark anything.
+        assert_eq!(d.get_one_float("zmin", -1.), Ok(-1.));
+        assert_eq!(d.report_unused(), Ok(()));
+    }
+
+    fn spectrum(d: &ParameterDictionary, name: &str, t: SpectrumType) -> Arc<dyn Spectrum> {
+        d.get_one_spectrum(name, None, t).unwrap().unwrap()
+    }
+
+    #[test]
+    fn rgb_spectra() {
+        let d = dict(vec![
+            floats("rgb", "reflectance", &[0.5, 0.5, 0.5]),
+            floats("rgb", "sigma_a", &[4., 4., 4.]),
+        ]);
+        let r = spectrum(&d, "reflectance", SpectrumType::Albedo);
+        assert!((r.evaluate(500.) - 0.5).abs() < 1e-4);
+        let s = spectrum(&d, "sigma_a", SpectrumType::Unbounded);
+        assert!((s.evaluate(500.) - 4.).abs() < 1e-3);
+        assert_eq!(d.report_unused(), Ok(()));
+
+        let e = d
+            .get_one_spectrum("sigma_a", None, SpectrumType::Albedo)
+            .err()
+            .unwrap();
+        assert!(e.message.contains("greater than one"), "{}", e.message);
+        let d = dict(vec![floats("rgb", "L", &[1., -1., 1.])]);
+        assert!(d
+            .get_one_spectrum("L", None, SpectrumType::Illuminant)
+            .is_err());
+    }
+
+    #[test]
+    fn blackbody_and_inline_spectra() {
+        let d = dict(vec![
+            floats("blackbody", "L", &[5500.]),
+            floats("spectrum", "eta", &[400., 1.5, 700., 1.4]),
+            floats("spectrum", "odd", &[400., 1.5, 700.]),
+            floats("spectrum", "backwards", &[700., 1.5, 400., 1.4]),
+        ]);
+        let l = spectrum(&d, "L", SpectrumType::Illuminant);
+        assert!((l.evaluate(2.897_772e6 / 5500.) - 1.).abs() < 1e-4);
+        let eta = spectrum(&d, "eta", SpectrumType::Unbounded);
+        assert!((eta.evaluate(550.) - 1.45).abs() < 1e-6);
+        for name in ["odd", "backwards"] {
+            assert!(d
+                .get_one_spectrum(name, None, SpectrumType::Unbounded)
+                .is_err());
         }
-        &[]
+        // Defaults, and parameters that aren't spectra, are passed over.
+        let d = dict(vec![floats("float", "eta", &[1.5])]);
+        assert!(d
+            .get_one_spectrum("eta", None, SpectrumType::Unbounded)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn named_and_file_spectra() {
+        let dir = std::env::temp_dir().join(format!("rpbrt-spd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("glass.spd"), "# eta\n400 1.5\n700 1.4 # red\n").unwrap();
+
+        let mut named = param("spectrum", "eta", 1);
+        named.strings = vec![String::from("metal-Au-eta")];
+        let mut file = param("spectrum", "k", 2);
+        file.loc.filename = dir.join("scene.pbrt").to_string_lossy().into_owned();
+        file.strings = vec![String::from("glass.spd")];
+        let mut missing = param("spectrum", "missing", 3);
+        missing.strings = vec![String::from("nonexistent.spd")];
+        let d = dict(vec![named, file, missing]);
+
+        let eta = spectrum(&d, "eta", SpectrumType::Unbounded);
+        assert!(eta.evaluate(700.) < 0.2);
+        let k = spectrum(&d, "k", SpectrumType::Unbounded);
+        assert!((k.evaluate(550.) - 1.45).abs() < 1e-6);
+        let e = d
+            .get_one_spectrum("missing", None, SpectrumType::Unbounded)
+            .err()
+            .unwrap();
+        assert_eq!(e.loc.line, 3);
+        assert!(e.message.starts_with("unable to read spectrum file"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn texture_param(name: &str, texname: &str, line: i32) -> ParsedParameter {
+        let mut p = param("texture", name, line);
+        p.strings = vec![String::from(texname)];
+        p
+    }
+
+    fn textures() -> NamedTextures {
+        let mut textures = NamedTextures::default();
+        textures.float_textures.insert(
+            String::from("bumps"),
+            Arc::new(FloatConstantTexture::new(0.25)),
+        );
+        textures.albedo_spectrum_textures.insert(
+            String::from("checks"),
+            Arc::new(SpectrumConstantTexture::new(Arc::new(
+                crate::util::spectrum::ConstantSpectrum::new(0.5),
+            ))),
+        );
+        textures
+    }
+
+    #[test]
+    fn float_textures() {
+        let ctx = crate::texture::TextureEvalContext {
+            p: Point3f::new(0., 0., 0.),
+            uv: Point2f::new(0., 0.),
+        };
+        let textures = textures();
+        let d = dict(vec![
+            texture_param("displacement", "bumps", 1),
+            floats("float", "roughness", &[0.1]),
+            texture_param("eta", "nope", 3),
+            texture_param("uroughness", "checks", 4),
+        ]);
+        let t = TextureParameterDictionary::new(&d, &textures);
+        let tex = t.get_float_texture("displacement", 0.).unwrap();
+        assert_eq!(tex.evaluate(&ctx), 0.25);
+        let tex = t.get_float_texture("roughness", 0.).unwrap();
+        assert_eq!(tex.evaluate(&ctx), 0.1);
+        let tex = t.get_float_texture("vroughness", 0.5).unwrap();
+        assert_eq!(tex.evaluate(&ctx), 0.5);
+        assert!(t.get_float_texture_or_null("vroughness").unwrap().is_none());
+
+        let e = t.get_float_texture("eta", 1.5).err().unwrap();
+        assert_eq!(e.loc.line, 3);
+        assert_eq!(
+            e.message,
+            "couldn't find float texture named \"nope\" for parameter \"eta\""
+        );
+        let e = t.get_float_texture("uroughness", 0.).err().unwrap();
+        assert!(e.message.contains("is a spectrum texture"), "{}", e.message);
+    }
+
+    #[test]
+    fn spectrum_textures() {
+        let ctx = crate::texture::TextureEvalContext {
+            p: Point3f::new(0., 0., 0.),
+            uv: Point2f::new(0., 0.),
+        };
+        let textures = textures();
+        let d = dict(vec![
+            texture_param("reflectance", "checks", 1),
+            floats("rgb", "transmittance", &[0.5, 0.5, 0.5]),
+            texture_param("eta", "bumps", 3),
+        ]);
+        let t = TextureParameterDictionary::new(&d, &textures);
+        let tex = t
+            .get_spectrum_texture_or_null("reflectance", SpectrumType::Albedo)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tex.evaluate(&ctx, 500.), 0.5);
+        let tex = t
+            .get_spectrum_texture("transmittance", None, SpectrumType::Albedo)
+            .unwrap()
+            .unwrap();
+        assert!((tex.evaluate(&ctx, 500.) - 0.5).abs() < 1e-4);
+        assert!(t
+            .get_spectrum_texture("sigma_a", None, SpectrumType::Unbounded)
+            .unwrap()
+            .is_none());
+        // Textures are kept apart by how their RGB values are interpreted.
+        assert!(t
+            .get_spectrum_texture_or_null("reflectance", SpectrumType::Unbounded)
+            .is_err());
+        let e = t
+            .get_spectrum_texture_or_null("eta", SpectrumType::Unbounded)
+            .err()
+            .unwrap();
+        assert!(e.message.contains("is a float texture"), "{}", e.message);
+        assert_eq!(d.report_unused(), Ok(()));
     }
 }
@@ -1,25 +1,45 @@
-use std::mem::replace;
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
 
 use crate::{
     paramdict::{ParsedParameter, ParsedParameterVector},
     scene::INTERNED_STRINGS,
-    util::{
-        error::{error_exit, FileLoc},
-        file::read_file_contents,
-    },
+    util::error::warning,
+    util::file::{read_file_contents, resolve_filename, same_file},
+    util::string::InternedString,
     Float,
 };
 
+pub use crate::util::error::{ErrorContext, FileLoc, ParseError};
+
 pub trait ParserTarget {
-    fn scale(&mut self, sx: Float, sy: Float, sz: Float, loc: FileLoc);
+    fn scale(&mut self, sx: Float, sy: Float, sz: Float, loc: FileLoc) -> Result<(), ParseError>;
 
-    fn shape(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
+    fn shape(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
 
-    fn option(&mut self, name: &String, value: &String, loc: FileLoc);
+    fn option(&mut self, name: &str, value: &str, loc: FileLoc) -> Result<(), ParseError>;
 
-    fn identity(&mut self, loc: FileLoc);
-    fn translate(&mut self, dx: Float, dy: Float, dz: Float, loc: FileLoc);
-    fn rotate(&mut self, angle: Float, ax: Float, ay: Float, az: Float, loc: FileLoc);
+    fn identity(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn translate(
+        &mut self,
+        dx: Float,
+        dy: Float,
+        dz: Float,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn rotate(
+        &mut self,
+        angle: Float,
+        ax: Float,
+        ay: Float,
+        az: Float,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
     fn look_at(
         &mut self,
         ex: Float,
@@ -32,58 +52,175 @@ pub trait ParserTarget {
         uy: Float,
         uz: Float,
         loc: FileLoc,
-    );
-    fn concat_transform(&mut self, transform: [Float; 16], loc: FileLoc);
-    fn transform(&mut self, transform: [Float; 16], loc: FileLoc);
-    fn coordinate_system(&mut self, name: &String, loc: FileLoc);
-    fn coord_sys_transform(&mut self, name: &String, loc: FileLoc);
-    fn active_transform_all(&mut self, loc: FileLoc);
-    fn active_transform_end_time(&mut self, loc: FileLoc);
-    fn active_transform_start_time(&mut self, loc: FileLoc);
-    fn transform_times(&mut self, start: Float, end: Float, loc: FileLoc);
-
-    fn color_space(&mut self, name: &String, loc: FileLoc);
-    fn pixel_filter(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn film(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn accelerator(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn integrator(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn camera(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn make_named_medium(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn medium_interface(&mut self, inside_name: &String, outside_name: &String, loc: FileLoc);
-    fn sampler(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-
-    fn world_begin(&mut self, loc: FileLoc);
-    fn attribute_begin(&mut self, loc: FileLoc);
-    fn attribute_end(&mut self, loc: FileLoc);
-    fn attribute(&mut self, target: &str, params: ParsedParameterVector, loc: FileLoc);
+    ) -> Result<(), ParseError>;
+    fn concat_transform(&mut self, transform: [Float; 16], loc: FileLoc) -> Result<(), ParseError>;
+    fn transform(&mut self, transform: [Float; 16], loc: FileLoc) -> Result<(), ParseError>;
+    fn coordinate_system(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    fn coord_sys_transform(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    fn active_transform_all(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn active_transform_end_time(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn active_transform_start_time(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn transform_times(&mut self, start: Float, end: Float, loc: FileLoc)
+        -> Result<(), ParseError>;
+
+    fn color_space(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    fn pixel_filter(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn film(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn accelerator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn integrator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn camera(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn make_named_medium(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn medium_interface(
+        &mut self,
+        inside_name: &str,
+        outside_name: &str,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn sampler(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+
+    fn world_begin(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn attribute_begin(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn attribute_end(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn attribute(
+        &mut self,
+        target: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
     fn texture(
         &mut self,
-        name: &String,
-        typename: &String,
-        texname: &String,
+        name: &str,
+        typename: &str,
+        texname: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn make_named_material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn named_material(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    fn light_source(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError>;
+    fn area_light_source(
+        &mut self,
+        name: &str,
         params: ParsedParameterVector,
         loc: FileLoc,
-    );
-    fn material(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn make_named_material(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn named_material(&mut self, name: &String, loc: FileLoc);
-    fn light_source(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn area_light_source(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc);
-    fn reverse_orientation(&mut self, loc: FileLoc);
-    fn object_begin(&mut self, name: &String, loc: FileLoc);
-    fn object_end(&mut self, loc: FileLoc);
-    fn object_instance(&mut self, name: &String, loc: FileLoc);
+    ) -> Result<(), ParseError>;
+    fn reverse_orientation(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn object_begin(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    fn object_end(&mut self, loc: FileLoc) -> Result<(), ParseError>;
+    fn object_instance(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError>;
+    /// Handles `Import`: unlike `Include`, the file is parsed into a separate
+    /// target whose named entities are merged back once it has been read.
+    fn import(&mut self, tokenizer: Tokenizer, loc: FileLoc) -> Result<(), Vec<ParseError>>;
 
-    fn end_of_files(&mut self);
+    /// Asked before the file named by an `Include` or `Import` directive is
+    /// read. Targets that write the scene back out return `false` to keep
+    /// the directive as it is instead.
+    fn read_nested_file(
+        &mut self,
+        _directive: &str,
+        _filename: &str,
+        _loc: FileLoc,
+    ) -> Result<bool, ParseError> {
+        Ok(true)
+    }
+
+    /// Receives each comment, including its leading `#`, before the
+    /// directive that follows it.
+    fn comment(&mut self, _comment: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        Ok(())
+    }
 
-    // fn error_exit_deferred(&mut self);
-    // error_exit
+    /// Called once every file has been read. Returns all the errors that
+    /// were found in the scene but not reported as they were parsed.
+    fn end_of_files(&mut self) -> Result<(), Vec<ParseError>>;
 }
 
 #[derive(Default)]
-struct Token<'a> {
+struct Token {
     loc: FileLoc,
-    token: &'a [u8],
+    token: TokenText,
+}
+
+// The bytes of a token. Most are a range of the file they were read from,
+// which is shared rather than copied; only strings with escapes need a
+// decoded copy of their own.
+enum TokenText {
+    Source(Arc<SourceFile>, Range<usize>),
+    Decoded(Vec<u8>),
+}
+
+impl Default for TokenText {
+    fn default() -> Self {
+        TokenText::Decoded(Vec::new())
+    }
+}
+
+impl Deref for TokenText {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            TokenText::Source(source, range) => &source.contents[range.clone()],
+            TokenText::Decoded(bytes) => bytes,
+        }
+    }
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::with_token(&self.loc, &self.token, message)
+    }
 }
 
 impl ParsedParameter {
@@ -98,8 +235,8 @@ impl ParsedParameter {
     }
 
     fn add_string(&mut self, s: String) {
-        assert!(self.floats.is_empty() && self.strings.is_empty() && self.strings.is_empty());
-        self.strings.push(s.to_string());
+        assert!(self.floats.is_empty() && self.ints.is_empty() && self.bools.is_empty());
+        self.strings.push(s);
     }
 
     fn add_bool(&mut self, v: bool) {
@@ -108,85 +245,52 @@ impl ParsedParameter {
     }
 }
 
-// fn parse_int(t: Token) -> i32 {
-//     let negate = t.token[0] == b'-';
-//     let mut index = 0;
-//     if t.token[0] == b'-' || t.token[0] == b'+' {
-//         index += 1;
-//     }
-//     let mut value: i32 = 0;
-//     while index < t.token.len() {
-//         let n = t.token[index] as i32 - 48;
-//         if !(n >= 0 && n <= 9) {
-//             error_exit(Some(&t.loc), &format!("\"{}\": expected a number", n));
-//         }
-//         value = 10 * value + n;
-//     }
-//     if negate {
-//         -value
-//     } else {
-//         value
-//     }
-// }
-
 #[inline]
 fn is_quoted_string(str: &[u8]) -> bool {
     str.len() >= 2 && str[0] == b'"' && str[str.len() - 1] == b'"'
 }
 
-fn dequote_string(t: &Token) -> String {
-    if !is_quoted_string(t.token) {
-        error_exit(
-            Some(&t.loc),
-            &format!(
-                "\"{}\": expected quoted string",
-                std::str::from_utf8(t.token).unwrap()
-            ),
-        );
+fn dequote_string(t: &Token) -> Result<String, ParseError> {
+    if !is_quoted_string(&t.token) {
+        return Err(t.error("expected quoted string"));
     }
-    String::from_utf8(t.token[1..t.token.len() - 1].to_vec()).unwrap()
+    String::from_utf8(t.token[1..t.token.len() - 1].to_vec())
+        .map_err(|_| t.error("string is not valid UTF-8"))
 }
 
 const TOKEN_OPTIONAL: i32 = 0;
 const TOKEN_REQUIRED: i32 = 1;
 
 #[inline]
-fn unget<'a>(unget_token: &mut Option<Token<'a>>, t: Token<'a>) {
+fn unget(unget_token: &mut Option<Token>, t: Token) {
     *unget_token = Some(t);
 }
 
-// All next_token are the same?
-fn parse_parameters<'a>(
-    file_stack: &mut Vec<Tokenizer<'a>>,
-    unget_token: &mut Option<Token<'a>>,
-) -> ParsedParameterVector {
-    let parse_error = |msg: &str, loc: &FileLoc| error_exit(Some(loc), &format!("{}", msg));
+fn parse_parameters(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<ParsedParameterVector, ParseError> {
     let mut parameter_vector = ParsedParameterVector::new();
-    let error_call_back = |t: &Token, msg: &str| {
-        parse_error(
-            &format!("{}:{}", std::str::from_utf8(t.token).unwrap(), msg),
-            &t.loc,
-        )
-    };
     loop {
-        let t = next_token(file_stack, unget_token, TOKEN_OPTIONAL);
-
-        if t.is_none() {
-            return parameter_vector;
-        }
-        let t = t.unwrap();
-        if !is_quoted_string(t.token) {
+        let t = match next_token(file_stack, unget_token, TOKEN_OPTIONAL)? {
+            None => return Ok(parameter_vector),
+            Some(t) => t,
+        };
+        if !is_quoted_string(&t.token) {
             unget(unget_token, t);
-            return parameter_vector;
+            return Ok(parameter_vector);
         }
 
         let mut param = ParsedParameter::new();
-        let decl = dequote_string(&t);
+        let decl = dequote_string(&t)?;
         let mut iter = decl.split_whitespace();
-        param.type_name = INTERNED_STRINGS.lookup(&iter.next().unwrap().to_string());
-        param.name = INTERNED_STRINGS.lookup(&iter.next().unwrap().to_string());
-
-        // println!("{},{}", param.type_name, param.name);
+        match (iter.next(), iter.next(), iter.next()) {
+            (Some(type_name), Some(name), None) => {
+                param.type_name = INTERNED_STRINGS.lookup(&type_name.to_string());
+                param.name = INTERNED_STRINGS.lookup(&name.to_string());
+            }
+            _ => return Err(t.error("expected parameter type and name")),
+        }
 
         #[derive(PartialEq)]
         enum ValType {
@@ -202,73 +306,68 @@ fn parse_parameters<'a>(
             val_type = ValType::Int;
         }
 
-        let val = next_token(file_stack, unget_token, TOKEN_REQUIRED).unwrap();
+        let val = next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap();
 
-        let mut add_val = |t: Token| {
-            if is_quoted_string(t.token) {
-                match val_type {
-                    ValType::Unknown => val_type = ValType::String,
-                    ValType::String => {}
-                    ValType::Float => error_call_back(&t, "expected floating-point value"),
-                    ValType::Int => error_call_back(&t, "expected integer value"),
-                    ValType::Bool => error_call_back(&t, "expected Boolean value"),
-                }
-                param.add_string(dequote_string(&t));
-            } else if t.token[0] == b't' && t.token == b"true" {
+        let mut add_val = |t: Token| -> Result<(), ParseError> {
+            // Booleans may be quoted, as older scenes write them.
+            let quoted_bool = *param.type_name == "bool" && is_quoted_string(&t.token);
+            if quoted_bool {
                 match val_type {
                     ValType::Unknown => val_type = ValType::Bool,
-                    ValType::String => error_call_back(&t, "expected string value"),
-                    ValType::Float => error_call_back(&t, "expected floating-point value"),
-                    ValType::Int => error_call_back(&t, "expected integer value"),
                     ValType::Bool => {}
+                    _ => return Err(t.error("expected Boolean value")),
+                }
+                match &t.token[1..t.token.len() - 1] {
+                    b"true" => param.add_bool(true),
+                    b"false" => param.add_bool(false),
+                    _ => return Err(t.error("expected \"true\" or \"false\"")),
                 }
-                param.add_bool(true);
-            } else if t.token[0] == b'f' && t.token == b"false" {
+            } else if is_quoted_string(&t.token) {
+                match val_type {
+                    ValType::Unknown => val_type = ValType::String,
+                    ValType::String => {}
+                    ValType::Float => return Err(t.error("expected floating-point value")),
+                    ValType::Int => return Err(t.error("expected integer value")),
+                    ValType::Bool => return Err(t.error("expected Boolean value")),
+                }
+                param.add_string(dequote_string(&t)?);
+            } else if &*t.token == b"true" || &*t.token == b"false" {
                 match val_type {
                     ValType::Unknown => val_type = ValType::Bool,
-                    ValType::String => error_call_back(&t, "expected string value"),
-                    ValType::Float => error_call_back(&t, "expected floating-point value"),
-                    ValType::Int => error_call_back(&t, "expected integer value"),
+                    ValType::String => return Err(t.error("expected string value")),
+                    ValType::Float => return Err(t.error("expected floating-point value")),
+                    ValType::Int => return Err(t.error("expected integer value")),
                     ValType::Bool => {}
                 }
-                param.add_bool(false);
+                param.add_bool(&*t.token == b"true");
             } else {
                 match val_type {
                     ValType::Unknown => val_type = ValType::Float,
-                    ValType::String => error_call_back(&t, "expected string value"),
+                    ValType::String => return Err(t.error("expected string value")),
                     ValType::Float => {}
                     ValType::Int => {}
-                    ValType::Bool => error_call_back(&t, "expected Boolean value"),
+                    ValType::Bool => return Err(t.error("expected Boolean value")),
                 }
 
                 if val_type == ValType::Int {
-                    param.add_int(
-                        std::str::from_utf8(t.token)
-                            .unwrap()
-                            .parse::<i32>()
-                            .unwrap(),
-                    );
+                    param.add_int(parse_int(&t)?);
                 } else {
-                    param.add_float(
-                        std::str::from_utf8(t.token)
-                            .unwrap()
-                            .parse::<Float>()
-                            .unwrap(),
-                    );
+                    param.add_float(parse_float(&t)?);
                 }
             }
+            Ok(())
         };
 
-        if val.token == &[b'['] {
+        if &*val.token == b"[" {
             loop {
-                let val = next_token(file_stack, unget_token, TOKEN_REQUIRED).unwrap();
-                if val.token == &[b']'] {
+                let val = next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap();
+                if &*val.token == b"]" {
                     break;
                 }
-                add_val(val);
+                add_val(val)?;
             }
         } else {
-            add_val(val);
+            add_val(val)?;
         }
 
         param.loc = t.loc;
@@ -276,277 +375,1770 @@ fn parse_parameters<'a>(
     }
 }
 
-fn basic_param_list_entrypoint<'a>(
-    file_stack: &mut Vec<Tokenizer<'a>>,
-    unget_token: &mut Option<Token<'a>>,
-) -> (String, ParsedParameterVector) {
-    let t = next_token(file_stack, unget_token, TOKEN_REQUIRED).unwrap();
-    let dequoted = dequote_string(&t);
-    let parameter_vector = parse_parameters(file_stack, unget_token);
-    (dequoted, parameter_vector)
+fn basic_param_list_entrypoint(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<(String, ParsedParameterVector), ParseError> {
+    let dequoted = next_string(file_stack, unget_token)?;
+    let parameter_vector = parse_parameters(file_stack, unget_token)?;
+    Ok((dequoted, parameter_vector))
 }
 
-fn next_token<'a>(
-    file_stack: &mut Vec<Tokenizer<'a>>,
-    unget_token: &mut Option<Token<'a>>,
+// With TOKEN_REQUIRED, running out of input is an error, so a successful
+// result always holds a token.
+fn next_token(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
     flags: i32,
-) -> Option<Token<'a>> {
+) -> Result<Option<Token>, ParseError> {
     if unget_token.is_some() {
-        return replace(unget_token, None);
+        return Ok(unget_token.take());
     }
-    if file_stack.is_empty() {
-        if flags & TOKEN_REQUIRED != 0 {
-            error_exit(None, &"premature end of file".to_string());
+    loop {
+        let tokenizer = match file_stack.last_mut() {
+            None => return Ok(None),
+            Some(t) => t,
+        };
+        match tokenizer.next()? {
+            None => {
+                let finished = file_stack.pop().unwrap();
+                if file_stack.is_empty() && flags & TOKEN_REQUIRED != 0 {
+                    return Err(ParseError::new(&finished.loc, "premature end of file"));
+                }
+            }
+            tok => return Ok(tok),
         }
-        return None;
     }
-    let tok: Option<Token> = file_stack.last_mut().unwrap().next();
-    match tok {
-        None => {
-            println!(
-                "Finished parsing {}",
-                file_stack.last().unwrap().loc.filename
-            );
-            file_stack.pop();
-            return next_token(file_stack, unget_token, flags);
+}
+
+fn syntax_error(t: &Token) -> ParseError {
+    t.error("unknown directive")
+}
+
+fn parse_float(t: &Token) -> Result<Float, ParseError> {
+    std::str::from_utf8(&t.token)
+        .ok()
+        .and_then(|s| s.parse::<Float>().ok())
+        .ok_or_else(|| t.error("expected a number"))
+}
+
+fn parse_int(t: &Token) -> Result<i32, ParseError> {
+    std::str::from_utf8(&t.token)
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .ok_or_else(|| t.error("expected an integer"))
+}
+
+fn next_float(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<Float, ParseError> {
+    parse_float(&next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap())
+}
+
+fn next_string(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<String, ParseError> {
+    dequote_string(&next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap())
+}
+
+fn expect_token(t: Token, expected: &[u8]) -> Result<(), ParseError> {
+    if &*t.token != expected {
+        return Err(t.error(format!(
+            "expected \"{}\"",
+            String::from_utf8_lossy(expected)
+        )));
+    }
+    Ok(())
+}
+
+// Reads a bracketed list of 16 floats, as used by Transform and ConcatTransform.
+fn next_matrix(
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<[Float; 16], ParseError> {
+    expect_token(
+        next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap(),
+        b"[",
+    )?;
+    let mut m = [0 as Float; 16];
+    for v in m.iter_mut() {
+        *v = next_float(file_stack, unget_token)?;
+    }
+    expect_token(
+        next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap(),
+        b"]",
+    )?;
+    Ok(m)
+}
+
+// After an error, drops tokens up to the start of the next directive so that
+// the rest of the file can still be checked. Directives are the only
+// unquoted tokens that start with an upper-case letter.
+fn skip_to_next_directive(file_stack: &mut Vec<Tokenizer>, unget_token: &mut Option<Token>) {
+    loop {
+        match next_token(file_stack, unget_token, TOKEN_OPTIONAL) {
+            Ok(None) => return,
+            Ok(Some(t)) if t.token.first().is_some_and(u8::is_ascii_uppercase) => {
+                unget(unget_token, t);
+                return;
+            }
+            _ => {}
         }
-        // Some(t) if t.token.starts_with("#") => next_token(file_stack, flags),
-        _ => tok,
     }
 }
 
-fn parse(target: &mut dyn ParserTarget, t: Tokenizer) {
+/// Parses the tokens from `t` and any files it includes, calling the
+/// matching `target` method for each directive. Unless the tokenizer was
+/// set up to collect errors, parsing stops at the first one.
+pub fn parse(target: &mut dyn ParserTarget, t: Tokenizer) -> Result<(), Vec<ParseError>> {
+    let collect_errors = t.collect_errors;
+    let comments = t.comments.clone();
     let mut file_stack: Vec<Tokenizer> = Vec::new();
     file_stack.push(t);
 
     let mut unget_token = None;
+    let mut errors = Vec::new();
 
     loop {
-        let tok = next_token(&mut file_stack, &mut unget_token, TOKEN_OPTIONAL);
-        if tok.is_none() {
-            break;
-        }
-        let tok = tok.unwrap();
-        let loc = tok.loc;
-        let token = std::str::from_utf8(tok.token).unwrap();
-        // println!("Token: {}", token);
-
-        match token {
-            "AttributeBegin" => {
-                // target.attribute_begin(loc);
+        let result = match next_token(&mut file_stack, &mut unget_token, TOKEN_OPTIONAL) {
+            Ok(None) => break,
+            // Comments read before the directive are passed on first.
+            Ok(Some(tok)) => match flush_comments(target, &comments) {
+                Err(e) => Err(vec![e]),
+                Ok(()) => {
+                    // The directive came from the file on top of the stack;
+                    // remember it in case that file is finished by the time
+                    // an error in the directive is reported.
+                    let source = file_stack.last().map(|t| t.source.clone());
+                    parse_directive(target, tok, &mut file_stack, &mut unget_token).map_err(
+                        |mut errors| {
+                            add_context(
+                                &mut errors,
+                                source.iter().chain(&file_stack_sources(&file_stack)),
+                            );
+                            errors
+                        },
+                    )
+                }
+            },
+            Err(e) => {
+                let mut errors = vec![e];
+                add_context(&mut errors, &file_stack_sources(&file_stack));
+                Err(errors)
             }
-            "AttributeEnd" => {
-                // target.attribute_end(loc);
+        };
+        if let Err(e) = result {
+            if !collect_errors {
+                return Err(e);
             }
-            "Attribute" => {
-                // basic_param_list_entrypoint(target, ParserTarget::attribute, loc);
+            errors.extend(e);
+            skip_to_next_directive(&mut file_stack, &mut unget_token);
+        }
+    }
+    if let Err(e) = flush_comments(target, &comments) {
+        errors.push(e);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn flush_comments(
+    target: &mut dyn ParserTarget,
+    comments: &Mutex<Vec<Token>>,
+) -> Result<(), ParseError> {
+    let comments = std::mem::take(&mut *comments.lock().unwrap());
+    for c in comments {
+        target.comment(&String::from_utf8_lossy(&c.token), c.loc)?;
+    }
+    Ok(())
+}
+
+// Attaches the source line and include trail to each error from the first
+// of `sources` that it was found in.
+fn add_context<'a>(
+    errors: &mut [ParseError],
+    sources: impl IntoIterator<Item = &'a Arc<SourceFile>> + Clone,
+) {
+    for e in errors.iter_mut() {
+        let source = sources
+            .clone()
+            .into_iter()
+            .find(|s| s.filename == e.loc.filename);
+        if let Some(s) = source {
+            e.add_context(&s.contents, &s.include_chain);
+        }
+    }
+}
+
+// The files still being parsed, innermost first.
+fn file_stack_sources(file_stack: &[Tokenizer]) -> Vec<Arc<SourceFile>> {
+    file_stack.iter().rev().map(|t| t.source.clone()).collect()
+}
+
+fn parse_directive(
+    target: &mut dyn ParserTarget,
+    tok: Token,
+    file_stack: &mut Vec<Tokenizer>,
+    unget_token: &mut Option<Token>,
+) -> Result<(), Vec<ParseError>> {
+    let loc = tok.loc.clone();
+    let token = std::str::from_utf8(&tok.token).unwrap_or("");
+
+    match token {
+        "AttributeBegin" => target.attribute_begin(loc)?,
+        "AttributeEnd" => target.attribute_end(loc)?,
+        "Attribute" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.attribute(&name, params, loc)?;
+        }
+        "ActiveTransform" => {
+            let a = next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap();
+            match &*a.token {
+                b"All" => target.active_transform_all(loc)?,
+                b"EndTime" => target.active_transform_end_time(loc)?,
+                b"StartTime" => target.active_transform_start_time(loc)?,
+                _ => return Err(syntax_error(&a).into()),
             }
-            "ActiveTransform" => {}
-            "AreaLightSource" => {}
-            "Accelerator" => {}
-            "Camera" => {
-                let (name, params) = basic_param_list_entrypoint(&mut file_stack, &mut unget_token);
-                target.camera(&name, params, loc);
+        }
+        "AreaLightSource" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.area_light_source(&name, params, loc)?;
+        }
+        "Accelerator" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.accelerator(&name, params, loc)?;
+        }
+        "ConcatTransform" => {
+            let m = next_matrix(file_stack, unget_token)?;
+            target.concat_transform(m, loc)?;
+        }
+        "CoordinateSystem" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.coordinate_system(&name, loc)?;
+        }
+        "CoordSysTransform" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.coord_sys_transform(&name, loc)?;
+        }
+        "ColorSpace" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.color_space(&name, loc)?;
+        }
+        "Camera" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.camera(&name, params, loc)?;
+        }
+        "Film" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.film(&name, params, loc)?;
+        }
+        "Identity" => target.identity(loc)?,
+        "Include" => {
+            let filename = next_string(file_stack, unget_token)?;
+            if target.read_nested_file(token, &filename, loc.clone())? {
+                let parent = file_stack.last().unwrap();
+                let mut t = open_nested_file(parent, &filename, &loc)?;
+                // Comments in the included file go to the same target.
+                t.comments = parent.comments.clone();
+                file_stack.push(t);
             }
-            "Film" => {
-                let (name, params) = basic_param_list_entrypoint(&mut file_stack, &mut unget_token);
-                target.film(&name, params, loc);
+        }
+        "Import" => {
+            let filename = next_string(file_stack, unget_token)?;
+            if target.read_nested_file(token, &filename, loc.clone())? {
+                let t = open_nested_file(file_stack.last().unwrap(), &filename, &loc)?;
+                target.import(t, loc)?;
             }
-            "Integrator" => {
-                let (name, params) = basic_param_list_entrypoint(&mut file_stack, &mut unget_token);
-                target.integrator(&name, params, loc);
+        }
+        "Integrator" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.integrator(&name, params, loc)?;
+        }
+        "LightSource" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.light_source(&name, params, loc)?;
+        }
+        "LookAt" => {
+            let mut v = [0 as Float; 9];
+            for x in v.iter_mut() {
+                *x = next_float(file_stack, unget_token)?;
             }
-            "LookAt" => {
-                let mut v = [0 as Float; 9];
-                for i in 0..9 {
-                    v[i] = std::str::from_utf8(
-                        next_token(&mut file_stack, &mut unget_token, TOKEN_REQUIRED)
-                            .unwrap()
-                            .token,
-                    )
-                    .unwrap()
-                    .parse::<Float>()
-                    .unwrap();
+            target.look_at(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7], v[8], loc)?;
+        }
+        "MakeNamedMaterial" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.make_named_material(&name, params, loc)?;
+        }
+        "MakeNamedMedium" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.make_named_medium(&name, params, loc)?;
+        }
+        "Material" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.material(&name, params, loc)?;
+        }
+        "MediumInterface" => {
+            let inside = next_string(file_stack, unget_token)?;
+            // The outside medium is optional and defaults to the inside one.
+            let outside = match next_token(file_stack, unget_token, TOKEN_OPTIONAL)? {
+                Some(t) if is_quoted_string(&t.token) => dequote_string(&t)?,
+                Some(t) => {
+                    unget(unget_token, t);
+                    inside.clone()
                 }
-                target.look_at(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7], v[8], loc);
+                None => inside.clone(),
+            };
+            target.medium_interface(&inside, &outside, loc)?;
+        }
+        "NamedMaterial" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.named_material(&name, loc)?;
+        }
+        "ObjectBegin" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.object_begin(&name, loc)?;
+        }
+        "ObjectEnd" => target.object_end(loc)?,
+        "ObjectInstance" => {
+            let name = next_string(file_stack, unget_token)?;
+            target.object_instance(&name, loc)?;
+        }
+        "Option" => {
+            let name = next_string(file_stack, unget_token)?;
+            let value = next_token(file_stack, unget_token, TOKEN_REQUIRED)?.unwrap();
+            let value = String::from_utf8_lossy(&value.token).into_owned();
+            target.option(&name, &value, loc)?;
+        }
+        "PixelFilter" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.pixel_filter(&name, params, loc)?;
+        }
+        "ReverseOrientation" => target.reverse_orientation(loc)?,
+        "Rotate" => {
+            let mut v = [0 as Float; 4];
+            for x in v.iter_mut() {
+                *x = next_float(file_stack, unget_token)?;
             }
-            "PixelFilter" => {
-                let (name, params) = basic_param_list_entrypoint(&mut file_stack, &mut unget_token);
-                target.pixel_filter(&name, params, loc);
+            target.rotate(v[0], v[1], v[2], v[3], loc)?;
+        }
+        "Shape" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.shape(&name, params, loc)?;
+        }
+        "Sampler" => {
+            let (name, params) = basic_param_list_entrypoint(file_stack, unget_token)?;
+            target.sampler(&name, params, loc)?;
+        }
+        "Scale" => {
+            let mut v = [0 as Float; 3];
+            for x in v.iter_mut() {
+                *x = next_float(file_stack, unget_token)?;
             }
-            "Sampler" => {
-                let (name, params) = basic_param_list_entrypoint(&mut file_stack, &mut unget_token);
-                target.sampler(&name, params, loc);
+            target.scale(v[0], v[1], v[2], loc)?;
+        }
+        // pbrt-v4 deprecates TransformBegin/End in favor of AttributeBegin/End.
+        "TransformBegin" => target.attribute_begin(loc)?,
+        "TransformEnd" => target.attribute_end(loc)?,
+        "Transform" => {
+            let m = next_matrix(file_stack, unget_token)?;
+            target.transform(m, loc)?;
+        }
+        "Translate" => {
+            let mut v = [0 as Float; 3];
+            for x in v.iter_mut() {
+                *x = next_float(file_stack, unget_token)?;
             }
-            _ => {}
+            target.translate(v[0], v[1], v[2], loc)?;
         }
+        "TransformTimes" => {
+            let start = next_float(file_stack, unget_token)?;
+            let end = next_float(file_stack, unget_token)?;
+            target.transform_times(start, end, loc)?;
+        }
+        "Texture" => {
+            let name = next_string(file_stack, unget_token)?;
+            let typename = next_string(file_stack, unget_token)?;
+            let texname = next_string(file_stack, unget_token)?;
+            let params = parse_parameters(file_stack, unget_token)?;
+            target.texture(&name, &typename, &texname, params, loc)?;
+        }
+        "WorldBegin" => target.world_begin(loc)?,
+        // WorldEnd no longer has any meaning in pbrt-v4.
+        "WorldEnd" => {}
+        _ => return Err(syntax_error(&tok).into()),
     }
+    Ok(())
+}
+
+/// Controls whether parsing stops at the first error in a file or carries on
+/// to report every error it finds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorMode {
+    #[default]
+    StopAtFirst,
+    CollectAll,
 }
 
+/// Parses the given scene files in order, or standard input if there are
+/// none, and finally calls `target.end_of_files()`.
 pub fn parse_files(
     target: &mut dyn ParserTarget,
     filenames: Vec<String>,
-) -> Result<(), &'static str> {
-    let tok_error = |msg: &'static str, loc: &FileLoc| error_exit(Some(loc), &format!("{}", msg));
-    if filenames.is_empty() {
-        // TODO: stdin file description
-        return Err("No file description given.");
+    error_mode: ErrorMode,
+) -> Result<(), Vec<ParseError>> {
+    let filenames = if filenames.is_empty() {
+        // Parse scene from standard input
+        vec![String::from("-")]
     } else {
-        for f in &filenames {
-            let contents = read_file_contents(f);
-            let t = Tokenizer::new(f, &contents, tok_error);
-            parse(target, t);
+        filenames
+    };
+    let mut errors = Vec::new();
+    for f in &filenames {
+        let mut t = Tokenizer::from_file(f, Vec::new())
+            .map_err(|e| ParseError::new(&FileLoc::new(f.clone()), e.to_string()))?;
+        t.collect_errors = error_mode == ErrorMode::CollectAll;
+        if let Err(e) = parse(target, t) {
+            if error_mode == ErrorMode::StopAtFirst {
+                return Err(e);
+            }
+            errors.extend(e);
         }
     }
 
-    println!("{:?}", INTERNED_STRINGS);
-
-    Ok(())
+    if errors.is_empty() {
+        target.end_of_files()?;
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
-// pub struct Tokenizer {
-//     contents: String,
-//     // end: &char,
-//     // s_escaped
-// }
-
-struct Tokenizer<'a> {
-    contents: &'a Vec<u8>,
-    // pos: Peekable<CharIndices>,
-    pos: usize,
-    end: usize,
-    loc: FileLoc,
-    error_call_back: ErrorCallBack,
+/// A `ParserTarget` that writes the scene description back out in a
+/// canonical form: one directive per line with its parameters on the lines
+/// below, and attribute and object blocks indented. Comments are kept, and
+/// `Include` and `Import` are written out rather than expanded. This is what
+/// `pbrt --format` prints.
+///
+/// When upgrading, pbrt-v3 entities and parameters are rewritten to their
+/// pbrt-v4 equivalents as they go by, with a warning for anything that
+/// can't be carried over exactly (`pbrt --upgrade`).
+#[derive(Default)]
+pub struct FormattingParserTarget {
+    output: String,
+    indent: usize,
+    upgrade: bool,
 }
 
-type ErrorCallBack = fn(&'static str, &FileLoc);
+// Values are wrapped onto new lines once a parameter gets this long.
+const FORMAT_LINE_WIDTH: usize = 80;
 
-impl<'a> Tokenizer<'a> {
-    pub fn new(filename: &String, contents: &'a Vec<u8>, error_call_back: ErrorCallBack) -> Self {
+impl FormattingParserTarget {
+    pub fn new(upgrade: bool) -> Self {
         Self {
-            contents: contents,
-            loc: FileLoc::new(String::from(filename)),
-            error_call_back,
-            // pos: contents.char_indices().peekable(),
-            pos: 0,
-            end: contents.len(),
+            upgrade,
+            ..Default::default()
         }
     }
 
-    // pub fn new(str: String, filename: &String, error_call_back: ErrorCallBack) -> Self {
-    //     Self {
-    //         loc: FileLoc::new(String::from(filename)),
-    //         contents: str,
-    //         error_call_back,
-    //         // pos: str.chars(),
-    //     }
-    // }
+    /// The scene description formatted so far.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
 
-    pub fn next(&mut self) -> Option<Token<'a>> {
-        loop {
-            let token_start = self.pos;
-            let start_loc = self.loc.clone();
+    fn dent(&self, extra: usize) -> String {
+        " ".repeat(4 * self.indent + extra)
+    }
 
-            let ch = self.get_char();
-            if ch.is_none() {
-                return None;
-            }
-            match ch.unwrap() {
-                ' ' | '\n' | '\t' | '\r' => {}
-                '"' => {
-                    let mut have_escaped = false;
-                    loop {
-                        let mut ch = self.get_char();
-                        match ch {
-                            Some('"') => break,
-                            None => {
-                                (self.error_call_back)("premature EOF", &start_loc);
-                                return None;
-                            }
-                            Some('\n') => {
-                                (self.error_call_back)("unterminated string", &start_loc);
-                                return None;
-                            }
-                            Some('\\') => {
-                                have_escaped = true;
-                                ch = self.get_char();
-                                if ch.is_none() {
-                                    (self.error_call_back)("premature EOF", &start_loc);
-                                    return None;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    if !have_escaped {
-                        return Some(Token {
-                            loc: start_loc,
-                            token: &self.contents[token_start..self.pos],
-                        });
-                    } else {
-                        todo!()
-                    }
-                }
-                '[' | ']' => {
-                    return Some(Token {
-                        loc: start_loc,
-                        token: &self.contents[token_start..token_start + 1],
-                    })
-                }
-                '#' => loop {
-                    let ch = self.get_char();
-                    match ch {
-                        None => break,
-                        Some('\n' | '\r') => {
-                            self.unget_char();
-                            break;
-                        }
-                        _ => {}
-                    }
-                },
-                _ => {
-                    loop {
-                        let ch = self.get_char();
-                        match ch {
-                            None => break,
-                            Some(' ' | '\n' | '\t' | '\r' | '"' | '[' | ']') => {
-                                self.unget_char();
-                                break;
-                            }
-                            _ => {}
-                        }
-                    }
-                    return Some(Token {
-                        loc: start_loc,
-                        token: &self.contents[token_start..self.pos],
-                    });
+    fn line(&mut self, text: &str) {
+        let dent = self.dent(0);
+        self.output += &dent;
+        self.output += text;
+        self.output.push('\n');
+    }
+
+    // Applies `upgrade` and the upgrades common to all parameter lists when
+    // upgrading, and otherwise leaves the entity as it is.
+    fn upgrade_entity(
+        &self,
+        name: &str,
+        mut params: ParsedParameterVector,
+        loc: &FileLoc,
+        upgrade: fn(&mut String, &mut ParsedParameterVector, &FileLoc),
+    ) -> (String, ParsedParameterVector) {
+        let mut name = name.to_string();
+        if self.upgrade {
+            upgrade_parameter_types(&mut params);
+            upgrade(&mut name, &mut params, loc);
+        }
+        (name, params)
+    }
+
+    fn entity(&mut self, directive: &str, name: &str, params: &ParsedParameterVector) {
+        self.line(&format!("{} {}", directive, quote_string(name)));
+        self.parameters(params);
+    }
+
+    fn parameters(&mut self, params: &ParsedParameterVector) {
+        for p in params {
+            let values: Vec<String> = if !p.floats.is_empty() {
+                p.floats.iter().map(|f| f.to_string()).collect()
+            } else if !p.ints.is_empty() {
+                p.ints.iter().map(|i| i.to_string()).collect()
+            } else if !p.strings.is_empty() {
+                p.strings.iter().map(|s| quote_string(s)).collect()
+            } else {
+                p.bools.iter().map(|&b| (b != 0).to_string()).collect()
+            };
+
+            let mut line = format!(
+                "{}{} [",
+                self.dent(4),
+                quote_string(&format!("{} {}", p.type_name, p.name))
+            );
+            for v in values {
+                if line.len() + v.len() + 3 > FORMAT_LINE_WIDTH && line.ends_with(|c| c != '[') {
+                    self.output += &line;
+                    self.output.push('\n');
+                    line = self.dent(8);
+                } else {
+                    line.push(' ');
                 }
+                line += &v;
             }
+            self.output += &line;
+            self.output += " ]\n";
         }
     }
 
-    #[inline]
-    fn get_char(&mut self) -> Option<char> {
-        // let next = self.pos.next();
-        if self.pos == self.end {
-            return None;
-        }
-        let ch = self.contents[self.pos] as char;
-        self.pos += 1;
-        if ch == '\n' {
-            self.loc.line += 1;
-            self.loc.column = 0;
-        } else {
-            self.loc.column += 1;
-        }
-        Some(ch as char)
+    fn floats(&mut self, directive: &str, values: &[Float]) {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        self.line(&format!("{} {}", directive, values.join(" ")));
     }
+}
 
-    #[inline]
+fn intern(s: &str) -> InternedString {
+    INTERNED_STRINGS.lookup(&s.to_string())
+}
+
+fn new_parameter(type_name: &str, name: &str, loc: &FileLoc) -> ParsedParameter {
+    let mut p = ParsedParameter::new();
+    p.type_name = intern(type_name);
+    p.name = intern(name);
+    p.loc = loc.clone();
+    p
+}
+
+fn rename_parameter(params: &mut ParsedParameterVector, from: &str, to: &str) {
+    for p in params.iter_mut().filter(|p| *p.name == from) {
+        p.name = intern(to);
+    }
+}
+
+fn remove_parameter(params: &mut ParsedParameterVector, name: &str) -> Option<ParsedParameter> {
+    let i = params.iter().position(|p| *p.name == name)?;
+    Some(params.remove(i))
+}
+
+// Removes a parameter that pbrt-v4 has no equivalent for. There is nothing
+// to warn about if it was set to `unchanged`, the value that v4 behaves as
+// if it had.
+fn drop_parameter(
+    params: &mut ParsedParameterVector,
+    name: &str,
+    unchanged: Option<Float>,
+    entity: &str,
+) {
+    if let Some(p) = remove_parameter(params, name) {
+        let constant = !p.floats.is_empty() && p.floats.iter().all(|&v| Some(v) == unchanged);
+        if !constant {
+            warning(
+                &p.loc,
+                &format!(
+                    "\"{}\": parameter has no pbrt-v4 equivalent for {} and was removed",
+                    name, entity
+                ),
+            );
+        }
+    }
+}
+
+// pbrt-v4 takes some parameters that were spectra in pbrt-v3 as plain
+// floats. That only works out if all the components are the same.
+fn spectrum_to_float(p: &mut ParsedParameter) -> bool {
+    match p.type_name.as_str() {
+        "float" => true,
+        "rgb" | "spectrum"
+            if !p.floats.is_empty() && p.floats.iter().all(|&v| v == p.floats[0]) =>
+        {
+            p.type_name = intern("float");
+            p.floats.truncate(1);
+            true
+        }
+        _ => false,
+    }
+}
+
+// Type names that changed between pbrt-v3 and pbrt-v4. Booleans given as
+// strings, which pbrt-v3 accepted, are already read as Booleans.
+fn upgrade_parameter_types(params: &mut ParsedParameterVector) {
+    for p in params.iter_mut() {
+        match p.type_name.as_str() {
+            "point" => p.type_name = intern("point3"),
+            "vector" => p.type_name = intern("vector3"),
+            "color" => p.type_name = intern("rgb"),
+            _ => {}
+        }
+    }
+}
+
+fn no_upgrade(_name: &mut String, _params: &mut ParsedParameterVector, _loc: &FileLoc) {}
+
+fn upgrade_shape(name: &mut String, params: &mut ParsedParameterVector, _loc: &FileLoc) {
+    // Texture coordinates were given as plain floats.
+    for p in params.iter_mut() {
+        if *p.type_name == "float" && (*p.name == "uv" || *p.name == "st") {
+            p.type_name = intern("point2");
+            p.name = intern("uv");
+        }
+    }
+    if name == "loopsubdiv" {
+        rename_parameter(params, "nlevels", "levels");
+    }
+}
+
+fn upgrade_film(name: &mut String, params: &mut ParsedParameterVector, _loc: &FileLoc) {
+    if name == "image" {
+        *name = String::from("rgb");
+    }
+    rename_parameter(params, "maxsampleluminance", "maxcomponentvalue");
+    drop_parameter(params, "scale", Some(1.), "films");
+}
+
+fn upgrade_integrator(name: &mut String, params: &mut ParsedParameterVector, loc: &FileLoc) {
+    match name.as_str() {
+        "directlighting" => {
+            // Only direct lighting is a path of depth one, though the v3
+            // integrator's "maxdepth" followed specular bounces beyond that.
+            *name = String::from("path");
+            if remove_parameter(params, "maxdepth").is_some() {
+                warning(
+                    loc,
+                    "\"directlighting\" integrator's \"maxdepth\" no longer follows specular \
+                     bounces; upgraded to \"path\" with \"maxdepth\" 1",
+                );
+            }
+            remove_parameter(params, "strategy");
+            let mut maxdepth = new_parameter("integer", "maxdepth", loc);
+            maxdepth.add_int(1);
+            params.push(maxdepth);
+        }
+        "whitted" => {
+            warning(loc, "\"whitted\" integrator upgraded to \"path\"");
+            *name = String::from("path");
+        }
+        "sppm" => {
+            rename_parameter(params, "numiterations", "iterations");
+            // Only affected how often the image was written out.
+            remove_parameter(params, "imagewritefrequency");
+        }
+        _ => {}
+    }
+}
+
+fn upgrade_camera(name: &mut String, params: &mut ParsedParameterVector, loc: &FileLoc) {
+    if name == "environment" {
+        *name = String::from("spherical");
+        let mut mapping = new_parameter("string", "mapping", loc);
+        mapping.add_string(String::from("equirectangular"));
+        params.push(mapping);
+    }
+}
+
+fn upgrade_sampler(name: &mut String, _params: &mut ParsedParameterVector, _loc: &FileLoc) {
+    match name.as_str() {
+        "lowdiscrepancy" | "02sequence" => *name = String::from("paddedsobol"),
+        "maxmindist" => *name = String::from("pmj02bn"),
+        "random" => *name = String::from("independent"),
+        _ => {}
+    }
+}
+
+fn upgrade_material(name: &mut String, params: &mut ParsedParameterVector, loc: &FileLoc) {
+    rename_parameter(params, "bumpmap", "displacement");
+    match name.as_str() {
+        "" | "none" => *name = String::from("interface"),
+        "matte" => {
+            *name = String::from("diffuse");
+            rename_parameter(params, "Kd", "reflectance");
+            drop_parameter(params, "sigma", Some(0.), "matte");
+        }
+        "plastic" => {
+            *name = String::from("coateddiffuse");
+            rename_parameter(params, "Kd", "reflectance");
+            drop_parameter(params, "Ks", None, "plastic");
+        }
+        "substrate" => {
+            *name = String::from("coateddiffuse");
+            rename_parameter(params, "Kd", "reflectance");
+            drop_parameter(params, "Ks", None, "substrate");
+        }
+        "uber" => {
+            *name = String::from("coateddiffuse");
+            rename_parameter(params, "Kd", "reflectance");
+            drop_parameter(params, "Ks", None, "uber");
+            drop_parameter(params, "Kr", Some(0.), "uber");
+            drop_parameter(params, "Kt", Some(0.), "uber");
+            drop_parameter(params, "opacity", Some(1.), "uber");
+        }
+        "glass" => {
+            *name = String::from("dielectric");
+            rename_parameter(params, "index", "eta");
+            drop_parameter(params, "Kr", Some(1.), "glass");
+            drop_parameter(params, "Kt", Some(1.), "glass");
+        }
+        "metal" => *name = String::from("conductor"),
+        "mirror" => {
+            *name = String::from("conductor");
+            rename_parameter(params, "Kr", "reflectance");
+            if !params.iter().any(|p| *p.name == "reflectance") {
+                // pbrt-v3's default "Kr".
+                let mut reflectance = new_parameter("rgb", "reflectance", loc);
+                for _ in 0..3 {
+                    reflectance.add_float(0.9);
+                }
+                params.push(reflectance);
+            }
+        }
+        "translucent" => {
+            *name = String::from("diffusetransmission");
+            rename_parameter(params, "reflect", "reflectance");
+            rename_parameter(params, "transmit", "transmittance");
+            drop_parameter(params, "Kd", None, "translucent");
+            drop_parameter(params, "Ks", None, "translucent");
+        }
+        "kdsubsurface" => {
+            *name = String::from("subsurface");
+            rename_parameter(params, "Kd", "reflectance");
+            drop_parameter(params, "Kr", Some(1.), "kdsubsurface");
+        }
+        "mix" => {
+            // The two named materials became a single string array.
+            let m1 = remove_parameter(params, "namedmaterial1");
+            let m2 = remove_parameter(params, "namedmaterial2");
+            if m1.is_some() || m2.is_some() {
+                let mut materials = new_parameter("string", "materials", loc);
+                for m in [m1, m2] {
+                    materials.add_string(
+                        m.and_then(|m| m.strings.first().cloned())
+                            .unwrap_or_default(),
+                    );
+                }
+                params.push(materials);
+            }
+        }
+        "disney" => {
+            warning(loc, "\"disney\" material upgraded to \"diffuse\"");
+            *name = String::from("diffuse");
+            params.retain(|p| *p.name == "color" || *p.name == "displacement");
+            rename_parameter(params, "color", "reflectance");
+        }
+        _ => {}
+    }
+}
+
+// A named material's type is given by its "string type" parameter.
+fn upgrade_named_material(_name: &mut String, params: &mut ParsedParameterVector, loc: &FileLoc) {
+    let Some(i) = params.iter().position(|p| *p.name == "type") else {
+        return;
+    };
+    let mut material = params[i].strings.first().cloned().unwrap_or_default();
+    upgrade_material(&mut material, params, loc);
+    if let Some(p) = params.iter_mut().find(|p| *p.name == "type") {
+        p.strings = vec![material];
+    }
+}
+
+fn upgrade_light(name: &mut String, params: &mut ParsedParameterVector, loc: &FileLoc) {
+    // Only affected how the light was sampled, not what it looks like.
+    remove_parameter(params, "nsamples");
+    if let Some(p) = params.iter_mut().find(|p| *p.name == "scale") {
+        if !spectrum_to_float(p) {
+            warning(&p.loc, "\"scale\" must be a float in pbrt-v4; removed");
+            remove_parameter(params, "scale");
+        }
+    }
+    if name == "infinite" {
+        rename_parameter(params, "mapname", "filename");
+        if params.iter().any(|p| *p.name == "filename") && remove_parameter(params, "L").is_some() {
+            warning(
+                loc,
+                "\"L\" can't be given with an environment map in pbrt-v4; removed",
+            );
+        }
+    }
+}
+
+fn upgrade_texture(
+    typename: &mut String,
+    texname: &str,
+    params: &mut ParsedParameterVector,
+    _loc: &FileLoc,
+) {
+    if typename == "color" {
+        *typename = String::from("spectrum");
+    }
+    match texname {
+        "scale" => {
+            rename_parameter(params, "tex1", "tex");
+            rename_parameter(params, "tex2", "scale");
+            if let Some(p) = params.iter_mut().find(|p| *p.name == "scale") {
+                if *p.type_name != "texture" && !spectrum_to_float(p) {
+                    warning(
+                        &p.loc,
+                        "\"scale\" texture's \"tex2\" must be a float in pbrt-v4; removed",
+                    );
+                    remove_parameter(params, "scale");
+                }
+            }
+        }
+        "imagemap" => {
+            if let Some(gamma) = remove_parameter(params, "gamma") {
+                let mut encoding = new_parameter("string", "encoding", &gamma.loc);
+                let srgb = gamma.bools.first().is_some_and(|&b| b != 0);
+                encoding.add_string(String::from(if srgb { "sRGB" } else { "linear" }));
+                params.push(encoding);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Quotes a string, escaping the characters that the tokenizer unescapes.
+fn quote_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\x08' => quoted += "\\b",
+            '\x0c' => quoted += "\\f",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            '\\' => quoted += "\\\\",
+            '"' => quoted += "\\\"",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl ParserTarget for FormattingParserTarget {
+    fn scale(&mut self, sx: Float, sy: Float, sz: Float, _loc: FileLoc) -> Result<(), ParseError> {
+        self.floats("Scale", &[sx, sy, sz]);
+        Ok(())
+    }
+
+    fn shape(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_shape);
+        self.entity("Shape", &name, &params);
+        Ok(())
+    }
+
+    fn option(&mut self, name: &str, value: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("Option {} {}", quote_string(name), value));
+        Ok(())
+    }
+
+    fn identity(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("Identity");
+        Ok(())
+    }
+
+    fn translate(
+        &mut self,
+        dx: Float,
+        dy: Float,
+        dz: Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        self.floats("Translate", &[dx, dy, dz]);
+        Ok(())
+    }
+
+    fn rotate(
+        &mut self,
+        angle: Float,
+        ax: Float,
+        ay: Float,
+        az: Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        self.floats("Rotate", &[angle, ax, ay, az]);
+        Ok(())
+    }
+
+    fn look_at(
+        &mut self,
+        ex: Float,
+        ey: Float,
+        ez: Float,
+        lx: Float,
+        ly: Float,
+        lz: Float,
+        ux: Float,
+        uy: Float,
+        uz: Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        // Eye, look-at point and up vector each go on their own line.
+        self.floats("LookAt", &[ex, ey, ez]);
+        self.floats("      ", &[lx, ly, lz]);
+        self.floats("      ", &[ux, uy, uz]);
+        Ok(())
+    }
+
+    fn concat_transform(
+        &mut self,
+        transform: [Float; 16],
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let values: Vec<String> = transform.iter().map(|v| v.to_string()).collect();
+        self.line(&format!("ConcatTransform [ {} ]", values.join(" ")));
+        Ok(())
+    }
+
+    fn transform(&mut self, transform: [Float; 16], _loc: FileLoc) -> Result<(), ParseError> {
+        let values: Vec<String> = transform.iter().map(|v| v.to_string()).collect();
+        self.line(&format!("Transform [ {} ]", values.join(" ")));
+        Ok(())
+    }
+
+    fn coordinate_system(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("CoordinateSystem {}", quote_string(name)));
+        Ok(())
+    }
+
+    fn coord_sys_transform(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("CoordSysTransform {}", quote_string(name)));
+        Ok(())
+    }
+
+    fn active_transform_all(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("ActiveTransform All");
+        Ok(())
+    }
+
+    fn active_transform_end_time(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("ActiveTransform EndTime");
+        Ok(())
+    }
+
+    fn active_transform_start_time(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("ActiveTransform StartTime");
+        Ok(())
+    }
+
+    fn transform_times(
+        &mut self,
+        start: Float,
+        end: Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        self.floats("TransformTimes", &[start, end]);
+        Ok(())
+    }
+
+    fn color_space(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("ColorSpace {}", quote_string(name)));
+        Ok(())
+    }
+
+    fn pixel_filter(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, no_upgrade);
+        self.entity("PixelFilter", &name, &params);
+        Ok(())
+    }
+
+    fn film(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_film);
+        self.entity("Film", &name, &params);
+        Ok(())
+    }
+
+    fn accelerator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, no_upgrade);
+        self.entity("Accelerator", &name, &params);
+        Ok(())
+    }
+
+    fn integrator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_integrator);
+        self.entity("Integrator", &name, &params);
+        Ok(())
+    }
+
+    fn camera(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_camera);
+        self.entity("Camera", &name, &params);
+        Ok(())
+    }
+
+    fn make_named_medium(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, no_upgrade);
+        self.entity("MakeNamedMedium", &name, &params);
+        Ok(())
+    }
+
+    fn medium_interface(
+        &mut self,
+        inside_name: &str,
+        outside_name: &str,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if inside_name == outside_name {
+            self.line(&format!("MediumInterface {}", quote_string(inside_name)));
+        } else {
+            self.line(&format!(
+                "MediumInterface {} {}",
+                quote_string(inside_name),
+                quote_string(outside_name)
+            ));
+        }
+        Ok(())
+    }
+
+    fn sampler(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_sampler);
+        self.entity("Sampler", &name, &params);
+        Ok(())
+    }
+
+    fn world_begin(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.line("WorldBegin");
+        self.output.push('\n');
+        Ok(())
+    }
+
+    fn attribute_begin(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("AttributeBegin");
+        self.indent += 1;
+        Ok(())
+    }
+
+    fn attribute_end(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.indent = self.indent.saturating_sub(1);
+        self.line("AttributeEnd");
+        Ok(())
+    }
+
+    fn attribute(
+        &mut self,
+        target: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (target, params) = self.upgrade_entity(target, params, &loc, no_upgrade);
+        self.entity("Attribute", &target, &params);
+        Ok(())
+    }
+
+    fn texture(
+        &mut self,
+        name: &str,
+        typename: &str,
+        texname: &str,
+        mut params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let mut typename = typename.to_string();
+        if self.upgrade {
+            upgrade_parameter_types(&mut params);
+            upgrade_texture(&mut typename, texname, &mut params, &loc);
+        }
+        self.line(&format!(
+            "Texture {} {} {}",
+            quote_string(name),
+            quote_string(&typename),
+            quote_string(texname)
+        ));
+        self.parameters(&params);
+        Ok(())
+    }
+
+    fn material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_material);
+        self.entity("Material", &name, &params);
+        Ok(())
+    }
+
+    fn make_named_material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_named_material);
+        self.entity("MakeNamedMaterial", &name, &params);
+        Ok(())
+    }
+
+    fn named_material(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("NamedMaterial {}", quote_string(name)));
+        Ok(())
+    }
+
+    fn light_source(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_light);
+        self.entity("LightSource", &name, &params);
+        Ok(())
+    }
+
+    fn area_light_source(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let (name, params) = self.upgrade_entity(name, params, &loc, upgrade_light);
+        self.entity("AreaLightSource", &name, &params);
+        Ok(())
+    }
+
+    fn reverse_orientation(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line("ReverseOrientation");
+        Ok(())
+    }
+
+    fn object_begin(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("ObjectBegin {}", quote_string(name)));
+        self.indent += 1;
+        Ok(())
+    }
+
+    fn object_end(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.indent = self.indent.saturating_sub(1);
+        self.line("ObjectEnd");
+        Ok(())
+    }
+
+    fn object_instance(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(&format!("ObjectInstance {}", quote_string(name)));
+        Ok(())
+    }
+
+    // Never called, since read_nested_file keeps Import directives as they
+    // are.
+    fn import(&mut self, _tokenizer: Tokenizer, _loc: FileLoc) -> Result<(), Vec<ParseError>> {
+        Ok(())
+    }
+
+    fn read_nested_file(
+        &mut self,
+        directive: &str,
+        filename: &str,
+        _loc: FileLoc,
+    ) -> Result<bool, ParseError> {
+        self.line(&format!("{} {}", directive, quote_string(filename)));
+        Ok(false)
+    }
+
+    fn comment(&mut self, comment: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.line(comment);
+        Ok(())
+    }
+
+    fn end_of_files(&mut self) -> Result<(), Vec<ParseError>> {
+        Ok(())
+    }
+}
+
+#[inline]
+fn get_escaped_char(c: char) -> Option<u8> {
+    match c {
+        'b' => Some(b'\x08'),
+        'f' => Some(b'\x0c'),
+        'n' => Some(b'\n'),
+        'r' => Some(b'\r'),
+        't' => Some(b'\t'),
+        '\\' => Some(b'\\'),
+        '\'' => Some(b'\''),
+        '"' => Some(b'"'),
+        _ => None,
+    }
+}
+
+// Opens a file named by Include or Import. Relative names are resolved
+// against the including file, and re-entering a file that is still being
+// parsed is an error, whose include trail shows how it was reached.
+fn open_nested_file(
+    parent: &Tokenizer,
+    filename: &str,
+    loc: &FileLoc,
+) -> Result<Tokenizer, ParseError> {
+    let filename = resolve_filename(filename, &parent.source.filename);
+    let mut include_chain = parent.source.include_chain.clone();
+    include_chain.push(loc.clone());
+    if include_chain
+        .iter()
+        .any(|l| same_file(&l.filename, &filename))
+    {
+        return Err(ParseError::new(loc, format!("{}: include cycle", filename)));
+    }
+    let mut t = Tokenizer::from_file(&filename, include_chain)
+        .map_err(|e| ParseError::new(loc, format!("{}: {}", filename, e)))?;
+    t.collect_errors = parent.collect_errors;
+    Ok(t)
+}
+
+// A file being tokenized, shared with the errors reported against it.
+struct SourceFile {
+    filename: String,
+    contents: Vec<u8>,
+    // Locations of the Include or Import directives that led to this file,
+    // outermost first.
+    include_chain: Vec<FileLoc>,
+}
+
+pub struct Tokenizer {
+    source: Arc<SourceFile>,
+    // pos: Peekable<CharIndices>,
+    pos: usize,
+    end: usize,
+    loc: FileLoc,
+    collect_errors: bool,
+    // Comments are kept here for parse() to hand to the target. Included
+    // files share their parent's list, so that none are lost when a file
+    // ends.
+    comments: Arc<Mutex<Vec<Token>>>,
+}
+
+impl Tokenizer {
+    pub fn new(filename: &str, contents: Vec<u8>) -> Self {
+        Self::with_include_chain(filename, contents, Vec::new())
+    }
+
+    fn with_include_chain(filename: &str, contents: Vec<u8>, include_chain: Vec<FileLoc>) -> Self {
+        Self {
+            end: contents.len(),
+            source: Arc::new(SourceFile {
+                filename: String::from(filename),
+                contents,
+                include_chain,
+            }),
+            loc: FileLoc::new(String::from(filename)),
+            // pos: contents.char_indices().peekable(),
+            pos: 0,
+            collect_errors: false,
+            comments: Default::default(),
+        }
+    }
+
+    fn from_file(filename: &str, include_chain: Vec<FileLoc>) -> std::io::Result<Self> {
+        Ok(Self::with_include_chain(
+            filename,
+            read_file_contents(filename)?,
+            include_chain,
+        ))
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, ParseError> {
+        loop {
+            let token_start = self.pos;
+            let start_loc = self.loc.clone();
+
+            let ch = match self.get_char() {
+                None => return Ok(None),
+                Some(ch) => ch,
+            };
+            match ch {
+                ' ' | '\n' | '\t' | '\r' => {}
+                '"' => {
+                    // Only strings that contain escapes need a decoded copy.
+                    let mut escaped: Option<Vec<u8>> = None;
+                    // An unknown escape is reported once the whole string has
+                    // been consumed, so that tokenizing can resume after it.
+                    let mut escape_error = None;
+                    loop {
+                        let ch = self.get_char();
+                        match ch {
+                            Some('"') => break,
+                            None => return Err(ParseError::new(&start_loc, "premature EOF")),
+                            Some('\n') => {
+                                return Err(ParseError::new(&start_loc, "unterminated string"))
+                            }
+                            Some('\\') => {
+                                let mut escape_loc = self.loc.clone();
+                                escape_loc.column -= 1;
+                                let decoded = escaped.get_or_insert_with(|| {
+                                    self.source.contents[token_start..self.pos - 1].to_vec()
+                                });
+                                match self.get_char() {
+                                    None => {
+                                        return Err(ParseError::new(&start_loc, "premature EOF"))
+                                    }
+                                    Some(c) => match get_escaped_char(c) {
+                                        Some(e) => decoded.push(e),
+                                        None => {
+                                            escape_error.get_or_insert(ParseError::with_token(
+                                                &escape_loc,
+                                                &[b'\\', c as u8],
+                                                "unexpected escaped character",
+                                            ));
+                                        }
+                                    },
+                                }
+                            }
+                            Some(c) => {
+                                if let Some(decoded) = escaped.as_mut() {
+                                    decoded.push(c as u8);
+                                }
+                            }
+                        }
+                    }
+                    if let Some(e) = escape_error {
+                        return Err(e);
+                    }
+                    let token = match escaped {
+                        None => self.text(token_start..self.pos),
+                        Some(mut decoded) => {
+                            decoded.push(b'"');
+                            TokenText::Decoded(decoded)
+                        }
+                    };
+                    return Ok(Some(Token {
+                        loc: start_loc,
+                        token,
+                    }));
+                }
+                '[' | ']' => {
+                    return Ok(Some(Token {
+                        loc: start_loc,
+                        token: self.text(token_start..token_start + 1),
+                    }))
+                }
+                '#' => {
+                    loop {
+                        let ch = self.get_char();
+                        match ch {
+                            None => break,
+                            Some('\n' | '\r') => {
+                                self.unget_char();
+                                break;
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.comments.lock().unwrap().push(Token {
+                        loc: start_loc,
+                        token: self.text(token_start..self.pos),
+                    });
+                }
+                _ => {
+                    loop {
+                        let ch = self.get_char();
+                        match ch {
+                            None => break,
+                            Some(' ' | '\n' | '\t' | '\r' | '"' | '[' | ']') => {
+                                self.unget_char();
+                                break;
+                            }
+                            _ => {}
+                        }
+                    }
+                    return Ok(Some(Token {
+                        loc: start_loc,
+                        token: self.text(token_start..self.pos),
+                    }));
+                }
+            }
+        }
+    }
+
+    fn text(&self, range: Range<usize>) -> TokenText {
+        TokenText::Source(self.source.clone(), range)
+    }
+
+    #[inline]
+    fn get_char(&mut self) -> Option<char> {
+        // let next = self.pos.next();
+        if self.pos == self.end {
+            return None;
+        }
+        let ch = self.source.contents[self.pos] as char;
+        self.pos += 1;
+        if ch == '\n' {
+            self.loc.line += 1;
+            self.loc.column = 1;
+        } else {
+            self.loc.column += 1;
+        }
+        Some(ch)
+    }
+
+    #[inline]
     fn unget_char(&mut self) {
         self.pos -= 1;
-        if self.contents[self.pos] == b'\n' {
+        let contents = &self.source.contents;
+        if contents[self.pos] == b'\n' {
+            // Back to the end of the previous line, whose length we have to
+            // find again.
+            let line_start = contents[..self.pos]
+                .iter()
+                .rposition(|&c| c == b'\n')
+                .map_or(0, |i| i + 1);
             self.loc.line -= 1;
+            self.loc.column = (self.pos - line_start) as i32 + 1;
+        } else {
+            self.loc.column -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokenize(s: &str) -> Result<Vec<Vec<u8>>, ParseError> {
+        let mut t = Tokenizer::new("test", s.as_bytes().to_vec());
+        let mut tokens = Vec::new();
+        while let Some(tok) = t.next()? {
+            tokens.push(tok.token.to_vec());
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn tokenizer_unescaped_string() {
+        assert_eq!(
+            tokenize(r#""foo bar""#).unwrap(),
+            vec![b"\"foo bar\"".to_vec()]
+        );
+    }
+
+    #[test]
+    fn tokenizer_escapes() {
+        let cases: [(&str, &[u8]); 8] = [
+            (r#""a\bc""#, b"\"a\x08c\""),
+            (r#""a\fc""#, b"\"a\x0cc\""),
+            (r#""a\nc""#, b"\"a\nc\""),
+            (r#""a\rc""#, b"\"a\rc\""),
+            (r#""a\tc""#, b"\"a\tc\""),
+            (r#""a\\c""#, b"\"a\\c\""),
+            (r#""a\'c""#, b"\"a'c\""),
+            (r#""a\"c""#, b"\"a\"c\""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                tokenize(input).unwrap(),
+                vec![expected.to_vec()],
+                "{}",
+                input
+            );
         }
     }
+
+    #[test]
+    fn tokenizer_copies_only_escaped_strings() {
+        let mut t = Tokenizer::new("test", br#"Shape "a b" "a\tb" 1"#.to_vec());
+        let mut copied = Vec::new();
+        while let Some(tok) = t.next().unwrap() {
+            copied.push(matches!(tok.token, TokenText::Decoded(_)));
+        }
+        assert_eq!(copied, vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn tokenizer_escaped_quote_at_end() {
+        let tokens = tokenize(r#"Shape "plymesh" "string filename" "a\\b\"" 1"#).unwrap();
+        assert_eq!(tokens[3], b"\"a\\b\"\"".to_vec());
+        assert_eq!(tokens[4], b"1".to_vec());
+    }
+
+    #[test]
+    fn tokenizer_unknown_escape() {
+        let e = tokenize("Shape\n \"ab\\qc\"").unwrap_err();
+        assert_eq!((e.loc.line, e.loc.column), (2, 5));
+        assert_eq!(e.token.as_deref(), Some("\\q"));
+        assert_eq!(e.message, "unexpected escaped character");
+    }
+
+    #[test]
+    fn quoted_bools_are_bools() {
+        let parameters = |s: &str| {
+            let mut file_stack = vec![Tokenizer::new("test", s.as_bytes().to_vec())];
+            parse_parameters(&mut file_stack, &mut None)
+        };
+        let params = parameters(r#""bool a" "true" "bool b" [ "false" true ]"#).unwrap();
+        assert_eq!(params[0].bools, vec![1]);
+        assert_eq!(params[1].bools, vec![0, 1]);
+        assert!(params.iter().all(|p| p.strings.is_empty()));
+
+        let e = parameters(r#""bool a" "yes""#).err().unwrap();
+        assert_eq!(e.token.as_deref(), Some("\"yes\""));
+        assert_eq!(e.message, "expected \"true\" or \"false\"");
+        // Only Booleans are unquoted.
+        let params = parameters(r#""string s" "true""#).unwrap();
+        assert_eq!(params[0].strings, vec!["true".to_string()]);
+    }
+
+    #[test]
+    fn parse_collects_all_errors() {
+        use crate::scene::{BasicScene, BasicSceneBuilder};
+        use std::sync::Arc;
+
+        let scene = "Film \"rgb\" \"integer xresolution\" [ 1.5 ]\n\
+                     Bogus 1 2\n\
+                     Sampler \"halton\" \"integer pixelsamples\" 16\n\
+                     LookAt 0 0 0 1 1 1 0 x 0\n";
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        let mut t = Tokenizer::new("test", scene.as_bytes().to_vec());
+        t.collect_errors = true;
+        let errors = parse(&mut builder, t).unwrap_err();
+        let lines: Vec<i32> = errors.iter().map(|e| e.loc.line).collect();
+        assert_eq!(lines, vec![1, 2, 4]);
+        assert_eq!(errors[1].token.as_deref(), Some("Bogus"));
+
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        let t = Tokenizer::new("test", scene.as_bytes().to_vec());
+        assert_eq!(parse(&mut builder, t).unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn parse_files_collects_errors_of_every_file() {
+        use crate::scene::{BasicScene, BasicSceneBuilder};
+
+        let dir = std::env::temp_dir().join(format!("rpbrt-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.pbrt");
+        let b = dir.join("b.pbrt");
+        std::fs::write(&a, "Bogus 1\nLookAt 0 0 0 1 1 1 0 x 0\n").unwrap();
+        std::fs::write(&b, "WorldBegin\nShape sphere\n").unwrap();
+        let filenames: Vec<String> = [&a, &b]
+            .iter()
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect();
+        let errors = |error_mode| {
+            let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+            parse_files(&mut builder, filenames.clone(), error_mode).unwrap_err()
+        };
+        let collected = errors(ErrorMode::CollectAll);
+        let stopped = errors(ErrorMode::StopAtFirst);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<(String, i32)> = (collected.iter())
+            .map(|e| (e.loc.filename.clone(), e.loc.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (filenames[0].clone(), 1),
+                (filenames[0].clone(), 2),
+                (filenames[1].clone(), 2),
+            ]
+        );
+        assert_eq!(stopped.len(), 1);
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        use crate::scene::{BasicScene, BasicSceneBuilder};
+
+        let dir = std::env::temp_dir().join(format!("rpbrt-cycle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.pbrt");
+        std::fs::write(&a, "Include \"b.pbrt\"\n").unwrap();
+        std::fs::write(dir.join("b.pbrt"), "Include \"a.pbrt\"\n").unwrap();
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        let filenames = vec![a.to_string_lossy().into_owned()];
+        let errors = parse_files(&mut builder, filenames, ErrorMode::StopAtFirst).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            errors[0].message,
+            format!("{}: include cycle", a.to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn parse_error_has_source_line() {
+        use crate::scene::{BasicScene, BasicSceneBuilder};
+        use std::sync::Arc;
+
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        let t = Tokenizer::new("test", b"# header\r\n\tBogus 1\n".to_vec());
+        let e = &parse(&mut builder, t).unwrap_err()[0];
+        assert_eq!((e.loc.line, e.loc.column), (2, 2));
+        let context = e.context.as_ref().unwrap();
+        assert_eq!(context.source_line.as_deref(), Some("\tBogus 1"));
+        assert!(context.include_trail.is_empty());
+    }
+
+    #[test]
+    fn include_is_found_in_search_directories() {
+        use crate::scene::{BasicScene, BasicSceneBuilder};
+        use crate::util::file::set_search_directories;
+        use std::sync::Arc;
+
+        let dir = std::env::temp_dir().join(format!("rpbrt-search-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("geometry.pbrt"), "Shape \"sphere\"\n").unwrap();
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        let t = Tokenizer::new("test", b"WorldBegin\nInclude \"geometry.pbrt\"\n".to_vec());
+        set_search_directories(vec![dir.clone()]);
+        let result = parse(&mut builder, t);
+        set_search_directories(Vec::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        builder.end_of_files().unwrap();
+        assert_eq!(scene.shapes.lock().unwrap().len(), 1);
+    }
+
+    fn format_with(scene: &str, upgrade: bool) -> String {
+        let mut formatter = FormattingParserTarget::new(upgrade);
+        parse(
+            &mut formatter,
+            Tokenizer::new("test", scene.as_bytes().to_vec()),
+        )
+        .unwrap();
+        formatter.output().to_string()
+    }
+
+    fn format(scene: &str) -> String {
+        format_with(scene, false)
+    }
+
+    #[test]
+    fn format_indents_blocks_and_keeps_comments() {
+        let scene = "# camera\nCamera \"perspective\" \"float fov\" 45\n\
+                     WorldBegin AttributeBegin # light\n\
+                     LightSource \"point\" \"bool twosided\" true AttributeEnd\n\
+                     Include \"geometry.pbrt\"\n";
+        assert_eq!(
+            format(scene),
+            "# camera\n\
+             Camera \"perspective\"\n    \"float fov\" [ 45 ]\n\
+             \nWorldBegin\n\n\
+             AttributeBegin\n\
+             \x20   # light\n\
+             \x20   LightSource \"point\"\n        \"bool twosided\" [ true ]\n\
+             AttributeEnd\n\
+             Include \"geometry.pbrt\"\n"
+        );
+    }
+
+    #[test]
+    fn format_round_trips() {
+        let scene = r#"LookAt 0 0 5 0 0 0 0 1 0
+            Film "rgb" "string filename" "a \"b\"\tc.exr" "integer xresolution" 400
+            WorldBegin
+            ObjectBegin "obj"
+            Shape "trianglemesh" "point3 P" [ 0 0 0 1 0 0 1 1 0 0.5 0.25 0.125 -1 -2 -3 0.1 0.2 0.3 ]
+                "integer indices" [ 0 1 2 ]
+            ObjectEnd
+            MediumInterface "fog" ""
+            Texture "checks" "spectrum" "checkerboard" "rgb tex1" [ 1 0 0 ]
+        "#;
+        let formatted = format(scene);
+        assert_eq!(format(&formatted), formatted);
+        assert!(formatted.contains(r#""string filename" [ "a \"b\"\tc.exr" ]"#));
+    }
+
+    #[test]
+    fn upgrade_v3_scene() {
+        let scene = r#"Film "image" "float maxsampleluminance" 10
+            Sampler "lowdiscrepancy"
+            Integrator "directlighting" "string strategy" "all"
+            Texture "t" "color" "scale" "texture tex1" "a" "color tex2" [ .5 .5 .5 ]
+            Material "uber" "color Kd" [ .5 .5 .5 ] "float opacity" 1 "texture bumpmap" "b"
+            MakeNamedMaterial "m" "string type" "matte" "color Kd" [ 1 0 0 ]
+            Shape "trianglemesh" "point P" [ 0 0 0 ] "float uv" [ 0 0 ] "bool alpha" "false"
+        "#;
+        let expected = [
+            "Film \"rgb\"",
+            "    \"float maxcomponentvalue\" [ 10 ]",
+            "Sampler \"paddedsobol\"",
+            "Integrator \"path\"",
+            "    \"integer maxdepth\" [ 1 ]",
+            "Texture \"t\" \"spectrum\" \"scale\"",
+            "    \"texture tex\" [ \"a\" ]",
+            "    \"float scale\" [ 0.5 ]",
+            "Material \"coateddiffuse\"",
+            "    \"rgb reflectance\" [ 0.5 0.5 0.5 ]",
+            "    \"texture displacement\" [ \"b\" ]",
+            "MakeNamedMaterial \"m\"",
+            "    \"string type\" [ \"diffuse\" ]",
+            "    \"rgb reflectance\" [ 1 0 0 ]",
+            "Shape \"trianglemesh\"",
+            "    \"point3 P\" [ 0 0 0 ]",
+            "    \"point2 uv\" [ 0 0 ]",
+            "    \"bool alpha\" [ false ]",
+        ];
+        assert_eq!(
+            format_with(scene, true).lines().collect::<Vec<_>>(),
+            expected
+        );
+
+        // Without upgrading, the v3 names are left alone.
+        assert!(format(scene).contains("Material \"uber\""));
+    }
 }
@@ -1,3 +1,269 @@
-pub fn init_pbrt() {
-    // Color
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{RwLock, RwLockReadGuard};
+
+use once_cell::sync::Lazy;
+
+use crate::Float;
+
+/// The space that rendering computations happen in. Rendering near the
+/// camera keeps floating-point error small where it matters most.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderingCoordinateSystem {
+    /// Camera space, oriented like the camera.
+    Camera,
+    /// World space translated so that the camera is at the origin.
+    #[default]
+    CameraWorld,
+    World,
+}
+
+impl RenderingCoordinateSystem {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "camera" => Some(Self::Camera),
+            "cameraworld" => Some(Self::CameraWorld),
+            "world" => Some(Self::World),
+            _ => None,
+        }
+    }
+}
+
+/// Options that control how pbrt runs. They can be given both on the
+/// command line and by the scene's `Option` directive; options from the
+/// command line take precedence.
+#[derive(Clone, Debug)]
+pub struct PbrtOptions {
+    /// Seed for the random number generators.
+    pub seed: i32,
+    /// The number of threads to use; zero uses one per core.
+    pub n_threads: usize,
+    /// Report unused parameters as warnings rather than errors.
+    pub lenient: bool,
+    /// Trade image quality for speed, for previews.
+    pub quick_render: bool,
+    /// Overrides the sampler's number of samples per pixel.
+    pub pixel_samples: Option<i32>,
+    /// Overrides the film's crop window, as `[x0, x1, y0, y1]` in [0, 1].
+    pub crop_window: Option<[Float; 4]>,
+    /// Overrides the film's pixel bounds, as `[x0, x1, y0, y1]`.
+    pub pixel_bounds: Option<[i32; 4]>,
+    /// Overrides the filename the film writes the image to.
+    pub image_file: Option<String>,
+    /// Sample the center of each pixel.
+    pub disable_pixel_jitter: bool,
+    /// Use the same wavelengths for every sample.
+    pub disable_wavelength_jitter: bool,
+    /// Look up textures without filtering.
+    pub disable_texture_filtering: bool,
+    /// Replace every material with a diffuse one.
+    pub force_diffuse: bool,
+    pub rendering_space: RenderingCoordinateSystem,
+    /// Scales the edge length that displaced shapes are tessellated to.
+    pub displacement_edge_scale: Float,
+    /// An image to compute the mean squared error of the render against.
+    pub mse_reference_image: Option<String>,
+    /// Where to write the mean squared error after each pass.
+    pub mse_reference_output: Option<String>,
+    /// Record statistics about each pixel's samples.
+    pub record_pixel_statistics: bool,
+    /// Print statistics gathered while loading and rendering once done.
+    pub print_statistics: bool,
+    // The normalized names of the options given on the command line.
+    command_line: HashSet<String>,
+}
+
+impl Default for PbrtOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            n_threads: 0,
+            lenient: false,
+            quick_render: false,
+            pixel_samples: None,
+            crop_window: None,
+            pixel_bounds: None,
+            image_file: None,
+            disable_pixel_jitter: false,
+            disable_wavelength_jitter: false,
+            disable_texture_filtering: false,
+            force_diffuse: false,
+            rendering_space: RenderingCoordinateSystem::default(),
+            displacement_edge_scale: 1.0,
+            mse_reference_image: None,
+            mse_reference_output: None,
+            record_pixel_statistics: false,
+            print_statistics: false,
+            command_line: HashSet::new(),
+        }
+    }
+}
+
+/// Option names are matched ignoring case, dashes and underscores, so that
+/// `--disable-pixel-jitter` and `"disablepixeljitter"` are the same option.
+pub fn normalize_option_name(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '-' && c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl PbrtOptions {
+    /// Whether the option called `name` is a switch, which needs no value
+    /// on the command line.
+    pub fn is_switch(name: &str) -> bool {
+        matches!(
+            normalize_option_name(name).as_str(),
+            "lenient"
+                | "quick"
+                | "disablepixeljitter"
+                | "disablewavelengthjitter"
+                | "disabletexturefiltering"
+                | "forcediffuse"
+                | "pixelstats"
+                | "stats"
+        )
+    }
+
+    /// Sets an option from the command line, after which the `Option`
+    /// directive leaves it alone.
+    pub fn set_from_command_line(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.set(name, value)?;
+        self.command_line.insert(normalize_option_name(name));
+        Ok(())
+    }
+
+    /// Sets the option called `name` from its value as text. Returns
+    /// whether the option changed, which it doesn't if it was given on
+    /// the command line.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        let name = normalize_option_name(name);
+        if self.command_line.contains(&name) {
+            return Ok(false);
+        }
+        fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("\"{}\": invalid option value", value))
+        }
+        fn parse_bool(value: &str) -> Result<bool, String> {
+            match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!(
+                    "\"{}\": expected \"true\" or \"false\" for option value",
+                    value
+                )),
+            }
+        }
+        // Four numbers, separated by commas or spaces.
+        fn parse4<T: FromStr + Copy + Default>(value: &str) -> Result<[T; 4], String> {
+            let values = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(parse)
+                .collect::<Result<Vec<T>, String>>()?;
+            values
+                .try_into()
+                .map_err(|_| format!("\"{}\": expected four values", value))
+        }
+        match name.as_str() {
+            "seed" => self.seed = parse(value)?,
+            "nthreads" => self.n_threads = parse(value)?,
+            "lenient" => self.lenient = parse_bool(value)?,
+            "quick" => self.quick_render = parse_bool(value)?,
+            "spp" => self.pixel_samples = Some(parse(value)?),
+            "cropwindow" => {
+                let [x0, x1, y0, y1] = parse4::<Float>(value)?;
+                if !(0.0..=1.0).contains(&x0.min(x1).min(y0).min(y1))
+                    || x0.max(x1).max(y0).max(y1) > 1.0
+                    || x0 >= x1
+                    || y0 >= y1
+                {
+                    return Err(format!("\"{}\": invalid crop window", value));
+                }
+                self.crop_window = Some([x0, x1, y0, y1]);
+            }
+            "pixelbounds" => {
+                let [x0, x1, y0, y1] = parse4::<i32>(value)?;
+                if x0 < 0 || y0 < 0 || x0 >= x1 || y0 >= y1 {
+                    return Err(format!("\"{}\": invalid pixel bounds", value));
+                }
+                self.pixel_bounds = Some([x0, x1, y0, y1]);
+            }
+            "outfile" => self.image_file = Some(value.to_string()),
+            "disablepixeljitter" => self.disable_pixel_jitter = parse_bool(value)?,
+            "disablewavelengthjitter" => self.disable_wavelength_jitter = parse_bool(value)?,
+            "disabletexturefiltering" => self.disable_texture_filtering = parse_bool(value)?,
+            "forcediffuse" => self.force_diffuse = parse_bool(value)?,
+            "rendercoordsys" => {
+                self.rendering_space = RenderingCoordinateSystem::from_name(value)
+                    .ok_or_else(|| format!("{}: unknown rendering coordinate system", value))?
+            }
+            "displacementedgescale" => self.displacement_edge_scale = parse(value)?,
+            "msereferenceimage" => self.mse_reference_image = Some(value.to_string()),
+            "msereferenceout" => self.mse_reference_output = Some(value.to_string()),
+            "pixelstats" => self.record_pixel_statistics = parse_bool(value)?,
+            "stats" => self.print_statistics = parse_bool(value)?,
+            _ => return Err(format!("{}: unknown option", name)),
+        }
+        Ok(true)
+    }
+}
+
+// Set by init_pbrt(), and then by the Option directive as the scene is
+// read.
+static PBRT_OPTIONS: Lazy<RwLock<PbrtOptions>> = Lazy::new(|| RwLock::new(PbrtOptions::default()));
+
+pub fn init_pbrt(options: PbrtOptions) {
+    *PBRT_OPTIONS.write().unwrap() = options;
+}
+
+/// The options passed to `init_pbrt`, as changed by the scene since, or
+/// the defaults if it hasn't been called.
+pub fn options() -> RwLockReadGuard<'static, PbrtOptions> {
+    PBRT_OPTIONS.read().unwrap()
+}
+
+/// Sets an option for the `Option` directive; see `PbrtOptions::set`.
+pub fn set_option(name: &str, value: &str) -> Result<bool, String> {
+    PBRT_OPTIONS.write().unwrap().set(name, value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn options_from_text() {
+        let mut options = PbrtOptions::default();
+        options.set("seed", "7").unwrap();
+        options.set("Disable_Pixel_Jitter", "true").unwrap();
+        options.set("cropwindow", "0.25, 0.75, 0 0.5").unwrap();
+        options.set("render-coord-sys", "world").unwrap();
+        assert_eq!(options.seed, 7);
+        assert!(options.disable_pixel_jitter);
+        assert_eq!(options.crop_window, Some([0.25, 0.75, 0.0, 0.5]));
+        assert_eq!(options.rendering_space, RenderingCoordinateSystem::World);
+
+        assert!(options.set("seed", "seven").is_err());
+        assert!(options.set("forcediffuse", "yes").is_err());
+        assert!(options.set("cropwindow", "0 1 0").is_err());
+        assert!(options.set("pixelbounds", "10 0 0 10").is_err());
+        assert_eq!(
+            options.set("sped", "1").unwrap_err(),
+            "sped: unknown option"
+        );
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let mut options = PbrtOptions::default();
+        options.set_from_command_line("--spp", "16").unwrap();
+        assert_eq!(options.set("spp", "1024"), Ok(false));
+        assert_eq!(options.pixel_samples, Some(16));
+        assert_eq!(options.set("seed", "3"), Ok(true));
+        assert_eq!(options.seed, 3);
+    }
 }
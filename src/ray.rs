@@ -1,25 +1,32 @@
 use crate::util::vecmath::*;
 use crate::Float;
 
-// No time
 pub struct Ray {
-    o: Point3f,
-    d: Vector3f,
+    pub o: Point3f,
+    pub d: Vector3f,
+    // When the ray is traced, for animated transforms.
+    pub time: Float,
 }
 
 impl Ray {
-    fn new(o: Point3f, d: Vector3f) -> Self {
-        Ray { o, d }
+    pub fn new(o: Point3f, d: Vector3f, time: Float) -> Self {
+        Ray { o, d, time }
     }
-    fn at(&self, t: Float) -> Point3f {
+    pub fn at(&self, t: Float) -> Point3f {
         self.o + t * self.d
     }
 }
+
+#[cfg(test)]
 mod test {
     #[test]
     fn ray() {
         use super::*;
-        let r = Ray::new(Point3f::new(0.0, 0.0, 0.0), Vector3f::new(1.0, 2.0, 4.0));
+        let r = Ray::new(
+            Point3f::new(0.0, 0.0, 0.0),
+            Vector3f::new(1.0, 2.0, 4.0),
+            0.0,
+        );
         assert_eq!(r.at(1.7), Point3f::new(1.7, 3.4, 6.8));
     }
 }
@@ -0,0 +1,495 @@
+//! A summary of what a `BasicScene` holds, for checking scenes without
+//! rendering them: how many of each kind of entity there are, roughly how
+//! much memory their descriptions take, and things that are likely to be
+//! mistakes, such as materials no shape uses.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::mem::size_of;
+
+use crate::paramdict::{ParameterDictionary, ParsedParameter};
+use crate::scene::{
+    AnimatedShapeSceneEntity, BasicScene, InstanceTransform, LightSceneEntity, SceneEntity,
+    ShapeSceneEntity, TransformedSceneEntity, INTERNED_STRINGS,
+};
+use crate::util::error::{warning, FileLoc};
+use crate::util::file::{read_file_contents, resolve_filename};
+use crate::util::transform::{AnimatedTransform, Transform};
+use crate::Float;
+
+#[derive(Debug, Default)]
+pub struct SceneReport {
+    /// Shapes by type, including those in object instance definitions.
+    pub shapes: BTreeMap<String, usize>,
+    pub animated_shapes: usize,
+    /// Triangles given by "trianglemesh" shapes, and faces of the files
+    /// "plymesh" shapes read, each counted once however often they're
+    /// instanced.
+    pub triangles: usize,
+    pub ply_faces: usize,
+    pub bilinear_patches: usize,
+    /// Triangles and PLY faces in object instance definitions, counted once
+    /// for each use of the instance.
+    pub instanced_triangles: usize,
+    /// The distinct transforms that static shapes and instances are placed
+    /// by.
+    pub unique_transforms: usize,
+    pub instance_definitions: usize,
+    pub instance_uses: usize,
+    pub lights: BTreeMap<String, usize>,
+    pub area_lights: usize,
+    pub float_textures: usize,
+    pub spectrum_textures: usize,
+    pub materials: usize,
+    pub named_materials: usize,
+    pub media: usize,
+    /// Strings interned by every scene read so far, not just this one.
+    pub interned_strings: usize,
+    /// Estimates of the memory the scene description takes: parameter
+    /// values, the entities holding them and the shared transforms.
+    pub parameter_bytes: usize,
+    pub entity_bytes: usize,
+    pub transform_bytes: usize,
+    pub warnings: Vec<(FileLoc, String)>,
+}
+
+impl SceneReport {
+    /// Prints the warnings the same way warnings found while parsing are.
+    pub fn report_warnings(&self) {
+        for (loc, message) in &self.warnings {
+            warning(loc, message);
+        }
+    }
+}
+
+impl fmt::Display for SceneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, name: &str, value: &dyn fmt::Display| {
+            writeln!(f, "    {:<42}{:>12}", name, value.to_string())
+        };
+        writeln!(f, "Scene statistics:")?;
+        writeln!(f, "  Shapes")?;
+        for (name, count) in &self.shapes {
+            line(f, name, count)?;
+        }
+        line(f, "(animated)", &self.animated_shapes)?;
+        line(f, "Triangles", &self.triangles)?;
+        line(f, "PLY mesh faces", &self.ply_faces)?;
+        line(f, "Bilinear patches", &self.bilinear_patches)?;
+        line(
+            f,
+            "Instanced triangles and faces",
+            &self.instanced_triangles,
+        )?;
+        writeln!(f, "  Lights")?;
+        for (name, count) in &self.lights {
+            line(f, name, count)?;
+        }
+        line(f, "Area lights", &self.area_lights)?;
+        writeln!(f, "  Scene")?;
+        line(f, "Unique transforms", &self.unique_transforms)?;
+        line(f, "Object instances created", &self.instance_definitions)?;
+        line(f, "Object instances used", &self.instance_uses)?;
+        line(f, "Float textures", &self.float_textures)?;
+        line(f, "Spectrum textures", &self.spectrum_textures)?;
+        line(f, "Materials", &self.materials)?;
+        line(f, "Named materials", &self.named_materials)?;
+        line(f, "Media", &self.media)?;
+        line(f, "Interned strings", &self.interned_strings)?;
+        writeln!(f, "  Memory")?;
+        line(f, "Parameters", &Bytes(self.parameter_bytes))?;
+        line(f, "Entities", &Bytes(self.entity_bytes))?;
+        line(f, "Transforms", &Bytes(self.transform_bytes))?;
+        let total = self.parameter_bytes + self.entity_bytes + self.transform_bytes;
+        line(f, "Total", &Bytes(total))
+    }
+}
+
+struct Bytes(usize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["B", "kB", "MB", "GB"];
+        let mut value = self.0 as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} {}", self.0, UNITS[0])
+        } else {
+            write!(f, "{:.2} {}", value, UNITS[unit])
+        }
+    }
+}
+
+/// Summarizes `scene`, which should have been read to the end of its files.
+pub fn scene_report(scene: &BasicScene) -> SceneReport {
+    let mut report = SceneReport {
+        interned_strings: INTERNED_STRINGS.len(),
+        ..Default::default()
+    };
+    let mut used = References::default();
+    let mut transforms = HashSet::new();
+
+    // Shapes, in the world and in instance definitions.
+    let shapes = scene.shapes.lock().unwrap();
+    let animated_shapes = scene.animated_shapes.lock().unwrap();
+    let definitions = scene.instance_definitions.lock().unwrap();
+    let mut definition_triangles = HashMap::new();
+    for (definition, (shapes, animated_shapes)) in
+        std::iter::once((None, (&*shapes, &*animated_shapes))).chain(
+            definitions
+                .values()
+                .map(|d| (Some(&d.name), (&d.shapes, &d.animated_shapes))),
+        )
+    {
+        let mut triangles = 0;
+        let static_shapes = shapes.iter().map(|s| {
+            transforms.insert(&*s.render_from_object as *const Transform);
+            (&s.scene_entity, &s.material_name)
+        });
+        let animated = animated_shapes
+            .iter()
+            .map(|s| (&s.scene_entity, &s.material_name));
+        report.animated_shapes += animated_shapes.len();
+        for (entity, material_name) in static_shapes.chain(animated) {
+            *report.shapes.entry(entity_name(entity)).or_default() += 1;
+            let counts = primitive_counts(entity, &mut report.warnings);
+            report.triangles += counts.triangles;
+            report.ply_faces += counts.ply_faces;
+            report.bilinear_patches += counts.bilinear_patches;
+            triangles += counts.triangles + counts.ply_faces;
+            if let Some(name) = material_name {
+                used.materials.insert(name.to_string());
+            }
+            used.note(&entity.parameters);
+            report.parameter_bytes += parameter_bytes(&entity.parameters);
+        }
+        report.entity_bytes += shapes.len() * size_of::<ShapeSceneEntity>()
+            + animated_shapes.len() * size_of::<AnimatedShapeSceneEntity>();
+        if let Some(name) = definition {
+            definition_triangles.insert(name.clone(), triangles);
+        }
+    }
+    report.instance_definitions = definitions.len();
+    drop((shapes, animated_shapes, definitions));
+
+    let instances = scene.instances.lock().unwrap();
+    report.instance_uses = instances.len();
+    for instance in instances.iter() {
+        report.instanced_triangles += definition_triangles.get(&instance.name).unwrap_or(&0);
+        match &instance.render_from_instance {
+            InstanceTransform::Static(t) => {
+                transforms.insert(&**t as *const Transform);
+            }
+            InstanceTransform::Animated(_) => report.entity_bytes += size_of::<AnimatedTransform>(),
+        }
+    }
+    report.unique_transforms = transforms.len();
+    report.transform_bytes = transforms.len() * size_of::<Transform>();
+    drop(instances);
+
+    // Lights.
+    for light in scene.lights.lock().unwrap().iter() {
+        let entity = &light.scene_entity;
+        *report.lights.entry(entity_name(entity)).or_default() += 1;
+        if has_zero_power(&entity.parameters) {
+            report.warnings.push((
+                entity.loc.clone(),
+                format!("{}: light has zero power", entity_name(entity)),
+            ));
+        }
+        used.note(&entity.parameters);
+        report.parameter_bytes += parameter_bytes(&entity.parameters);
+        report.entity_bytes += size_of::<LightSceneEntity>();
+    }
+    for entity in scene.area_lights.lock().unwrap().iter() {
+        report.area_lights += 1;
+        if has_zero_power(&entity.parameters) {
+            report.warnings.push((
+                entity.loc.clone(),
+                format!("{}: area light has zero power", entity_name(entity)),
+            ));
+        }
+        used.note(&entity.parameters);
+        report.parameter_bytes += parameter_bytes(&entity.parameters);
+        report.entity_bytes += size_of::<SceneEntity>();
+    }
+
+    // Materials, textures and media.
+    for entity in scene.materials.lock().unwrap().iter() {
+        report.materials += 1;
+        used.note(&entity.parameters);
+        report.parameter_bytes += parameter_bytes(&entity.parameters);
+        report.entity_bytes += size_of::<SceneEntity>();
+    }
+    let named_materials = scene.named_materials.lock().unwrap();
+    report.named_materials = named_materials.len();
+    for (_, entity) in named_materials.iter() {
+        used.note(&entity.parameters);
+        report.parameter_bytes += parameter_bytes(&entity.parameters);
+        report.entity_bytes += size_of::<SceneEntity>();
+    }
+    let float_textures = scene.float_textures.lock().unwrap();
+    let spectrum_textures = scene.spectrum_textures.lock().unwrap();
+    report.float_textures = float_textures.len();
+    report.spectrum_textures = spectrum_textures.len();
+    let textures = float_textures.iter().chain(spectrum_textures.iter());
+    for (_, texture) in textures.clone() {
+        used.note(&texture.scene_entity.parameters);
+    }
+    let media = scene.media.lock().unwrap();
+    report.media = media.len();
+    for entity in textures.clone().map(|(_, t)| t).chain(media.iter()) {
+        report.parameter_bytes += parameter_bytes(&entity.scene_entity.parameters);
+        report.entity_bytes += size_of::<TransformedSceneEntity>();
+    }
+
+    for (name, entity) in named_materials.iter() {
+        if !used.materials.contains(name.as_str()) {
+            report.warnings.push((
+                entity.loc.clone(),
+                format!("{}: named material is never used", name),
+            ));
+        }
+    }
+    for (name, texture) in textures {
+        if !used.textures.contains(name.as_str()) {
+            report.warnings.push((
+                texture.scene_entity.loc.clone(),
+                format!("{}: texture is never used", name),
+            ));
+        }
+    }
+    report
+}
+
+// Names that some entity refers to, to find the ones nothing does.
+#[derive(Default)]
+struct References {
+    materials: HashSet<String>,
+    textures: HashSet<String>,
+}
+
+impl References {
+    fn note(&mut self, dict: &ParameterDictionary) {
+        for p in dict.params() {
+            if *p.type_name == "texture" {
+                self.textures.extend(p.strings.iter().cloned());
+            }
+            // The materials a "mix" material chooses between.
+            if *p.name == "materials" && *p.type_name == "string" {
+                self.materials.extend(p.strings.iter().cloned());
+            }
+        }
+    }
+}
+
+fn entity_name(entity: &SceneEntity) -> String {
+    entity.name.as_deref().cloned().unwrap_or_default()
+}
+
+fn param<'a>(dict: &'a ParameterDictionary, name: &str) -> Option<&'a ParsedParameter> {
+    dict.params().iter().find(|p| *p.name == name)
+}
+
+#[derive(Default)]
+struct PrimitiveCounts {
+    triangles: usize,
+    ply_faces: usize,
+    bilinear_patches: usize,
+}
+
+fn primitive_counts(
+    entity: &SceneEntity,
+    warnings: &mut Vec<(FileLoc, String)>,
+) -> PrimitiveCounts {
+    let dict = &entity.parameters;
+    // Meshes of a single primitive may leave out the indices.
+    let indexed = |per_primitive: usize| match (param(dict, "indices"), param(dict, "P")) {
+        (Some(indices), _) => indices.ints.len() / per_primitive,
+        (None, Some(p)) if p.floats.len() == 3 * per_primitive => 1,
+        _ => 0,
+    };
+    match entity.name.as_deref().map(|n| n.as_str()) {
+        Some("trianglemesh") => PrimitiveCounts {
+            triangles: indexed(3),
+            ..Default::default()
+        },
+        Some("bilinearmesh") => PrimitiveCounts {
+            bilinear_patches: indexed(4),
+            ..Default::default()
+        },
+        Some("plymesh") => {
+            let Some(p) = param(dict, "filename").filter(|p| !p.strings.is_empty()) else {
+                return PrimitiveCounts::default();
+            };
+            let filename = resolve_filename(&p.strings[0], &p.loc.filename);
+            match ply_face_count(&filename) {
+                Ok(faces) => PrimitiveCounts {
+                    ply_faces: faces,
+                    ..Default::default()
+                },
+                Err(e) => {
+                    warnings.push((p.loc.clone(), format!("{}: {}", filename, e)));
+                    PrimitiveCounts::default()
+                }
+            }
+        }
+        _ => PrimitiveCounts::default(),
+    }
+}
+
+// Reads the number of faces from the header of a PLY file.
+fn ply_face_count(filename: &str) -> Result<usize, String> {
+    let contents = read_file_contents(filename).map_err(|e| e.to_string())?;
+    let header_end = contents
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .ok_or("not a PLY file")?;
+    let header = String::from_utf8_lossy(&contents[..header_end]);
+    if !header.starts_with("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    for line in header.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let ["element", "face", count] = words[..] {
+            return count
+                .parse()
+                .map_err(|_| format!("\"{}\": bad face count", count));
+        }
+    }
+    Ok(0)
+}
+
+// Whether a light's parameters make it emit nothing: a zero scale, or
+// radiance or intensity given as all zeros.
+fn has_zero_power(dict: &ParameterDictionary) -> bool {
+    if param(dict, "scale").is_some_and(|p| p.floats.first() == Some(&0.0)) {
+        return true;
+    }
+    ["L", "I"]
+        .iter()
+        .filter_map(|name| param(dict, name))
+        .any(|p| {
+            let values: Vec<Float> = match p.type_name.as_str() {
+                "rgb" | "blackbody" => p.floats.clone(),
+                // Given inline as (wavelength, value) pairs.
+                "spectrum" if p.strings.is_empty() => {
+                    p.floats.iter().skip(1).step_by(2).copied().collect()
+                }
+                _ => return false,
+            };
+            !values.is_empty() && values.iter().all(|&v| v == 0.0)
+        })
+}
+
+fn parameter_bytes(dict: &ParameterDictionary) -> usize {
+    dict.params()
+        .iter()
+        .map(|p| {
+            size_of::<ParsedParameter>()
+                + p.floats.len() * size_of::<Float>()
+                + p.ints.len() * size_of::<i32>()
+                + p.strings
+                    .iter()
+                    .map(|s| size_of::<String>() + s.len())
+                    .sum::<usize>()
+                + p.bools.len()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::parser::{parse, ParserTarget, Tokenizer};
+    use crate::scene::BasicSceneBuilder;
+
+    fn report_for(text: &str) -> SceneReport {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse(
+            &mut builder,
+            Tokenizer::new("test", text.as_bytes().to_vec()),
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        scene_report(&scene)
+    }
+
+    #[test]
+    fn entities_are_counted() {
+        let report = report_for(
+            r#"
+            WorldBegin
+            Texture "checks" "spectrum" "checkerboard"
+            Material "diffuse" "texture reflectance" "checks"
+            Shape "sphere"
+            Translate 1 0 0
+            Shape "sphere"
+            Shape "trianglemesh" "point3 P" [0 0 0 1 0 0 0 1 0 1 1 0]
+                "integer indices" [0 1 2 2 1 3]
+            LightSource "point"
+            LightSource "distant"
+            ObjectBegin "tri"
+            Shape "trianglemesh" "point3 P" [0 0 0 1 0 0 0 1 0]
+            ObjectEnd
+            ObjectInstance "tri"
+            ObjectInstance "tri"
+            ObjectInstance "tri"
+            "#,
+        );
+        assert_eq!(report.shapes["sphere"], 2);
+        assert_eq!(report.shapes["trianglemesh"], 2);
+        assert_eq!(report.triangles, 3);
+        assert_eq!(report.instanced_triangles, 3);
+        assert_eq!(report.instance_definitions, 1);
+        assert_eq!(report.instance_uses, 3);
+        assert_eq!(report.lights["point"], 1);
+        assert_eq!(report.lights["distant"], 1);
+        assert_eq!(report.spectrum_textures, 1);
+        // The identity and the translation.
+        assert_eq!(report.unique_transforms, 2);
+        assert!(report.parameter_bytes > 0);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert!(report.to_string().contains("Unique transforms"));
+    }
+
+    #[test]
+    fn likely_mistakes_are_warned_about() {
+        let report = report_for(
+            r#"
+            WorldBegin
+            Texture "unused" "float" "constant"
+            MakeNamedMaterial "red" "string type" "diffuse"
+            MakeNamedMaterial "blue" "string type" "diffuse"
+            NamedMaterial "blue"
+            Shape "sphere"
+            LightSource "point" "rgb I" [0 0 0]
+            LightSource "distant" "float scale" 0
+            AttributeBegin
+            AreaLightSource "diffuse" "blackbody L" 0
+            Shape "sphere"
+            AttributeEnd
+            LightSource "spot" "rgb I" [1 1 1]
+            "#,
+        );
+        let messages: Vec<&str> = report.warnings.iter().map(|(_, m)| m.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "point: light has zero power",
+                "distant: light has zero power",
+                "diffuse: area light has zero power",
+                "red: named material is never used",
+                "unused: texture is never used",
+            ]
+        );
+        assert_eq!(report.warnings[0].0.line, 8);
+    }
+}
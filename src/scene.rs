@@ -1,30 +1,44 @@
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 
+use crate::camera::{CameraBaseParameters, CameraTransform};
 use crate::cpu::integrator;
-use crate::paramdict::{ParameterDictionary, ParsedParameterVector};
-use crate::parser::ParserTarget;
+use crate::light::Light;
+use crate::paramdict::{
+    ParameterDictionary, ParsedParameterVector, SpectrumType, TextureParameterDictionary,
+};
+use crate::parser::{parse, ParserTarget, Tokenizer};
+use crate::pbrt::{options, set_option};
+use crate::shape::Shape;
+use crate::texture::{
+    create_float_texture, create_spectrum_texture, FloatConstantTexture, FloatTexture,
+    NamedTextures, SpectrumTexture,
+};
 use crate::util::colorspace::{self, sRGB, RGBColorSpace};
 use crate::util::containers::InternCache;
-use crate::util::error::FileLoc;
+use crate::util::error::{sort_errors, warning, FileLoc, ParseError};
+use crate::util::parallel::{max_concurrency, parallel_map};
+use crate::util::stats;
 use crate::util::string::InternedString;
-use crate::util::transform;
+use crate::util::transform::{self, AnimatedTransform, Transform};
 use crate::util::vecmath::{Point3f, Tuple3, Vector3f};
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Index, IndexMut};
+use std::sync::{Arc, Mutex};
 
 pub static INTERNED_STRINGS: Lazy<InternCache<String>> = Lazy::new(|| InternCache::new());
 
 #[derive(Default)]
-struct SceneEntity {
-    name: Option<InternedString>,
-    loc: FileLoc,
-    parameters: ParameterDictionary,
+pub(crate) struct SceneEntity {
+    pub(crate) name: Option<InternedString>,
+    pub(crate) loc: FileLoc,
+    pub(crate) parameters: ParameterDictionary,
 }
 
 impl SceneEntity {
     #[inline]
-    pub fn new(name: &String, parameters: ParameterDictionary, loc: FileLoc) -> Self {
+    pub fn new(name: &str, parameters: ParameterDictionary, loc: FileLoc) -> Self {
         Self {
-            name: Some(INTERNED_STRINGS.lookup(name)),
+            name: Some(INTERNED_STRINGS.lookup(&name.to_string())),
             parameters,
             loc,
         }
@@ -32,32 +46,645 @@ impl SceneEntity {
 }
 
 #[derive(Default)]
-struct CameraSceneEntity {
-    scene_entity: SceneEntity,
-    // medium: &String,
+pub(crate) struct CameraSceneEntity {
+    pub(crate) scene_entity: SceneEntity,
+    pub(crate) camera_transform: CameraTransform,
+    pub(crate) medium: Option<InternedString>,
 }
 
 impl CameraSceneEntity {
     pub fn new(
-        name: &String,
+        name: &str,
         parameters: ParameterDictionary,
         loc: FileLoc,
-        // camera_transform: CameraTransform,
-        // medium: Option<InternedString>,
+        camera_transform: CameraTransform,
+        medium: Option<InternedString>,
     ) -> Self {
         Self {
             scene_entity: SceneEntity::new(name, parameters, loc),
-            // medium,
+            camera_transform,
+            medium,
         }
     }
 }
 
-pub struct BasicScene {}
+// An entity placed by the CTM in effect where it was declared.
+pub(crate) struct TransformedSceneEntity {
+    pub(crate) scene_entity: SceneEntity,
+    pub(crate) render_from_object: AnimatedTransform,
+}
+
+impl TransformedSceneEntity {
+    fn new(
+        name: &str,
+        parameters: ParameterDictionary,
+        loc: FileLoc,
+        render_from_object: AnimatedTransform,
+    ) -> Self {
+        Self {
+            scene_entity: SceneEntity::new(name, parameters, loc),
+            render_from_object,
+        }
+    }
+}
+
+pub(crate) type MediumSceneEntity = TransformedSceneEntity;
+pub(crate) type TextureSceneEntity = TransformedSceneEntity;
+
+pub(crate) struct LightSceneEntity {
+    pub(crate) scene_entity: SceneEntity,
+    pub(crate) render_from_object: AnimatedTransform,
+    // The medium the light is in.
+    pub(crate) medium: Option<InternedString>,
+}
+
+// A shape whose transform is fixed over the shutter interval. Transforms
+// are shared through the builder's cache, since large scenes repeat the
+// same few many times over.
+pub(crate) struct ShapeSceneEntity {
+    pub(crate) scene_entity: SceneEntity,
+    pub(crate) render_from_object: Arc<Transform>,
+    pub(crate) object_from_render: Arc<Transform>,
+    pub(crate) reverse_orientation: bool,
+    pub(crate) material_index: usize,
+    pub(crate) material_name: Option<InternedString>,
+    // Into the area lights, if the shape emits light.
+    pub(crate) light_index: Option<usize>,
+    pub(crate) inside_medium: Option<InternedString>,
+    pub(crate) outside_medium: Option<InternedString>,
+}
+
+// A shape that moves during the shutter interval.
+pub(crate) struct AnimatedShapeSceneEntity {
+    pub(crate) scene_entity: SceneEntity,
+    pub(crate) render_from_object: AnimatedTransform,
+    // Animated shapes are defined in render space and moved by
+    // render_from_object when rays are intersected with them.
+    pub(crate) identity: Arc<Transform>,
+    pub(crate) reverse_orientation: bool,
+    pub(crate) material_index: usize,
+    pub(crate) material_name: Option<InternedString>,
+    pub(crate) light_index: Option<usize>,
+    pub(crate) inside_medium: Option<InternedString>,
+    pub(crate) outside_medium: Option<InternedString>,
+}
+
+// The shapes between an ObjectBegin and its ObjectEnd, which are
+// collected into an acceleration structure of their own that every use of
+// the instance shares.
+pub(crate) struct InstanceDefinitionSceneEntity {
+    pub(crate) name: InternedString,
+    pub(crate) loc: FileLoc,
+    pub(crate) shapes: Vec<ShapeSceneEntity>,
+    pub(crate) animated_shapes: Vec<AnimatedShapeSceneEntity>,
+}
+
+impl InstanceDefinitionSceneEntity {
+    fn new(name: InternedString, loc: FileLoc) -> Self {
+        Self {
+            name,
+            loc,
+            shapes: Vec::new(),
+            animated_shapes: Vec::new(),
+        }
+    }
+}
+
+pub(crate) enum InstanceTransform {
+    Static(Arc<Transform>),
+    Animated(Box<AnimatedTransform>),
+}
+
+// A use of an instance definition by ObjectInstance.
+pub(crate) struct InstanceSceneEntity {
+    pub(crate) name: InternedString,
+    pub(crate) loc: FileLoc,
+    pub(crate) render_from_instance: InstanceTransform,
+}
+
+// The instance definition that shapes are currently being added to.
+struct ActiveInstanceDefinition {
+    entity: InstanceDefinitionSceneEntity,
+    // Set in the builder of a file imported inside ObjectBegin, whose
+    // shapes are added to the definition when the import is merged.
+    imported: bool,
+}
+
+// The entities from before WorldBegin.
+#[derive(Default)]
+pub(crate) struct SceneOptions {
+    pub(crate) filter: SceneEntity,
+    pub(crate) film: SceneEntity,
+    pub(crate) camera: CameraSceneEntity,
+    pub(crate) sampler: SceneEntity,
+    pub(crate) integrator: SceneEntity,
+    pub(crate) accelerator: SceneEntity,
+    // Set by TransformTimes.
+    pub(crate) transform_start_time: crate::Float,
+    pub(crate) transform_end_time: crate::Float,
+}
+
+/// Everything the scene description declares, as entities that the
+/// renderer creates its objects from. The builders of imported files add
+/// to it alongside the main one.
+#[derive(Default)]
+pub struct BasicScene {
+    pub(crate) options: OnceCell<SceneOptions>,
+    pub(crate) named_materials: Mutex<Vec<(InternedString, SceneEntity)>>,
+    // Indexed by the material index of shapes.
+    pub(crate) materials: Mutex<Vec<SceneEntity>>,
+    // Named by their entities.
+    pub(crate) media: Mutex<Vec<MediumSceneEntity>>,
+    pub(crate) float_textures: Mutex<Vec<(InternedString, TextureSceneEntity)>>,
+    pub(crate) spectrum_textures: Mutex<Vec<(InternedString, TextureSceneEntity)>>,
+    pub(crate) lights: Mutex<Vec<LightSceneEntity>>,
+    // Indexed by the light index of shapes.
+    pub(crate) area_lights: Mutex<Vec<SceneEntity>>,
+    pub(crate) shapes: Mutex<Vec<ShapeSceneEntity>>,
+    pub(crate) animated_shapes: Mutex<Vec<AnimatedShapeSceneEntity>>,
+    pub(crate) instance_definitions: Mutex<HashMap<InternedString, InstanceDefinitionSceneEntity>>,
+    pub(crate) instances: Mutex<Vec<InstanceSceneEntity>>,
+    // Errors that don't stop the scene from being read, which are
+    // reported together once it has been.
+    pub(crate) errors: Mutex<Vec<ParseError>>,
+}
 
 impl BasicScene {
     pub fn new() -> Self {
-        BasicScene {}
+        Self::default()
+    }
+
+    fn set_options(&self, options: SceneOptions, loc: &FileLoc) -> Result<(), ParseError> {
+        self.options
+            .set(options)
+            .map_err(|_| ParseError::new(loc, "WorldBegin: scene options were already set"))
+    }
+
+    fn add_named_material(&self, name: InternedString, material: SceneEntity) {
+        self.named_materials.lock().unwrap().push((name, material));
+    }
+
+    // Returns the index of the material.
+    fn add_material(&self, material: SceneEntity) -> usize {
+        let mut materials = self.materials.lock().unwrap();
+        materials.push(material);
+        materials.len() - 1
+    }
+
+    fn add_medium(&self, medium: MediumSceneEntity) {
+        self.media.lock().unwrap().push(medium);
+    }
+
+    fn add_float_texture(&self, name: InternedString, texture: TextureSceneEntity) {
+        self.float_textures.lock().unwrap().push((name, texture));
+    }
+
+    fn add_spectrum_texture(&self, name: InternedString, texture: TextureSceneEntity) {
+        self.spectrum_textures.lock().unwrap().push((name, texture));
+    }
+
+    fn add_light(&self, light: LightSceneEntity) {
+        self.lights.lock().unwrap().push(light);
+    }
+
+    // Returns the index of the light.
+    fn add_area_light(&self, light: SceneEntity) -> usize {
+        let mut area_lights = self.area_lights.lock().unwrap();
+        area_lights.push(light);
+        area_lights.len() - 1
+    }
+
+    fn add_shapes(&self, shapes: Vec<ShapeSceneEntity>) {
+        self.shapes.lock().unwrap().extend(shapes);
+    }
+
+    fn add_animated_shapes(&self, shapes: Vec<AnimatedShapeSceneEntity>) {
+        self.animated_shapes.lock().unwrap().extend(shapes);
+    }
+
+    fn add_instance_definition(&self, definition: InstanceDefinitionSceneEntity) {
+        self.instance_definitions
+            .lock()
+            .unwrap()
+            .insert(definition.name.clone(), definition);
+    }
+
+    fn add_instance_uses(&self, instances: Vec<InstanceSceneEntity>) {
+        self.instances.lock().unwrap().extend(instances);
+    }
+
+    /// Records an error to be reported once the whole scene has been read,
+    /// so that as many as possible are reported at once.
+    pub fn error_deferred(&self, error: ParseError) {
+        self.errors.lock().unwrap().push(error);
+    }
+
+    /// Returns the errors recorded so far, sorted by where they are in the
+    /// files, leaving none.
+    pub fn take_errors(&self) -> Vec<ParseError> {
+        let mut errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        sort_errors(&mut errors);
+        errors
+    }
+
+    /// Creates the textures declared with the Texture directive. Those that
+    /// don't refer to other textures are created in parallel; the rest
+    /// follow one at a time in the order they were declared, so that the
+    /// textures they refer to exist by then. Errors come back sorted by
+    /// where they are in the files, however many threads there are.
+    pub fn create_textures(&self) -> Result<NamedTextures, Vec<ParseError>> {
+        let float_textures = self.float_textures.lock().unwrap();
+        let spectrum_textures = self.spectrum_textures.lock().unwrap();
+        // Spectrum textures are created once for each spectrum type.
+        let spectrum_types = [
+            SpectrumType::Albedo,
+            SpectrumType::Unbounded,
+            SpectrumType::Illuminant,
+        ];
+        let jobs: Vec<TextureJob> = (float_textures.iter())
+            .map(|(name, texture)| (name, texture, None))
+            .chain(spectrum_textures.iter().flat_map(|(name, texture)| {
+                spectrum_types.map(|spectrum_type| (name, texture, Some(spectrum_type)))
+            }))
+            .collect();
+        let (serial, independent): (Vec<TextureJob>, Vec<TextureJob>) = jobs
+            .into_iter()
+            .partition(|(_, texture, _)| texture.scene_entity.parameters.has_texture_parameters());
+
+        let mut textures = NamedTextures::default();
+        let mut errors = Vec::new();
+        let mut add = |textures: &mut NamedTextures, job: &TextureJob, result| match result {
+            Ok(CreatedTexture::Float(texture)) => {
+                textures.float_textures.insert(job.0.to_string(), texture);
+            }
+            Ok(CreatedTexture::Spectrum(texture)) => {
+                let spectrum_type = job.2.unwrap();
+                let map = match spectrum_type {
+                    SpectrumType::Albedo => &mut textures.albedo_spectrum_textures,
+                    SpectrumType::Unbounded => &mut textures.unbounded_spectrum_textures,
+                    SpectrumType::Illuminant => &mut textures.illuminant_spectrum_textures,
+                };
+                map.insert(job.0.to_string(), texture);
+            }
+            Err(e) => errors.push(e),
+        };
+        let no_textures = NamedTextures::default();
+        let created = parallel_map(max_concurrency(), &independent, |job| {
+            create_texture(job, &no_textures)
+        });
+        for (job, result) in independent.iter().zip(created) {
+            add(&mut textures, job, result);
+        }
+        for job in &serial {
+            let result = create_texture(job, &textures);
+            add(&mut textures, job, result);
+        }
+        // A spectrum texture's error is the same for each spectrum type,
+        // and is reported once.
+        sort_errors(&mut errors);
+        if errors.is_empty() {
+            Ok(textures)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reads what every camera needs from the Camera directive, its
+    /// shutter interval among it.
+    pub fn create_camera_base(&self) -> Result<CameraBaseParameters, ParseError> {
+        let default = CameraSceneEntity::default();
+        let camera = self
+            .options
+            .get()
+            .map_or(&default, |options| &options.camera);
+        CameraBaseParameters::new(
+            camera.camera_transform.clone(),
+            &camera.scene_entity.parameters,
+            &camera.scene_entity.loc,
+        )
+    }
+
+    /// Creates the shapes of the Shape directives in parallel, those of
+    /// instance definitions included. Errors come back sorted by where they
+    /// are in the files.
+    pub fn create_shapes(&self, textures: &NamedTextures) -> Result<SceneShapes, Vec<ParseError>> {
+        let mut errors = Vec::new();
+        let world = create_shape_groups(
+            &self.shapes.lock().unwrap(),
+            &self.animated_shapes.lock().unwrap(),
+            textures,
+            &mut errors,
+        );
+        let instance_definitions = (self.instance_definitions.lock().unwrap().values())
+            .map(|definition| {
+                let shapes = create_shape_groups(
+                    &definition.shapes,
+                    &definition.animated_shapes,
+                    textures,
+                    &mut errors,
+                );
+                (definition.name.clone(), shapes)
+            })
+            .collect();
+        sort_errors(&mut errors);
+        if errors.is_empty() {
+            Ok(SceneShapes {
+                world,
+                instance_definitions,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Creates the lights of the LightSource directives and the area lights
+    /// of the shapes that emit, in parallel. Errors come back sorted by
+    /// where they are in the files.
+    pub fn create_lights(&self) -> Result<SceneLights, Vec<ParseError>> {
+        let lights = self.lights.lock().unwrap();
+        for light in lights.iter() {
+            if light.render_from_object.is_animated() {
+                warning(
+                    &light.scene_entity.loc,
+                    "Animated lights aren't supported. Using the start transform.",
+                );
+            }
+        }
+        let created = parallel_map(max_concurrency(), &lights, |light| {
+            let entity = &light.scene_entity;
+            let name = entity.name.as_deref().map_or("", |n| n.as_str());
+            let render_from_light = &light.render_from_object.start_transform;
+            Light::create(name, &entity.parameters, render_from_light, &entity.loc)
+        });
+        let mut errors = Vec::new();
+        let mut scene_lights = SceneLights::default();
+        for result in created {
+            match result {
+                Ok(light) => scene_lights.lights.push(Arc::new(light)),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        // Only shapes outside of instance definitions that don't move can
+        // emit light; an area light on a moving one is an error.
+        let area_lights = self.area_lights.lock().unwrap();
+        let shapes = self.shapes.lock().unwrap();
+        let emitting: Vec<(usize, &ShapeSceneEntity)> = (shapes.iter().enumerate())
+            .filter(|(_, shape)| shape.light_index.is_some())
+            .collect();
+        let created = parallel_map(max_concurrency(), &emitting, |(_, shape)| {
+            let area_light = &area_lights[shape.light_index.unwrap()];
+            let name = area_light.name.as_deref().map_or("", |n| n.as_str());
+            let entity = &shape.scene_entity;
+            let shape_name = entity.name.as_deref().map_or("", |n| n.as_str());
+            let render_from_light = &shape.render_from_object;
+            Shape::create(
+                shape_name,
+                render_from_light,
+                &shape.object_from_render,
+                shape.reverse_orientation,
+                &entity.parameters,
+                &entity.loc,
+            )?
+            .iter()
+            .map(|s| {
+                Light::create_area(
+                    name,
+                    &area_light.parameters,
+                    render_from_light,
+                    s,
+                    &area_light.loc,
+                )
+                .map(Arc::new)
+            })
+            .collect::<Result<Vec<_>, _>>()
+        });
+        for ((index, _), result) in emitting.iter().zip(created) {
+            match result {
+                Ok(lights) => {
+                    scene_lights.lights.extend(lights.iter().cloned());
+                    scene_lights
+                        .shape_index_to_area_lights
+                        .insert(*index, lights);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        for shape in self.animated_shapes.lock().unwrap().iter() {
+            if shape.light_index.is_some() {
+                errors.push(ParseError::new(
+                    &shape.scene_entity.loc,
+                    "Area lights not supported with animated shapes",
+                ));
+            }
+        }
+        sort_errors(&mut errors);
+        if errors.is_empty() {
+            Ok(scene_lights)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Records an error for each named material and medium that is used but
+    // never defined, which can only be known once the scene has been read.
+    fn check_references(&self) {
+        let named_materials: HashSet<InternedString> = (self.named_materials.lock().unwrap())
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let media: HashSet<InternedString> = (self.media.lock().unwrap())
+            .iter()
+            .filter_map(|m| m.scene_entity.name.clone())
+            .collect();
+        let medium_error = |medium: &Option<InternedString>, loc: &FileLoc| {
+            medium
+                .as_ref()
+                .filter(|medium| !media.contains(*medium))
+                .map(|medium| ParseError::new(loc, format!("{}: medium not defined", medium)))
+        };
+        let mut errors = Vec::new();
+        if let Some(options) = self.options.get() {
+            let camera = &options.camera;
+            errors.extend(medium_error(&camera.medium, &camera.scene_entity.loc));
+        }
+        for light in self.lights.lock().unwrap().iter() {
+            errors.extend(medium_error(&light.medium, &light.scene_entity.loc));
+        }
+        // The material name and media of every shape, with its location.
+        let definitions = self.instance_definitions.lock().unwrap();
+        let shapes = self.shapes.lock().unwrap();
+        let animated_shapes = self.animated_shapes.lock().unwrap();
+        let static_uses = (shapes.iter())
+            .chain(definitions.values().flat_map(|d| d.shapes.iter()))
+            .map(|s| {
+                (
+                    &s.material_name,
+                    &s.inside_medium,
+                    &s.outside_medium,
+                    &s.scene_entity.loc,
+                )
+            });
+        let animated_uses = (animated_shapes.iter())
+            .chain(definitions.values().flat_map(|d| d.animated_shapes.iter()))
+            .map(|s| {
+                (
+                    &s.material_name,
+                    &s.inside_medium,
+                    &s.outside_medium,
+                    &s.scene_entity.loc,
+                )
+            });
+        for (material_name, inside, outside, loc) in static_uses.chain(animated_uses) {
+            if let Some(name) = material_name
+                .as_ref()
+                .filter(|n| !named_materials.contains(*n))
+            {
+                errors.push(ParseError::new(
+                    loc,
+                    format!("{}: named material not defined.", name),
+                ));
+            }
+            errors.extend(medium_error(inside, loc));
+            errors.extend(medium_error(outside, loc));
+        }
+        self.errors.lock().unwrap().extend(errors);
+    }
+}
+
+// A texture to create: its name, its entity and, for spectrum textures,
+// how to interpret RGB values.
+type TextureJob<'a> = (
+    &'a InternedString,
+    &'a TextureSceneEntity,
+    Option<SpectrumType>,
+);
+
+enum CreatedTexture {
+    Float(Arc<dyn FloatTexture>),
+    Spectrum(Arc<dyn SpectrumTexture>),
+}
+
+fn create_texture(
+    (_, texture, spectrum_type): &TextureJob,
+    textures: &NamedTextures,
+) -> Result<CreatedTexture, ParseError> {
+    let entity = &texture.scene_entity;
+    let name = entity.name.as_deref().map_or("", |n| n.as_str());
+    // Textures don't move during the shutter interval.
+    let render_from_texture = &texture.render_from_object.start_transform;
+    let parameters = TextureParameterDictionary::new(&entity.parameters, textures);
+    match spectrum_type {
+        None => create_float_texture(name, render_from_texture, &parameters, &entity.loc)
+            .map(CreatedTexture::Float),
+        Some(spectrum_type) => create_spectrum_texture(
+            name,
+            render_from_texture,
+            &parameters,
+            *spectrum_type,
+            &entity.loc,
+        )
+        .map(CreatedTexture::Spectrum),
+    }
+}
+
+/// The shapes created from one Shape directive, with the texture that cuts
+/// away parts of them, if any.
+pub struct ShapeGroup {
+    pub shapes: Vec<Shape>,
+    pub alpha: Option<Arc<dyn FloatTexture>>,
+}
+
+/// The shapes of a list of Shape directives, in the order of the directives.
+#[derive(Default)]
+pub struct ShapeGroups {
+    pub shapes: Vec<ShapeGroup>,
+    pub animated_shapes: Vec<ShapeGroup>,
+}
+
+pub struct SceneShapes {
+    pub world: ShapeGroups,
+    pub instance_definitions: HashMap<InternedString, ShapeGroups>,
+}
+
+#[derive(Default)]
+pub struct SceneLights {
+    pub lights: Vec<Arc<Light>>,
+    // The area lights of each shape that emits, by the index of its
+    // ShapeSceneEntity.
+    pub shape_index_to_area_lights: HashMap<usize, Vec<Arc<Light>>>,
+}
+
+fn create_shape_groups(
+    shapes: &[ShapeSceneEntity],
+    animated_shapes: &[AnimatedShapeSceneEntity],
+    textures: &NamedTextures,
+    errors: &mut Vec<ParseError>,
+) -> ShapeGroups {
+    let mut groups = ShapeGroups::default();
+    let created = parallel_map(max_concurrency(), shapes, |shape| {
+        create_shape_group(
+            &shape.scene_entity,
+            &shape.render_from_object,
+            &shape.object_from_render,
+            shape.reverse_orientation,
+            textures,
+        )
+    });
+    for result in created {
+        match result {
+            Ok(group) => groups.shapes.push(group),
+            Err(e) => errors.push(e),
+        }
+    }
+    // Animated shapes are defined in render space and moved by their
+    // transform when rays are intersected with them.
+    let created = parallel_map(max_concurrency(), animated_shapes, |shape| {
+        create_shape_group(
+            &shape.scene_entity,
+            &shape.identity,
+            &shape.identity,
+            shape.reverse_orientation,
+            textures,
+        )
+    });
+    for result in created {
+        match result {
+            Ok(group) => groups.animated_shapes.push(group),
+            Err(e) => errors.push(e),
+        }
     }
+    groups
+}
+
+fn create_shape_group(
+    entity: &SceneEntity,
+    render_from_object: &Arc<Transform>,
+    object_from_render: &Arc<Transform>,
+    reverse_orientation: bool,
+    textures: &NamedTextures,
+) -> Result<ShapeGroup, ParseError> {
+    let name = entity.name.as_deref().map_or("", |n| n.as_str());
+    let parameters = &entity.parameters;
+    let shapes = Shape::create(
+        name,
+        render_from_object,
+        object_from_render,
+        reverse_orientation,
+        parameters,
+        &entity.loc,
+    )?;
+    // An alpha of one cuts nothing away.
+    let is_texture = (parameters.params().iter())
+        .any(|p| *p.name == "alpha" && p.type_name.as_str() == "texture");
+    let alpha: Option<Arc<dyn FloatTexture>> = if is_texture {
+        TextureParameterDictionary::new(parameters, textures).get_float_texture_or_null("alpha")?
+    } else {
+        let alpha = parameters.get_one_float("alpha", 1.0)?;
+        (alpha < 1.0).then(|| Arc::new(FloatConstantTexture::new(alpha)) as _)
+    };
+    parameters.report_unused()?;
+    Ok(ShapeGroup { shapes, alpha })
 }
 
 // trait Scene {
@@ -74,93 +701,401 @@ enum BlockState {
     WorldBlock,
 }
 
+// The blocks that AttributeBegin and ObjectBegin open, which must be closed
+// in the reverse order.
+#[derive(Clone, Copy, PartialEq)]
+enum PushedBlock {
+    Attribute,
+    Object,
+}
+
+impl PushedBlock {
+    fn directives(self) -> (&'static str, &'static str) {
+        match self {
+            PushedBlock::Attribute => ("AttributeBegin", "AttributeEnd"),
+            PushedBlock::Object => ("ObjectBegin", "ObjectEnd"),
+        }
+    }
+}
+
 pub struct BasicSceneBuilder {
-    scene: Rc<BasicScene>,
+    scene: Arc<BasicScene>,
     current_block: BlockState,
     graphics_state: GraphicsState,
-    // render_from_world: Transform,
-    // transform_cache: InternCache<Transform>,
+    pushed_graphics_states: Vec<GraphicsState>,
+    push_stack: Vec<(PushedBlock, FileLoc)>,
+    // Set by the Camera directive; every entity's transform is expressed
+    // relative to the rendering space rather than world space.
+    render_from_world: Transform,
+    named_coordinate_systems: HashMap<String, TransformSet>,
+    // The times the two transforms of the CTM apply at.
+    transform_start_time: crate::Float,
+    transform_end_time: crate::Float,
+    // Shared with the builders of imported files, so that their shapes'
+    // transforms are deduplicated along with everyone else's.
+    transform_cache: Arc<InternCache<Transform>>,
     sampler: SceneEntity,
     film: SceneEntity,
     integrator: SceneEntity,
     filter: SceneEntity,
     camera: CameraSceneEntity,
+    accelerator: SceneEntity,
+    named_material_names: HashSet<InternedString>,
+    medium_names: HashSet<InternedString>,
+    float_texture_names: HashSet<InternedString>,
+    spectrum_texture_names: HashSet<InternedString>,
+    instance_names: HashSet<InternedString>,
+    // Passed on to the scene once all the files have been read.
+    shapes: Vec<ShapeSceneEntity>,
+    animated_shapes: Vec<AnimatedShapeSceneEntity>,
+    instance_uses: Vec<InstanceSceneEntity>,
+    active_instance_definition: Option<ActiveInstanceDefinition>,
 }
 
 impl BasicSceneBuilder {
-    pub fn new(scene: Rc<BasicScene>) -> Self {
-        let mut camera = CameraSceneEntity::default();
-        camera.scene_entity.name = Some(INTERNED_STRINGS.lookup(&String::from("perspective")));
-        let mut sampler = SceneEntity::default();
-        sampler.name = Some(INTERNED_STRINGS.lookup(&String::from("zsobol")));
-        let mut filter = SceneEntity::default();
-        filter.name = Some(INTERNED_STRINGS.lookup(&String::from("gaussian")));
-        let mut integrator = SceneEntity::default();
-        integrator.name = Some(INTERNED_STRINGS.lookup(&String::from("volpath")));
-
-        let mut film = SceneEntity::default();
-        film.name = Some(INTERNED_STRINGS.lookup(&String::from("rgb")));
-        film.parameters = ParameterDictionary::new(Vec::new(), &sRGB);
+    pub fn new(scene: Arc<BasicScene>) -> Self {
+        let mut builder = Self::empty(scene);
+        // Shapes before any Material directive are diffuse.
+        let diffuse = SceneEntity::new(
+            "diffuse",
+            ParameterDictionary::new(Vec::new(), &sRGB),
+            FileLoc::default(),
+        );
+        builder.graphics_state.current_material_index = builder.scene.add_material(diffuse);
+        builder
+    }
+
+    // A builder with the default options that doesn't add anything to the
+    // scene yet.
+    fn empty(scene: Arc<BasicScene>) -> Self {
+        let name = |name: &str| Some(INTERNED_STRINGS.lookup(&name.to_string()));
+        let camera = CameraSceneEntity {
+            scene_entity: SceneEntity {
+                name: name("perspective"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sampler = SceneEntity {
+            name: name("zsobol"),
+            ..Default::default()
+        };
+        let filter = SceneEntity {
+            name: name("gaussian"),
+            ..Default::default()
+        };
+        let integrator = SceneEntity {
+            name: name("volpath"),
+            ..Default::default()
+        };
+        let film = SceneEntity {
+            name: name("rgb"),
+            parameters: ParameterDictionary::new(Vec::new(), &sRGB),
+            ..Default::default()
+        };
+        let accelerator = SceneEntity {
+            name: name("bvh"),
+            ..Default::default()
+        };
 
         Self {
             scene,
             current_block: BlockState::OptionsBlock,
             graphics_state: GraphicsState::new(),
+            pushed_graphics_states: Vec::new(),
+            push_stack: Vec::new(),
+            render_from_world: Transform::identity(),
+            named_coordinate_systems: HashMap::new(),
+            transform_start_time: 0.0,
+            transform_end_time: 1.0,
+            transform_cache: Arc::new(InternCache::new()),
             sampler,
             film,
             integrator,
             filter,
             camera,
+            accelerator,
+            named_material_names: HashSet::new(),
+            medium_names: HashSet::new(),
+            float_texture_names: HashSet::new(),
+            spectrum_texture_names: HashSet::new(),
+            instance_names: HashSet::new(),
+            shapes: Vec::new(),
+            animated_shapes: Vec::new(),
+            instance_uses: Vec::new(),
+            active_instance_definition: None,
         }
     }
 
-    #[inline]
-    fn verify_options(&self, func_name: &str) {
-        if self.current_block != BlockState::OptionsBlock {
-            panic!("{} only allowed in options block.", func_name);
+    // The builder that an imported file is parsed into: it starts out with
+    // the current graphics state and shares the scene, but its named
+    // entities are only merged back once the whole file has been read.
+    fn copy_for_import(&self) -> Self {
+        let mut import_builder = Self::empty(self.scene.clone());
+        import_builder.current_block = BlockState::WorldBlock;
+        import_builder.graphics_state = self.graphics_state.clone();
+        import_builder.render_from_world = self.render_from_world.clone();
+        import_builder.named_coordinate_systems = self.named_coordinate_systems.clone();
+        import_builder.transform_start_time = self.transform_start_time;
+        import_builder.transform_end_time = self.transform_end_time;
+        import_builder.transform_cache = self.transform_cache.clone();
+        if let Some(active) = &self.active_instance_definition {
+            let entity = &active.entity;
+            import_builder.active_instance_definition = Some(ActiveInstanceDefinition {
+                entity: InstanceDefinitionSceneEntity::new(entity.name.clone(), entity.loc.clone()),
+                imported: true,
+            });
         }
+        import_builder
     }
 
-    #[inline]
-    fn verify_world(&self, func_name: &str) {
-        if self.current_block != BlockState::WorldBlock {
-            panic!("{} only allowed in world block.", func_name);
+    fn merge_imported(
+        &mut self,
+        import_builder: BasicSceneBuilder,
+        loc: &FileLoc,
+    ) -> Result<(), ParseError> {
+        let merge_set =
+            |base: &mut HashSet<InternedString>, imported: HashSet<InternedString>, kind: &str| {
+                for item in imported {
+                    if base.contains(&item) {
+                        return Err(ParseError::new(
+                            loc,
+                            format!("{}: multiply defined {}.", item, kind),
+                        ));
+                    }
+                    base.insert(item);
+                }
+                Ok(())
+            };
+        merge_set(
+            &mut self.named_material_names,
+            import_builder.named_material_names,
+            "named material",
+        )?;
+        merge_set(
+            &mut self.float_texture_names,
+            import_builder.float_texture_names,
+            "texture",
+        )?;
+        merge_set(
+            &mut self.spectrum_texture_names,
+            import_builder.spectrum_texture_names,
+            "texture",
+        )?;
+        merge_set(
+            &mut self.medium_names,
+            import_builder.medium_names,
+            "medium",
+        )?;
+        merge_set(
+            &mut self.instance_names,
+            import_builder.instance_names,
+            "object instance",
+        )?;
+        self.instance_uses.extend(import_builder.instance_uses);
+
+        // Shapes imported inside ObjectBegin belong to the definition.
+        match (
+            &mut self.active_instance_definition,
+            import_builder.active_instance_definition,
+        ) {
+            (Some(active), Some(imported)) => {
+                active.entity.shapes.extend(imported.entity.shapes);
+                active
+                    .entity
+                    .animated_shapes
+                    .extend(imported.entity.animated_shapes);
+            }
+            _ => {
+                self.shapes.extend(import_builder.shapes);
+                self.animated_shapes.extend(import_builder.animated_shapes);
+            }
         }
+        Ok(())
+    }
+
+    // The transform from the current object space to the rendering space.
+    fn render_from_object(&self, index: usize) -> Transform {
+        &self.render_from_world * &self.graphics_state.ctm[index]
+    }
+
+    // The same, moving between the two transforms of the CTM.
+    fn animated_render_from_object(&self) -> AnimatedTransform {
+        AnimatedTransform::new(
+            &self.render_from_object(0),
+            self.transform_start_time,
+            &self.render_from_object(1),
+            self.transform_end_time,
+        )
+    }
+
+    // These record an error for a directive that isn't allowed in the
+    // current block and return false, in which case the directive is
+    // ignored.
+    fn verify_options(&self, func_name: &str, loc: &FileLoc) -> bool {
+        let ok = self.current_block == BlockState::OptionsBlock;
+        if !ok {
+            self.scene.error_deferred(ParseError::new(
+                loc,
+                format!(
+                    "Options cannot be set inside world block; \"{}\" not allowed.",
+                    func_name
+                ),
+            ));
+        }
+        ok
+    }
+
+    fn verify_world(&self, func_name: &str, loc: &FileLoc) -> bool {
+        let ok = self.current_block == BlockState::WorldBlock;
+        if !ok {
+            self.scene.error_deferred(ParseError::new(
+                loc,
+                format!(
+                    "Scene description must be inside world block; \"{}\" not allowed.",
+                    func_name
+                ),
+            ));
+        }
+        ok
     }
 }
 
 impl ParserTarget for BasicSceneBuilder {
-    fn reverse_orientation(&mut self, loc: FileLoc) {
-        self.verify_world("RerverseOrientation");
+    fn reverse_orientation(&mut self, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("ReverseOrientation", &loc) {
+            return Ok(());
+        }
         self.graphics_state.reverse_orientation = !self.graphics_state.reverse_orientation;
+        Ok(())
     }
 
-    fn color_space(&mut self, name: &String, loc: FileLoc) {
-        self.graphics_state.color_space = colorspace::get_named(name).unwrap()
+    fn color_space(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError> {
+        match colorspace::get_named(name) {
+            Some(color_space) => self.graphics_state.color_space = color_space,
+            None => self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!("{}: color space unknown", name),
+            )),
+        }
+        Ok(())
     }
 
-    fn identity(&mut self, loc: FileLoc) {}
+    fn identity(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.graphics_state
+            .for_active_transforms(|_| Transform::identity());
+        Ok(())
+    }
 
-    fn sampler(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
+    fn sampler(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
         let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
-        self.verify_options("Sampler");
+        if !self.verify_options("Sampler", &loc) {
+            return Ok(());
+        }
         self.sampler = SceneEntity::new(name, dict, loc);
+        Ok(())
     }
 
-    fn scale(&mut self, sx: crate::Float, sy: crate::Float, sz: crate::Float, loc: FileLoc) {
-        todo!()
+    fn scale(
+        &mut self,
+        sx: crate::Float,
+        sy: crate::Float,
+        sz: crate::Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let scale = transform::scale(sx, sy, sz);
+        self.graphics_state.for_active_transforms(|t| t * &scale);
+        Ok(())
     }
 
-    fn shape(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn shape(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("Shape", &loc) {
+            return Ok(());
+        }
+        let gs = &self.graphics_state;
+        let dict =
+            ParameterDictionary::new_with_attributes(params, &gs.shape_attributes, gs.color_space);
+
+        let light_index = gs.area_light_name.as_ref().map(|light_name| {
+            self.scene.add_area_light(SceneEntity::new(
+                light_name,
+                gs.area_light_params.clone(),
+                gs.area_light_loc.clone(),
+            ))
+        });
+        if light_index.is_some() && self.active_instance_definition.is_some() {
+            warning(&loc, "Area lights not supported with object instancing");
+        }
+
+        if gs.ctm.is_animated() {
+            let entity = AnimatedShapeSceneEntity {
+                scene_entity: SceneEntity::new(name, dict, loc),
+                render_from_object: self.animated_render_from_object(),
+                identity: self.transform_cache.lookup(&Transform::identity()),
+                reverse_orientation: gs.reverse_orientation,
+                material_index: gs.current_material_index,
+                material_name: gs.current_material_name.clone(),
+                light_index,
+                inside_medium: gs.current_inside_medium.clone(),
+                outside_medium: gs.current_outside_medium.clone(),
+            };
+            match &mut self.active_instance_definition {
+                Some(active) => active.entity.animated_shapes.push(entity),
+                None => self.animated_shapes.push(entity),
+            }
+        } else {
+            let render_from_object = self.transform_cache.lookup(&self.render_from_object(0));
+            let object_from_render = self.transform_cache.lookup(&render_from_object.inverse());
+            let entity = ShapeSceneEntity {
+                scene_entity: SceneEntity::new(name, dict, loc),
+                render_from_object,
+                object_from_render,
+                reverse_orientation: gs.reverse_orientation,
+                material_index: gs.current_material_index,
+                material_name: gs.current_material_name.clone(),
+                light_index,
+                inside_medium: gs.current_inside_medium.clone(),
+                outside_medium: gs.current_outside_medium.clone(),
+            };
+            match &mut self.active_instance_definition {
+                Some(active) => active.entity.shapes.push(entity),
+                None => self.shapes.push(entity),
+            }
+        }
+        Ok(())
     }
 
-    fn option(&mut self, name: &String, value: &String, loc: FileLoc) {
-        todo!()
+    fn option(&mut self, name: &str, value: &str, loc: FileLoc) -> Result<(), ParseError> {
+        // String values arrive still quoted.
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        set_option(name, value).map_err(|msg| ParseError::new(&loc, &msg))?;
+        Ok(())
     }
 
-    fn translate(&mut self, dx: crate::Float, dy: crate::Float, dz: crate::Float, loc: FileLoc) {
-        todo!()
+    fn translate(
+        &mut self,
+        dx: crate::Float,
+        dy: crate::Float,
+        dz: crate::Float,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let translate = transform::translate(Vector3f::new(dx, dy, dz));
+        self.graphics_state
+            .for_active_transforms(|t| t * &translate);
+        Ok(())
     }
 
     fn rotate(
@@ -169,9 +1104,11 @@ impl ParserTarget for BasicSceneBuilder {
         ax: crate::Float,
         ay: crate::Float,
         az: crate::Float,
-        loc: FileLoc,
-    ) {
-        todo!()
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let rotate = transform::rotate(angle, Vector3f::new(ax, ay, az));
+        self.graphics_state.for_active_transforms(|t| t * &rotate);
+        Ok(())
     }
 
     fn look_at(
@@ -186,167 +1123,1360 @@ impl ParserTarget for BasicSceneBuilder {
         uy: crate::Float,
         uz: crate::Float,
         loc: FileLoc,
-    ) {
+    ) -> Result<(), ParseError> {
         let look_at = transform::look_at(
             Point3f::new(ex, ey, ez),
             Point3f::new(lx, ly, lz),
             Vector3f::new(ux, uy, uz),
-        );
-        println!("{:?}", look_at);
+        )
+        .ok_or_else(|| {
+            ParseError::new(
+                &loc,
+                "up vector and viewing direction are pointing in the same direction",
+            )
+        })?;
+        self.graphics_state.for_active_transforms(|t| t * &look_at);
+        Ok(())
     }
 
-    fn concat_transform(&mut self, transform: [crate::Float; 16], loc: FileLoc) {
-        todo!()
+    fn concat_transform(
+        &mut self,
+        transform: [crate::Float; 16],
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        // Matrices in scene files are given column by column.
+        let transform = transform::transpose(&Transform::from_array(&transform));
+        self.graphics_state
+            .for_active_transforms(|t| t * &transform);
+        Ok(())
     }
 
-    fn transform(&mut self, transform: [crate::Float; 16], loc: FileLoc) {
-        todo!()
+    fn transform(
+        &mut self,
+        transform: [crate::Float; 16],
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let transform = transform::transpose(&Transform::from_array(&transform));
+        self.graphics_state
+            .for_active_transforms(|_| transform.clone());
+        Ok(())
     }
 
-    fn coordinate_system(&mut self, name: &String, loc: FileLoc) {
-        todo!()
+    fn coordinate_system(&mut self, name: &str, _loc: FileLoc) -> Result<(), ParseError> {
+        self.named_coordinate_systems
+            .insert(name.to_string(), self.graphics_state.ctm.clone());
+        Ok(())
     }
 
-    fn coord_sys_transform(&mut self, name: &String, loc: FileLoc) {
-        todo!()
+    fn coord_sys_transform(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError> {
+        match self.named_coordinate_systems.get(name) {
+            Some(ctm) => self.graphics_state.ctm = ctm.clone(),
+            None => warning(
+                &loc,
+                &format!("couldn't find named coordinate system \"{}\"", name),
+            ),
+        }
+        Ok(())
     }
 
-    fn active_transform_all(&mut self, loc: FileLoc) {
-        todo!()
+    fn active_transform_all(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.graphics_state.active_transform_bits = ALL_TRANSFORMS_BITS;
+        Ok(())
     }
 
-    fn active_transform_end_time(&mut self, loc: FileLoc) {
-        todo!()
+    fn active_transform_end_time(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.graphics_state.active_transform_bits = END_TRANSFORM_BITS;
+        Ok(())
     }
 
-    fn active_transform_start_time(&mut self, loc: FileLoc) {
-        todo!()
+    fn active_transform_start_time(&mut self, _loc: FileLoc) -> Result<(), ParseError> {
+        self.graphics_state.active_transform_bits = START_TRANSFORM_BITS;
+        Ok(())
     }
 
-    fn transform_times(&mut self, start: crate::Float, end: crate::Float, loc: FileLoc) {
-        todo!()
+    fn transform_times(
+        &mut self,
+        start: crate::Float,
+        end: crate::Float,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_options("TransformTimes", &loc) {
+            return Ok(());
+        }
+        self.transform_start_time = start;
+        self.transform_end_time = end;
+        Ok(())
     }
 
-    fn pixel_filter(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
+    fn pixel_filter(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
         let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
-        self.verify_options("PixelFilter");
+        if !self.verify_options("PixelFilter", &loc) {
+            return Ok(());
+        }
         self.filter = SceneEntity::new(name, dict, loc);
+        Ok(())
     }
 
-    fn film(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
+    fn film(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
         let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
-        self.verify_options("Film");
+        if !self.verify_options("Film", &loc) {
+            return Ok(());
+        }
         self.film = SceneEntity::new(name, dict, loc);
+        Ok(())
     }
 
-    fn accelerator(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn accelerator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
+        if !self.verify_options("Accelerator", &loc) {
+            return Ok(());
+        }
+        self.accelerator = SceneEntity::new(name, dict, loc);
+        Ok(())
     }
 
-    fn integrator(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
+    fn integrator(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
         let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
-        self.verify_options("Integrator");
+        if !self.verify_options("Integrator", &loc) {
+            return Ok(());
+        }
         self.integrator = SceneEntity::new(name, dict, loc);
+        Ok(())
     }
 
-    fn camera(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
+    fn camera(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
         let dict = ParameterDictionary::new(params, self.graphics_state.color_space);
-        self.verify_options("Camera");
+        if !self.verify_options("Camera", &loc) {
+            return Ok(());
+        }
 
+        // The CTM is camera-from-world at this point; the "camera" named
+        // coordinate system lets later directives get back to it.
+        let world_from_camera = self.graphics_state.ctm.inverse();
+        self.named_coordinate_systems
+            .insert(String::from("camera"), world_from_camera.clone());
+        let world_from_camera = AnimatedTransform::new(
+            &world_from_camera[0],
+            self.transform_start_time,
+            &world_from_camera[1],
+            self.transform_end_time,
+        );
+        let camera_transform = CameraTransform::new(&world_from_camera, options().rendering_space);
+        self.render_from_world = camera_transform.render_from_world();
         self.camera = CameraSceneEntity::new(
-            name, dict, loc,
-            // self.graphics_state.current_outside_medium.unwrap(),
+            name,
+            dict,
+            loc,
+            camera_transform,
+            self.graphics_state.current_outside_medium.clone(),
         );
+        Ok(())
     }
 
-    fn make_named_medium(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn make_named_medium(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let gs = &self.graphics_state;
+        let dict =
+            ParameterDictionary::new_with_attributes(params, &gs.medium_attributes, gs.color_space);
+        let name = INTERNED_STRINGS.lookup(&name.to_string());
+        if !self.medium_names.insert(name.clone()) {
+            self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!("Named medium \"{}\" redefined.", name),
+            ));
+            return Ok(());
+        }
+        // The medium's type is given by its "type" parameter.
+        let medium = MediumSceneEntity::new(&name, dict, loc, self.animated_render_from_object());
+        self.scene.add_medium(medium);
+        Ok(())
     }
 
-    fn medium_interface(&mut self, inside_name: &String, outside_name: &String, loc: FileLoc) {
-        todo!()
+    fn medium_interface(
+        &mut self,
+        inside_name: &str,
+        outside_name: &str,
+        _loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let medium =
+            |name: &str| (!name.is_empty()).then(|| INTERNED_STRINGS.lookup(&name.to_string()));
+        self.graphics_state.current_inside_medium = medium(inside_name);
+        self.graphics_state.current_outside_medium = medium(outside_name);
+        Ok(())
     }
 
-    fn world_begin(&mut self, loc: FileLoc) {
-        todo!()
+    fn world_begin(&mut self, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_options("WorldBegin", &loc) {
+            return Ok(());
+        }
+        self.current_block = BlockState::WorldBlock;
+        self.graphics_state.ctm = TransformSet::default();
+        self.graphics_state.active_transform_bits = ALL_TRANSFORMS_BITS;
+        self.named_coordinate_systems
+            .insert(String::from("world"), self.graphics_state.ctm.clone());
+
+        // Everything from the options block is known now.
+        let options = SceneOptions {
+            filter: std::mem::take(&mut self.filter),
+            film: std::mem::take(&mut self.film),
+            camera: std::mem::take(&mut self.camera),
+            sampler: std::mem::take(&mut self.sampler),
+            integrator: std::mem::take(&mut self.integrator),
+            accelerator: std::mem::take(&mut self.accelerator),
+            transform_start_time: self.transform_start_time,
+            transform_end_time: self.transform_end_time,
+        };
+        self.scene.set_options(options, &loc)
     }
 
-    fn attribute_begin(&mut self, loc: FileLoc) {
-        self.verify_world("AttributeBegin");
-        todo!()
-        // self.pushed_graphics_states.push_back(self.graphics_state);
+    fn attribute_begin(&mut self, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("AttributeBegin", &loc) {
+            return Ok(());
+        }
+        self.pushed_graphics_states
+            .push(self.graphics_state.clone());
+        self.push_stack.push((PushedBlock::Attribute, loc));
+        Ok(())
     }
 
-    fn attribute_end(&mut self, loc: FileLoc) {
-        todo!()
+    fn attribute_end(&mut self, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("AttributeEnd", &loc) {
+            return Ok(());
+        }
+        match self.push_stack.last() {
+            None => Err(ParseError::new(&loc, "unmatched AttributeEnd")),
+            Some((PushedBlock::Object, begin_loc)) => Err(ParseError::new(
+                &loc,
+                format!(
+                    "mismatched nesting: AttributeEnd inside ObjectBegin from {}",
+                    begin_loc
+                ),
+            )),
+            Some((PushedBlock::Attribute, _)) => {
+                self.graphics_state = self.pushed_graphics_states.pop().unwrap();
+                self.push_stack.pop();
+                Ok(())
+            }
+        }
     }
 
-    fn attribute(&mut self, target: &str, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn attribute(
+        &mut self,
+        target: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        let color_space = self.graphics_state.color_space;
+        let attributes = match target {
+            "shape" => &mut self.graphics_state.shape_attributes,
+            "light" => &mut self.graphics_state.light_attributes,
+            "material" => &mut self.graphics_state.material_attributes,
+            "medium" => &mut self.graphics_state.medium_attributes,
+            "texture" => &mut self.graphics_state.texture_attributes,
+            _ => {
+                return Err(ParseError::with_token(
+                    &loc,
+                    target.as_bytes(),
+                    "unknown attribute target; must be \"shape\", \"light\", \"material\", \"medium\", or \"texture\"",
+                ))
+            }
+        };
+        // Keep the color space in effect now, rather than the one where
+        // the parameters are used.
+        for mut p in params {
+            p.may_be_unused = true;
+            p.color_space = Some(color_space);
+            attributes.push(p);
+        }
+        Ok(())
     }
 
     fn texture(
         &mut self,
-        name: &String,
-        typename: &String,
-        texname: &String,
+        name: &str,
+        typename: &str,
+        texname: &str,
         params: ParsedParameterVector,
         loc: FileLoc,
-    ) {
-        todo!()
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("Texture", &loc) {
+            return Ok(());
+        }
+        let gs = &self.graphics_state;
+        let dict = ParameterDictionary::new_with_attributes(
+            params,
+            &gs.texture_attributes,
+            gs.color_space,
+        );
+        let names = match typename {
+            "float" => &mut self.float_texture_names,
+            "spectrum" => &mut self.spectrum_texture_names,
+            _ => {
+                self.scene.error_deferred(ParseError::with_token(
+                    &loc,
+                    typename.as_bytes(),
+                    "texture type unknown; must be \"float\" or \"spectrum\"",
+                ));
+                return Ok(());
+            }
+        };
+        let name = INTERNED_STRINGS.lookup(&name.to_string());
+        if !names.insert(name.clone()) {
+            self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!("Redefining texture \"{}\".", name),
+            ));
+            return Ok(());
+        }
+        let texture =
+            TextureSceneEntity::new(texname, dict, loc, self.animated_render_from_object());
+        if typename == "float" {
+            self.scene.add_float_texture(name, texture);
+        } else {
+            self.scene.add_spectrum_texture(name, texture);
+        }
+        Ok(())
     }
 
-    fn material(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("Material", &loc) {
+            return Ok(());
+        }
+        let gs = &mut self.graphics_state;
+        let dict = ParameterDictionary::new_with_attributes(
+            params,
+            &gs.material_attributes,
+            gs.color_space,
+        );
+        gs.current_material_index = self.scene.add_material(SceneEntity::new(name, dict, loc));
+        gs.current_material_name = None;
+        Ok(())
     }
 
-    fn make_named_material(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn make_named_material(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("MakeNamedMaterial", &loc) {
+            return Ok(());
+        }
+        let gs = &self.graphics_state;
+        let dict = ParameterDictionary::new_with_attributes(
+            params,
+            &gs.material_attributes,
+            gs.color_space,
+        );
+        let name = INTERNED_STRINGS.lookup(&name.to_string());
+        if !self.named_material_names.insert(name.clone()) {
+            self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!("{}: named material redefined.", name),
+            ));
+            return Ok(());
+        }
+        // The material's type is given by its "type" parameter.
+        self.scene
+            .add_named_material(name, SceneEntity::new("", dict, loc));
+        Ok(())
     }
 
-    fn named_material(&mut self, name: &String, loc: FileLoc) {
-        todo!()
+    fn named_material(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("NamedMaterial", &loc) {
+            return Ok(());
+        }
+        self.graphics_state.current_material_name =
+            Some(INTERNED_STRINGS.lookup(&name.to_string()));
+        Ok(())
     }
 
-    fn light_source(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn light_source(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("LightSource", &loc) {
+            return Ok(());
+        }
+        let gs = &self.graphics_state;
+        let dict =
+            ParameterDictionary::new_with_attributes(params, &gs.light_attributes, gs.color_space);
+        self.scene.add_light(LightSceneEntity {
+            scene_entity: SceneEntity::new(name, dict, loc),
+            render_from_object: self.animated_render_from_object(),
+            medium: gs.current_outside_medium.clone(),
+        });
+        Ok(())
     }
 
-    fn area_light_source(&mut self, name: &String, params: ParsedParameterVector, loc: FileLoc) {
-        todo!()
+    fn area_light_source(
+        &mut self,
+        name: &str,
+        params: ParsedParameterVector,
+        loc: FileLoc,
+    ) -> Result<(), ParseError> {
+        if !self.verify_world("AreaLightSource", &loc) {
+            return Ok(());
+        }
+        let gs = &mut self.graphics_state;
+        gs.area_light_name = Some(INTERNED_STRINGS.lookup(&name.to_string()));
+        gs.area_light_params =
+            ParameterDictionary::new_with_attributes(params, &gs.light_attributes, gs.color_space);
+        gs.area_light_loc = loc;
+        Ok(())
+    }
+
+    fn object_begin(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("ObjectBegin", &loc) {
+            return Ok(());
+        }
+        self.pushed_graphics_states
+            .push(self.graphics_state.clone());
+        self.push_stack.push((PushedBlock::Object, loc.clone()));
+        if let Some(active) = &self.active_instance_definition {
+            return Err(ParseError::new(
+                &loc,
+                format!(
+                    "ObjectBegin called inside of instance definition \"{}\"",
+                    active.entity.name
+                ),
+            ));
+        }
+        let name = INTERNED_STRINGS.lookup(&name.to_string());
+        // The definition is still read, so that its ObjectEnd matches.
+        if !self.instance_names.insert(name.clone()) {
+            self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!("{}: trying to redefine an object instance", name),
+            ));
+        }
+        self.active_instance_definition = Some(ActiveInstanceDefinition {
+            entity: InstanceDefinitionSceneEntity::new(name, loc),
+            imported: false,
+        });
+        Ok(())
+    }
+
+    fn object_end(&mut self, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("ObjectEnd", &loc) {
+            return Ok(());
+        }
+        match (&self.active_instance_definition, self.push_stack.last()) {
+            (None, _) => Err(ParseError::new(
+                &loc,
+                "ObjectEnd called outside of instance definition",
+            )),
+            (Some(active), _) if active.imported => Err(ParseError::new(
+                &loc,
+                "ObjectEnd called inside Import for instance definition",
+            )),
+            (Some(_), Some((PushedBlock::Attribute, begin_loc))) => Err(ParseError::new(
+                &loc,
+                format!(
+                    "mismatched nesting: ObjectEnd inside AttributeBegin from {}",
+                    begin_loc
+                ),
+            )),
+            (Some(_), _) => {
+                self.graphics_state = self.pushed_graphics_states.pop().unwrap();
+                self.push_stack.pop();
+                let active = self.active_instance_definition.take().unwrap();
+                self.scene.add_instance_definition(active.entity);
+                Ok(())
+            }
+        }
+    }
+
+    fn object_instance(&mut self, name: &str, loc: FileLoc) -> Result<(), ParseError> {
+        if !self.verify_world("ObjectInstance", &loc) {
+            return Ok(());
+        }
+        if let Some(active) = &self.active_instance_definition {
+            self.scene.error_deferred(ParseError::new(
+                &loc,
+                format!(
+                    "ObjectInstance can't be called inside instance definition \"{}\"",
+                    active.entity.name
+                ),
+            ));
+            return Ok(());
+        }
+        let name = INTERNED_STRINGS.lookup(&name.to_string());
+        // Instance definitions are already in render space.
+        let world_from_render = self.render_from_world.inverse();
+        if self.graphics_state.ctm.is_animated() {
+            let render_from_instance = AnimatedTransform::new(
+                &(&self.render_from_object(0) * &world_from_render),
+                self.transform_start_time,
+                &(&self.render_from_object(1) * &world_from_render),
+                self.transform_end_time,
+            );
+            // Tiny differences in the CTM can vanish in the product, in
+            // which case the instance is static after all.
+            if render_from_instance.is_animated() {
+                self.instance_uses.push(InstanceSceneEntity {
+                    name,
+                    loc,
+                    render_from_instance: InstanceTransform::Animated(Box::new(
+                        render_from_instance,
+                    )),
+                });
+                return Ok(());
+            }
+        }
+        let render_from_instance = self
+            .transform_cache
+            .lookup(&(&self.render_from_object(0) * &world_from_render));
+        self.instance_uses.push(InstanceSceneEntity {
+            name,
+            loc,
+            render_from_instance: InstanceTransform::Static(render_from_instance),
+        });
+        Ok(())
+    }
+
+    fn import(&mut self, tokenizer: Tokenizer, loc: FileLoc) -> Result<(), Vec<ParseError>> {
+        if !self.verify_world("Import", &loc) {
+            return Ok(());
+        }
+        let mut import_builder = self.copy_for_import();
+        parse(&mut import_builder, tokenizer)?;
+        self.merge_imported(import_builder, &loc)?;
+        Ok(())
+    }
+
+    fn end_of_files(&mut self) -> Result<(), Vec<ParseError>> {
+        if self.current_block != BlockState::WorldBlock {
+            self.scene.error_deferred(ParseError::new(
+                &FileLoc::default(),
+                "End of files before \"WorldBegin\".",
+            ));
+            return Err(self.scene.take_errors());
+        }
+        // Report the outermost block that was left open.
+        if let Some((block, loc)) = self.push_stack.first() {
+            let (begin, end) = block.directives();
+            self.scene.error_deferred(ParseError::new(
+                loc,
+                format!("missing {} for this {}", end, begin),
+            ));
+            return Err(self.scene.take_errors());
+        }
+        // Instances may be used before they're defined, so this can only be
+        // checked now.
+        let definitions = self.scene.instance_definitions.lock().unwrap();
+        for instance in &self.instance_uses {
+            if !definitions.contains_key(&instance.name) {
+                self.scene.error_deferred(ParseError::new(
+                    &instance.loc,
+                    format!("{}: object instance not defined", instance.name),
+                ));
+            }
+        }
+        stats::set_counter(
+            "Scene",
+            "Object instances created",
+            definitions.len() as i64,
+        );
+        drop(definitions);
+        stats::set_counter(
+            "Scene",
+            "Unique transforms",
+            self.transform_cache.len() as i64,
+        );
+        stats::set_counter(
+            "Scene",
+            "Object instances used",
+            self.instance_uses.len() as i64,
+        );
+
+        self.scene.add_shapes(std::mem::take(&mut self.shapes));
+        self.scene
+            .add_animated_shapes(std::mem::take(&mut self.animated_shapes));
+        self.scene
+            .add_instance_uses(std::mem::take(&mut self.instance_uses));
+        self.scene.check_references();
+
+        let errors = self.scene.take_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
+}
 
-    fn object_begin(&mut self, name: &String, loc: FileLoc) {
-        todo!()
+const MAX_TRANSFORMS: usize = 2;
+const START_TRANSFORM_BITS: u32 = 1 << 0;
+const END_TRANSFORM_BITS: u32 = 1 << 1;
+const ALL_TRANSFORMS_BITS: u32 = (1 << MAX_TRANSFORMS) - 1;
+
+// The current transformation at the start and end of the shutter interval.
+#[derive(Clone, Default, PartialEq)]
+struct TransformSet {
+    t: [Transform; MAX_TRANSFORMS],
+}
+
+impl TransformSet {
+    fn inverse(&self) -> Self {
+        Self {
+            t: [self.t[0].inverse(), self.t[1].inverse()],
+        }
     }
 
-    fn object_end(&mut self, loc: FileLoc) {
-        todo!()
+    fn is_animated(&self) -> bool {
+        self.t[0] != self.t[1]
     }
+}
 
-    fn object_instance(&mut self, name: &String, loc: FileLoc) {
-        todo!()
+impl Index<usize> for TransformSet {
+    type Output = Transform;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.t[index]
     }
+}
 
-    fn end_of_files(&mut self) {
-        todo!()
+impl IndexMut<usize> for TransformSet {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.t[index]
     }
 }
 
+// Everything AttributeBegin saves and AttributeEnd restores.
+#[derive(Clone)]
 struct GraphicsState {
-    reverse_orientation: bool,
-    color_space: &'static RGBColorSpace,
     current_inside_medium: Option<InternedString>,
     current_outside_medium: Option<InternedString>,
+    // The index of the material set by Material, unless NamedMaterial
+    // has set a name since.
+    current_material_index: usize,
+    current_material_name: Option<InternedString>,
+    area_light_name: Option<InternedString>,
+    area_light_params: ParameterDictionary,
+    area_light_loc: FileLoc,
+    shape_attributes: ParsedParameterVector,
+    light_attributes: ParsedParameterVector,
+    material_attributes: ParsedParameterVector,
+    medium_attributes: ParsedParameterVector,
+    texture_attributes: ParsedParameterVector,
+    reverse_orientation: bool,
+    color_space: &'static RGBColorSpace,
+    ctm: TransformSet,
+    active_transform_bits: u32,
 }
 
 impl GraphicsState {
     pub fn new() -> Self {
         Self {
-            reverse_orientation: false,
-            color_space: &colorspace::sRGB,
             current_inside_medium: None,
             current_outside_medium: None,
+            current_material_index: 0,
+            current_material_name: None,
+            area_light_name: None,
+            area_light_params: ParameterDictionary::default(),
+            area_light_loc: FileLoc::default(),
+            shape_attributes: Vec::new(),
+            light_attributes: Vec::new(),
+            material_attributes: Vec::new(),
+            medium_attributes: Vec::new(),
+            texture_attributes: Vec::new(),
+            reverse_orientation: false,
+            color_space: &colorspace::sRGB,
+            ctm: TransformSet::default(),
+            active_transform_bits: ALL_TRANSFORMS_BITS,
+        }
+    }
+
+    // Replaces each transform that's active with `f` of it.
+    fn for_active_transforms(&mut self, f: impl Fn(&Transform) -> Transform) {
+        for i in 0..MAX_TRANSFORMS {
+            if self.active_transform_bits & (1 << i) != 0 {
+                self.ctm[i] = f(&self.ctm[i]);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::texture::TextureEvalContext;
+    use crate::util::vecmath::{Point2f, Tuple2};
+    use crate::Float;
+
+    fn world_builder() -> BasicSceneBuilder {
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        builder.current_block = BlockState::WorldBlock;
+        builder
+    }
+
+    fn parse_world(builder: &mut BasicSceneBuilder, scene: &str) -> Result<(), Vec<ParseError>> {
+        parse(builder, Tokenizer::new("test", scene.as_bytes().to_vec()))
+    }
+
+    // Where the current transformation takes `p`.
+    fn ctm_point(
+        builder: &BasicSceneBuilder,
+        x: Float,
+        y: Float,
+        z: Float,
+    ) -> (Float, Float, Float) {
+        let p = builder.graphics_state.ctm[0].apply_point(Point3f::new(x, y, z));
+        let round = |v: Float| (v * 1e4).round() / 1e4;
+        (round(p.x()), round(p.y()), round(p.z()))
+    }
+
+    #[test]
+    fn transforms_post_multiply_the_ctm() {
+        let mut builder = world_builder();
+        // Later transforms apply to objects first, so the point is scaled
+        // and then translated.
+        parse_world(&mut builder, "Translate 1 0 0\nScale 2 2 2\n").unwrap();
+        assert_eq!(ctm_point(&builder, 1., 1., 1.), (3., 2., 2.));
+        assert_eq!(builder.graphics_state.ctm[0], builder.graphics_state.ctm[1]);
+
+        parse_world(&mut builder, "Identity\nRotate 90 0 0 1\n").unwrap();
+        assert_eq!(ctm_point(&builder, 1., 0., 0.), (0., 1., 0.));
+
+        // Transform replaces the CTM; both it and ConcatTransform take
+        // their matrices column by column.
+        parse_world(
+            &mut builder,
+            "Transform [1 0 0 0  0 1 0 0  0 0 1 0  5 6 7 1]\nConcatTransform [2 0 0 0  0 2 0 0  0 0 2 0  0 0 0 1]\n",
+        )
+        .unwrap();
+        assert_eq!(ctm_point(&builder, 1., 1., 1.), (7., 8., 9.));
+
+        parse_world(&mut builder, "Identity\nLookAt 0 0 -5  0 0 0  0 1 0\n").unwrap();
+        assert_eq!(ctm_point(&builder, 0., 0., 0.), (0., 0., 5.));
+        let errors = parse_world(&mut builder, "LookAt 0 0 0  0 1 0  0 1 0\n").unwrap_err();
+        assert!(errors[0].message.contains("same direction"));
+    }
+
+    #[test]
+    fn active_transforms_animate_the_ctm() {
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        parse_world(
+            &mut builder,
+            "TransformTimes 0.25 0.75\nActiveTransform EndTime\nTranslate 0 0 -2\nActiveTransform All\nCamera \"perspective\"\n",
+        )
+        .unwrap();
+        // The camera moves from the origin to (0, 0, 2), and the rendering
+        // space sits where it is halfway.
+        let camera = builder.camera.camera_transform.render_from_camera();
+        assert_eq!((camera.start_time, camera.end_time), (0.25, 0.75));
+        let origin = Point3f::new(0., 0., 0.);
+        assert_eq!(camera.apply_point(origin, 0.25), Point3f::new(0., 0., -1.));
+        assert_eq!(camera.apply_point(origin, 0.75), Point3f::new(0., 0., 1.));
+
+        builder.current_block = BlockState::WorldBlock;
+        parse_world(
+            &mut builder,
+            "Identity\nActiveTransform StartTime\nTranslate 1 0 0\nActiveTransform All\nScale 2 2 2\n",
+        )
+        .unwrap();
+        assert!(builder.graphics_state.ctm.is_animated());
+        assert_eq!(ctm_point(&builder, 1., 0., 0.), (3., 0., 0.));
+        let at = builder.animated_render_from_object();
+        assert_eq!(at.apply_point(origin, 0.5), Point3f::new(0.5, 0., -1.));
+    }
+
+    #[test]
+    fn shapes_share_transforms() {
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            "Translate 1 0 0\nShape \"sphere\"\nAttributeBegin\nAreaLightSource \"diffuse\"\nShape \"sphere\"\nAttributeEnd\nTranslate 0 0 0\nShape \"disk\"\nIdentity\nShape \"sphere\"\n",
+        )
+        .unwrap();
+        let shapes = &builder.shapes;
+        assert_eq!(shapes.len(), 4);
+        assert!(Arc::ptr_eq(
+            &shapes[0].render_from_object,
+            &shapes[2].render_from_object
+        ));
+        assert!(Arc::ptr_eq(
+            &shapes[0].object_from_render,
+            &shapes[1].object_from_render
+        ));
+        assert!(!Arc::ptr_eq(
+            &shapes[0].render_from_object,
+            &shapes[3].render_from_object
+        ));
+        assert_eq!(
+            shapes.iter().map(|s| s.light_index).collect::<Vec<_>>(),
+            [None, Some(0), None, None]
+        );
+        assert_eq!(builder.scene.area_lights.lock().unwrap().len(), 1);
+
+        parse_world(
+            &mut builder,
+            "ActiveTransform EndTime\nTranslate 0 1 0\nActiveTransform All\nShape \"sphere\"\n",
+        )
+        .unwrap();
+        assert_eq!(builder.animated_shapes.len(), 1);
+
+        // The translation and its inverse, and the identity.
+        assert_eq!(builder.transform_cache.len(), 3);
+    }
+
+    #[test]
+    fn object_instances() {
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            "Translate 0 5 0\nObjectBegin \"tree\"\nTranslate 1 0 0\nShape \"sphere\"\nShape \"disk\"\nObjectEnd\nShape \"sphere\"\nTranslate 0 0 2\nObjectInstance \"tree\"\nActiveTransform EndTime\nTranslate 0 0 1\nActiveTransform All\nObjectInstance \"tree\"\n",
+        )
+        .unwrap();
+        // ObjectEnd restores the transform from before ObjectBegin.
+        assert_eq!(builder.shapes.len(), 1);
+        assert_eq!(
+            builder.shapes[0]
+                .render_from_object
+                .apply_point(Point3f::new(0., 0., 0.)),
+            Point3f::new(0., 5., 0.)
+        );
+        let definitions = builder.scene.instance_definitions.lock().unwrap();
+        let tree = &definitions[&INTERNED_STRINGS.lookup(&String::from("tree"))];
+        assert_eq!(tree.shapes.len(), 2);
+        drop(definitions);
+        assert_eq!(builder.instance_uses.len(), 2);
+        match &builder.instance_uses[0].render_from_instance {
+            InstanceTransform::Static(t) => assert_eq!(
+                t.apply_point(Point3f::new(0., 0., 0.)),
+                Point3f::new(0., 5., 2.)
+            ),
+            InstanceTransform::Animated(_) => panic!("instance shouldn't be animated"),
+        }
+        assert!(matches!(
+            builder.instance_uses[1].render_from_instance,
+            InstanceTransform::Animated(_)
+        ));
+
+        // Shapes imported inside a definition belong to it.
+        parse_world(&mut builder, "Identity\nObjectBegin \"bush\"\n").unwrap();
+        builder
+            .import(
+                Tokenizer::new("bush.pbrt", b"Shape \"sphere\"\n".to_vec()),
+                FileLoc::default(),
+            )
+            .unwrap();
+        parse_world(&mut builder, "ObjectEnd\n").unwrap();
+        let definitions = builder.scene.instance_definitions.lock().unwrap();
+        let bush = &definitions[&INTERNED_STRINGS.lookup(&String::from("bush"))];
+        assert_eq!(bush.shapes.len(), 1);
+        drop(definitions);
+        assert_eq!(builder.shapes.len(), 1);
+
+        builder.end_of_files().unwrap();
+        assert_eq!(builder.scene.shapes.lock().unwrap().len(), 1);
+        assert_eq!(builder.scene.instances.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn object_instance_errors() {
+        let error = |scene: &str| {
+            let mut builder = world_builder();
+            match parse_world(&mut builder, scene) {
+                Ok(()) => builder.end_of_files().unwrap_err().remove(0),
+                Err(errors) => errors.into_iter().next().unwrap(),
+            }
+        };
+        let e = error("ObjectBegin \"a\"\nObjectInstance \"a\"\nObjectEnd\n");
+        assert_eq!(
+            (e.loc.line, e.message.as_str()),
+            (
+                2,
+                "ObjectInstance can't be called inside instance definition \"a\""
+            )
+        );
+        let e = error("ObjectBegin \"a\"\nObjectEnd\nObjectInstance \"b\"\n");
+        assert_eq!(
+            (e.loc.line, e.message.as_str()),
+            (3, "b: object instance not defined")
+        );
+        let e = error("ObjectBegin \"a\"\nObjectEnd\nObjectBegin \"a\"\n");
+        assert_eq!(e.message, "a: trying to redefine an object instance");
+        let e = error("ObjectBegin \"a\"\nObjectBegin \"b\"\n");
+        assert_eq!(
+            e.message,
+            "ObjectBegin called inside of instance definition \"a\""
+        );
+        let e = error("ObjectEnd\n");
+        assert_eq!(e.message, "ObjectEnd called outside of instance definition");
+        let e = error("ObjectBegin \"a\"\nAttributeBegin\nObjectEnd\n");
+        assert_eq!(
+            e.message,
+            "mismatched nesting: ObjectEnd inside AttributeBegin from test:2:1"
+        );
+        let e = error("ObjectBegin \"a\"\nShape \"sphere\"\n");
+        assert_eq!(
+            (e.loc.line, e.message.as_str()),
+            (1, "missing ObjectEnd for this ObjectBegin")
+        );
+    }
+
+    #[test]
+    fn scene_holds_every_entity() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            r#"LookAt 0 0 -5  0 0 0  0 1 0
+Camera "orthographic"
+Film "gbuffer"
+Sampler "halton"
+Integrator "path"
+PixelFilter "box"
+Accelerator "kdtree"
+MakeNamedMedium "fog" "string type" "homogeneous"
+Translate 1 0 0
+WorldBegin
+CoordinateSystem "here"
+LightSource "point"
+MediumInterface "" "fog"
+LightSource "spot"
+Texture "checks" "spectrum" "checkerboard"
+Texture "bumps" "float" "wrinkled"
+MakeNamedMaterial "metal" "string type" "conductor"
+Shape "sphere"
+Material "dielectric"
+Shape "sphere"
+NamedMaterial "metal"
+Shape "sphere"
+"#,
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+
+        let options = scene.options.get().unwrap();
+        let name = |e: &SceneEntity| e.name.as_ref().unwrap().to_string();
+        assert_eq!(name(&options.camera.scene_entity), "orthographic");
+        assert_eq!(
+            [
+                &options.film,
+                &options.sampler,
+                &options.integrator,
+                &options.filter,
+                &options.accelerator
+            ]
+            .map(name),
+            ["gbuffer", "halton", "path", "box", "kdtree"]
+        );
+        // WorldBegin resets the CTM, which is world space from then on.
+        assert!(
+            builder.named_coordinate_systems["here"] == builder.named_coordinate_systems["world"]
+        );
+        assert!(builder.named_coordinate_systems["world"][0].is_identity());
+
+        let media = scene.media.lock().unwrap();
+        assert_eq!(media.len(), 1);
+        assert_eq!(name(&media[0].scene_entity), "fog");
+        let lights = scene.lights.lock().unwrap();
+        assert_eq!(lights.len(), 2);
+        assert_eq!(lights[0].medium, None);
+        assert_eq!(lights[1].medium.as_deref().map(String::as_str), Some("fog"));
+        let textures = scene.spectrum_textures.lock().unwrap();
+        assert_eq!(textures[0].0.as_str(), "checks");
+        assert_eq!(name(&textures[0].1.scene_entity), "checkerboard");
+        assert_eq!(scene.float_textures.lock().unwrap().len(), 1);
+        assert_eq!(scene.named_materials.lock().unwrap()[0].0.as_str(), "metal");
+
+        // The default material is diffuse.
+        let materials = scene.materials.lock().unwrap();
+        assert_eq!(
+            materials.iter().map(name).collect::<Vec<_>>(),
+            ["diffuse", "dielectric"]
+        );
+        let shapes = scene.shapes.lock().unwrap();
+        let material = |s: &ShapeSceneEntity| {
+            (
+                s.material_index,
+                s.material_name.as_ref().map(|n| n.to_string()),
+            )
+        };
+        assert_eq!(
+            shapes.iter().map(material).collect::<Vec<_>>(),
+            [(0, None), (1, None), (1, Some(String::from("metal")))]
+        );
+    }
+
+    #[test]
+    fn scene_errors() {
+        let error = |scene: &str| {
+            let mut builder = world_builder();
+            match parse_world(&mut builder, scene) {
+                Ok(()) => builder.end_of_files().unwrap_err().remove(0),
+                Err(errors) => errors.into_iter().next().unwrap(),
+            }
+        };
+        let e = error("Texture \"t\" \"float\" \"constant\"\nTexture \"t\" \"float\" \"scale\"\n");
+        assert_eq!(
+            (e.loc.line, e.message.as_str()),
+            (2, "Redefining texture \"t\".")
+        );
+        // Float and spectrum textures are named separately.
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            "Texture \"t\" \"float\" \"constant\"\nTexture \"t\" \"spectrum\" \"constant\"\n",
+        )
+        .unwrap();
+        let e = error("Texture \"t\" \"color\" \"constant\"\n");
+        assert!(e.message.contains("texture type unknown"));
+        let e = error("MakeNamedMaterial \"m\"\nMakeNamedMaterial \"m\"\n");
+        assert_eq!(e.message, "m: named material redefined.");
+        let e = error("MakeNamedMedium \"m\"\nMakeNamedMedium \"m\"\n");
+        assert_eq!(e.message, "Named medium \"m\" redefined.");
+        let e = error("Option \"samples\" 16\n");
+        assert_eq!(e.message, "samples: unknown option");
+        let e = error("Option \"forcediffuse\" \"yes\"\n");
+        assert!(e.message.contains("expected \"true\" or \"false\""));
+
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        parse_world(&mut builder, "Camera \"perspective\"\n").unwrap();
+        let e = builder.end_of_files().unwrap_err().remove(0);
+        assert_eq!(e.message, "End of files before \"WorldBegin\".");
+    }
+
+    #[test]
+    fn options_are_set_once() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(&mut builder, "WorldBegin\n").unwrap();
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        let e = parse_world(&mut builder, "WorldBegin\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(e.message, "WorldBegin: scene options were already set");
+        assert!(scene.options.get().is_some());
+    }
+
+    #[test]
+    fn scene_is_shared_between_threads() {
+        fn send_and_sync<T: Send + Sync>() {}
+        send_and_sync::<BasicScene>();
+    }
+
+    #[test]
+    fn textures_are_created() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\n\
+             Texture \"half\" \"float\" \"constant\" \"float value\" 0.5\n\
+             Texture \"one\" \"float\" \"constant\"\n\
+             Texture \"grey\" \"spectrum\" \"constant\" \"rgb value\" [0.5 0.5 0.5]\n",
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        let textures = scene.create_textures().unwrap();
+        let ctx = TextureEvalContext {
+            p: Point3f::new(0.0, 0.0, 0.0),
+            uv: Point2f::new(0.0, 0.0),
+        };
+        assert_eq!(textures.float_textures["half"].evaluate(&ctx), 0.5);
+        assert_eq!(textures.float_textures["one"].evaluate(&ctx), 1.0);
+        for spectrum_type in [
+            SpectrumType::Albedo,
+            SpectrumType::Unbounded,
+            SpectrumType::Illuminant,
+        ] {
+            assert!(textures
+                .spectrum_textures(spectrum_type)
+                .contains_key("grey"));
+        }
+
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\n\
+             Texture \"a\" \"float\" \"marble\"\n\
+             Texture \"b\" \"spectrum\" \"noise\"\n\
+             Texture \"c\" \"float\" \"constant\" \"float vaule\" 2\n",
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        let errors: Vec<_> = (scene.create_textures().err().unwrap())
+            .into_iter()
+            .map(|e| (e.loc.line, e.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (2, "marble: float texture type unknown.".to_string()),
+                (3, "noise: spectrum texture type unknown.".to_string()),
+                (4, "unused parameter".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shapes_and_lights_are_created() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\n\
+             LightSource \"point\"\n\
+             AttributeBegin\n\
+             AreaLightSource \"diffuse\"\n\
+             Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0 1 1 0]\n\
+             \"integer indices\" [0 1 2 2 1 3]\n\
+             AttributeEnd\n\
+             Shape \"sphere\" \"float alpha\" 0.5\n\
+             ObjectBegin \"tree\"\nShape \"disk\"\nObjectEnd\n",
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        let textures = scene.create_textures().unwrap();
+        let shapes = scene.create_shapes(&textures).unwrap();
+        assert_eq!(shapes.world.shapes.len(), 2);
+        assert_eq!(shapes.world.shapes[0].shapes.len(), 2);
+        assert!(shapes.world.shapes[0].alpha.is_none());
+        assert!(shapes.world.shapes[1].alpha.is_some());
+        assert_eq!(
+            shapes.instance_definitions[&Arc::new("tree".to_string())]
+                .shapes
+                .len(),
+            1
+        );
+        // The sphere keeps the transforms the builder cached for it.
+        let Shape::Sphere(sphere) = &shapes.world.shapes[1].shapes[0] else {
+            panic!("not a sphere")
+        };
+        let entities = scene.shapes.lock().unwrap();
+        assert!(Arc::ptr_eq(
+            &sphere.quadric.render_from_object,
+            &entities[1].render_from_object
+        ));
+        assert!(Arc::ptr_eq(
+            &sphere.quadric.object_from_render,
+            &entities[1].object_from_render
+        ));
+        drop(entities);
+        let lights = scene.create_lights().unwrap();
+        assert_eq!(lights.lights.len(), 3);
+        assert_eq!(lights.shape_index_to_area_lights[&0].len(), 2);
+
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\n\
+             Shape \"cone\"\n\
+             LightSource \"laser\"\n\
+             Shape \"sphere\" \"float radius\" 1 \"float raduis\" 2\n\
+             LightSource \"point\" \"float power\" \"one\"\n",
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        let textures = scene.create_textures().unwrap();
+        let lines = |errors: Vec<ParseError>| -> Vec<i32> {
+            errors.into_iter().map(|e| e.loc.line).collect()
+        };
+        assert_eq!(
+            lines(scene.create_shapes(&textures).err().unwrap()),
+            vec![2, 4]
+        );
+        assert_eq!(lines(scene.create_lights().err().unwrap()), vec![3, 5]);
+
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\n\
+             AreaLightSource \"diffuse\"\n\
+             ActiveTransform EndTime\nTranslate 1 0 0\nActiveTransform All\n\
+             Shape \"sphere\"\n",
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        let errors = scene.create_lights().err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].loc.line, 6);
+        assert_eq!(
+            errors[0].message,
+            "Area lights not supported with animated shapes"
+        );
+    }
+
+    #[test]
+    fn errors_are_reported_together() {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse_world(
+            &mut builder,
+            "WorldBegin\nSampler \"halton\"\nColorSpace \"adobe\"\nNamedMaterial \"gold\"\n\
+             MediumInterface \"fog\" \"\"\nShape \"sphere\"\nObjectInstance \"tree\"\n",
+        )
+        .unwrap();
+        let errors = builder.end_of_files().unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.loc.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    2,
+                    "Options cannot be set inside world block; \"Sampler\" not allowed."
+                ),
+                (3, "adobe: color space unknown"),
+                (6, "gold: named material not defined."),
+                (6, "fog: medium not defined"),
+                (7, "tree: object instance not defined"),
+            ]
+        );
+        // Reading went on past the errors.
+        assert_eq!(scene.shapes.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn camera_coordinate_system() {
+        let mut builder = BasicSceneBuilder::new(Arc::new(BasicScene::new()));
+        parse_world(
+            &mut builder,
+            "LookAt 5 0 0  5 0 1  0 1 0\nCamera \"perspective\"\nCoordinateSystem \"eye\"\n",
+        )
+        .unwrap();
+        builder.current_block = BlockState::WorldBlock;
+        // The default rendering space is world space moved to the camera.
+        assert_eq!(
+            builder
+                .render_from_world
+                .apply_point(Point3f::new(5., 0., 0.)),
+            Point3f::new(0., 0., 0.)
+        );
+
+        parse_world(&mut builder, "Identity\nCoordSysTransform \"camera\"\n").unwrap();
+        assert_eq!(ctm_point(&builder, 0., 0., 1.), (5., 0., 1.));
+        assert_eq!(
+            builder
+                .render_from_object(0)
+                .apply_point(Point3f::new(0., 0., 1.)),
+            Point3f::new(0., 0., 1.)
+        );
+
+        parse_world(&mut builder, "Identity\nCoordSysTransform \"eye\"\n").unwrap();
+        assert_eq!(ctm_point(&builder, 5., 0., 1.), (0., 0., 1.));
+        // Unknown names only warn, and leave the CTM alone.
+        parse_world(&mut builder, "CoordSysTransform \"nowhere\"\n").unwrap();
+        assert_eq!(ctm_point(&builder, 5., 0., 1.), (0., 0., 1.));
+    }
+
+    #[test]
+    fn attribute_end_restores_graphics_state() {
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            r#"
+NamedMaterial "outer"
+AttributeBegin
+    Translate 1 2 3
+    ReverseOrientation
+    NamedMaterial "inner"
+    MediumInterface "fog" ""
+    Attribute "shape" "float radius" 2
+    AreaLightSource "diffuse" "rgb L" [1 1 1]
+AttributeEnd
+"#,
+        )
+        .unwrap();
+        let gs = &builder.graphics_state;
+        assert!(!gs.reverse_orientation);
+        assert!(gs.ctm[0].is_identity());
+        assert_eq!(gs.current_material_name.as_deref().unwrap(), "outer");
+        assert!(gs.current_inside_medium.is_none());
+        assert!(gs.shape_attributes.is_empty());
+        assert!(gs.area_light_name.is_none());
+        assert!(builder.pushed_graphics_states.is_empty());
+        assert_eq!(builder.end_of_files(), Ok(()));
+    }
+
+    #[test]
+    fn attributes_apply_to_their_category() {
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            r#"
+Attribute "shape" "float radius" 2
+Attribute "light" "float scale" 4
+AreaLightSource "diffuse" "float scale" 3
+"#,
+        )
+        .unwrap();
+        let gs = &builder.graphics_state;
+        assert_eq!(gs.shape_attributes.len(), 1);
+        assert!(gs.shape_attributes[0].may_be_unused);
+        // The directive's own parameters come first, so they win.
+        assert_eq!(gs.area_light_params.get_one_float("scale", 1.), Ok(3.));
+        assert_eq!(gs.area_light_params.report_unused(), Ok(()));
+    }
+
+    #[test]
+    fn unknown_attribute_target() {
+        let mut builder = world_builder();
+        let errors = parse_world(&mut builder, r#"Attribute "camera" "float fov" 45"#).unwrap_err();
+        assert_eq!(errors[0].token.as_deref(), Some("camera"));
+    }
+
+    #[test]
+    fn mismatched_attribute_blocks() {
+        let mut builder = world_builder();
+        let errors =
+            parse_world(&mut builder, "AttributeBegin\nAttributeEnd\nAttributeEnd\n").unwrap_err();
+        assert_eq!(errors[0].loc.line, 3);
+        assert_eq!(errors[0].message, "unmatched AttributeEnd");
+
+        let mut builder = world_builder();
+        parse_world(
+            &mut builder,
+            "AttributeBegin\n  AttributeBegin\nAttributeEnd\n",
+        )
+        .unwrap();
+        let e = builder.end_of_files().unwrap_err().remove(0);
+        assert_eq!(e.loc.line, 1);
+        assert_eq!(e.message, "missing AttributeEnd for this AttributeBegin");
+    }
+}
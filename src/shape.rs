@@ -0,0 +1,703 @@
+use std::sync::Arc;
+
+use crate::paramdict::ParameterDictionary;
+use crate::util::error::{warning, FileLoc, ParseError};
+use crate::util::math::radians;
+use crate::util::mesh::TriQuadMesh;
+use crate::util::transform::Transform;
+use crate::util::vecmath::vector::{cross, dot, length, normalize};
+use crate::util::vecmath::{Bounds3f, Normal3f, Point2f, Point3f, Tuple3, Vector3f};
+use crate::Float;
+
+/// A shape as the Shape directive describes it. Meshes become one shape
+/// for each of their triangles or patches, which share the mesh.
+pub enum Shape {
+    Sphere(Sphere),
+    Cylinder(Cylinder),
+    Disk(Disk),
+    Triangle(Triangle),
+    BilinearPatch(BilinearPatch),
+}
+
+impl Shape {
+    /// Creates the shapes of the type called `name`. Parameters that are
+    /// left unused are for the caller to report, since some, like "alpha",
+    /// apply to every shape and are read elsewhere.
+    pub fn create(
+        name: &str,
+        render_from_object: &Arc<Transform>,
+        object_from_render: &Arc<Transform>,
+        reverse_orientation: bool,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Vec<Shape>, ParseError> {
+        let quadric = Quadric {
+            render_from_object: render_from_object.clone(),
+            object_from_render: object_from_render.clone(),
+            reverse_orientation,
+            transform_swaps_handedness: render_from_object.swaps_handedness(),
+        };
+        let shapes = match name {
+            "sphere" => vec![Shape::Sphere(Sphere::create(quadric, parameters)?)],
+            "cylinder" => vec![Shape::Cylinder(Cylinder::create(quadric, parameters)?)],
+            "disk" => vec![Shape::Disk(Disk::create(quadric, parameters)?)],
+            "trianglemesh" => {
+                let mesh =
+                    TriangleMesh::create(render_from_object, reverse_orientation, parameters, loc)?;
+                Triangle::create_triangles(mesh)
+            }
+            "bilinearmesh" => {
+                let mesh = BilinearPatchMesh::create(
+                    render_from_object,
+                    reverse_orientation,
+                    parameters,
+                    loc,
+                )?;
+                BilinearPatch::create_patches(mesh)
+            }
+            "plymesh" => create_ply_mesh(render_from_object, reverse_orientation, parameters, loc)?,
+            "curve" | "loopsubdiv" => {
+                return Err(ParseError::new(
+                    loc,
+                    format!("{}: shape type not supported yet.", name),
+                ))
+            }
+            _ => {
+                return Err(ParseError::new(
+                    loc,
+                    format!("{}: shape type unknown.", name),
+                ))
+            }
+        };
+        Ok(shapes)
+    }
+
+    /// The bounds of the shape in rendering space.
+    pub fn bounds(&self) -> Bounds3f {
+        match self {
+            Shape::Sphere(s) => s.quadric.render_from_object.apply_bounds(&Bounds3f::new(
+                Point3f::new(-s.radius, -s.radius, s.z_min),
+                Point3f::new(s.radius, s.radius, s.z_max),
+            )),
+            Shape::Cylinder(c) => c.quadric.render_from_object.apply_bounds(&Bounds3f::new(
+                Point3f::new(-c.radius, -c.radius, c.z_min),
+                Point3f::new(c.radius, c.radius, c.z_max),
+            )),
+            Shape::Disk(d) => d.quadric.render_from_object.apply_bounds(&Bounds3f::new(
+                Point3f::new(-d.radius, -d.radius, d.height),
+                Point3f::new(d.radius, d.radius, d.height),
+            )),
+            Shape::Triangle(t) => {
+                let [p0, p1, p2] = t.vertices();
+                Bounds3f::new(p0, p1).union_point(p2)
+            }
+            Shape::BilinearPatch(b) => {
+                let [p00, p10, p01, p11] = b.vertices();
+                Bounds3f::new(p00, p10).union(&Bounds3f::new(p01, p11))
+            }
+        }
+    }
+
+    /// The surface area of the shape in rendering space, ignoring any
+    /// scale in the transform of quadrics, as pbrt does.
+    pub fn area(&self) -> Float {
+        match self {
+            Shape::Sphere(s) => s.phi_max * s.radius * (s.z_max - s.z_min),
+            Shape::Cylinder(c) => (c.z_max - c.z_min) * c.radius * c.phi_max,
+            Shape::Disk(d) => {
+                d.phi_max * 0.5 * (d.radius * d.radius - d.inner_radius * d.inner_radius)
+            }
+            Shape::Triangle(t) => {
+                let [p0, p1, p2] = t.vertices();
+                0.5 * length(cross(p1 - p0, p2 - p0))
+            }
+            Shape::BilinearPatch(b) => b.area,
+        }
+    }
+}
+
+/// What every quadric holds: where it is and which way it faces. The
+/// transforms are those the scene shares between its shapes.
+pub struct Quadric {
+    pub render_from_object: Arc<Transform>,
+    pub object_from_render: Arc<Transform>,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+}
+
+pub struct Sphere {
+    pub quadric: Quadric,
+    pub radius: Float,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub theta_z_min: Float,
+    pub theta_z_max: Float,
+    pub phi_max: Float,
+}
+
+impl Sphere {
+    fn create(quadric: Quadric, parameters: &ParameterDictionary) -> Result<Self, ParseError> {
+        let radius = parameters.get_one_float("radius", 1.0)?;
+        let z_min = parameters.get_one_float("zmin", -radius)?;
+        let z_max = parameters.get_one_float("zmax", radius)?;
+        let phi_max = parameters.get_one_float("phimax", 360.0)?;
+        let (z_min, z_max) = (
+            z_min.min(z_max).clamp(-radius, radius),
+            z_min.max(z_max).clamp(-radius, radius),
+        );
+        Ok(Self {
+            quadric,
+            radius,
+            z_min,
+            z_max,
+            theta_z_min: (z_min / radius).clamp(-1.0, 1.0).acos(),
+            theta_z_max: (z_max / radius).clamp(-1.0, 1.0).acos(),
+            phi_max: radians(phi_max.clamp(0.0, 360.0)),
+        })
+    }
+}
+
+pub struct Cylinder {
+    pub quadric: Quadric,
+    pub radius: Float,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub phi_max: Float,
+}
+
+impl Cylinder {
+    fn create(quadric: Quadric, parameters: &ParameterDictionary) -> Result<Self, ParseError> {
+        let radius = parameters.get_one_float("radius", 1.0)?;
+        let z_min = parameters.get_one_float("zmin", -1.0)?;
+        let z_max = parameters.get_one_float("zmax", 1.0)?;
+        let phi_max = parameters.get_one_float("phimax", 360.0)?;
+        Ok(Self {
+            quadric,
+            radius,
+            z_min: z_min.min(z_max),
+            z_max: z_min.max(z_max),
+            phi_max: radians(phi_max.clamp(0.0, 360.0)),
+        })
+    }
+}
+
+pub struct Disk {
+    pub quadric: Quadric,
+    pub height: Float,
+    pub radius: Float,
+    pub inner_radius: Float,
+    pub phi_max: Float,
+}
+
+impl Disk {
+    fn create(quadric: Quadric, parameters: &ParameterDictionary) -> Result<Self, ParseError> {
+        let height = parameters.get_one_float("height", 0.0)?;
+        let radius = parameters.get_one_float("radius", 1.0)?;
+        let inner_radius = parameters.get_one_float("innerradius", 0.0)?;
+        let phi_max = parameters.get_one_float("phimax", 360.0)?;
+        Ok(Self {
+            quadric,
+            height,
+            radius,
+            inner_radius,
+            phi_max: radians(phi_max.clamp(0.0, 360.0)),
+        })
+    }
+}
+
+// The vertex data the mesh shapes read from their parameters, checked the
+// way pbrt-v4 does: optional buffers of the wrong size are dropped with a
+// warning, while missing or out-of-bounds indices reject the mesh.
+struct MeshParameters {
+    indices: Vec<i32>,
+    p: Vec<Point3f>,
+    n: Vec<Normal3f>,
+    uv: Vec<Point2f>,
+    face_indices: Vec<i32>,
+}
+
+impl MeshParameters {
+    fn read(
+        what: &str,
+        per_face: usize,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Self, ParseError> {
+        let mut indices = parameters.get_int_array("indices")?;
+        let p = parameters.get_point3f_array("P")?;
+        let mut n = parameters.get_normal3f_array("N")?;
+        let mut uv = parameters.get_point2f_array("uv")?;
+        let mut face_indices = parameters.get_int_array("faceIndices")?;
+        if indices.is_empty() {
+            if p.len() != per_face {
+                return Err(ParseError::new(
+                    loc,
+                    format!("Vertex indices \"indices\" must be provided with {}.", what),
+                ));
+            }
+            indices = (0..per_face as i32).collect();
+        } else if indices.len() % per_face != 0 {
+            let excess = indices.len() % per_face;
+            warning(
+                loc,
+                &format!(
+                    "Number of vertex indices {} not a multiple of {}. Discarding {} excess.",
+                    indices.len(),
+                    per_face,
+                    excess
+                ),
+            );
+            indices.truncate(indices.len() - excess);
+        }
+        if p.is_empty() {
+            return Err(ParseError::new(
+                loc,
+                format!("Vertex positions \"P\" must be provided with {}.", what),
+            ));
+        }
+        if !uv.is_empty() && uv.len() != p.len() {
+            warning(
+                loc,
+                &format!(
+                    "Number of \"uv\"s for {} must match \"P\"s. Discarding uvs.",
+                    what
+                ),
+            );
+            uv.clear();
+        }
+        if !n.is_empty() && n.len() != p.len() {
+            warning(
+                loc,
+                &format!(
+                    "Number of \"N\"s for {} must match \"P\"s. Discarding \"N\"s.",
+                    what
+                ),
+            );
+            n.clear();
+        }
+        if let Some(&i) = indices.iter().find(|&&i| i < 0 || i as usize >= p.len()) {
+            return Err(ParseError::new(
+                loc,
+                format!(
+                    "{} has out-of-bounds vertex index {} ({} \"P\" values were given). \
+                     Discarding this mesh.",
+                    what,
+                    i,
+                    p.len()
+                ),
+            ));
+        }
+        if !face_indices.is_empty() && face_indices.len() != indices.len() / per_face {
+            warning(
+                loc,
+                &format!(
+                    "Number of face indices {} does not match number of faces {}. \
+                     Discarding face indices.",
+                    face_indices.len(),
+                    indices.len() / per_face
+                ),
+            );
+            face_indices.clear();
+        }
+        Ok(Self {
+            indices,
+            p,
+            n,
+            uv,
+            face_indices,
+        })
+    }
+}
+
+// Moves vertices and normals into rendering space, flipping the normals if
+// the shape's orientation is reversed.
+fn to_render_space(
+    render_from_object: &Transform,
+    reverse_orientation: bool,
+    p: &mut [Point3f],
+    n: &mut [Normal3f],
+) {
+    for p in p.iter_mut() {
+        *p = render_from_object.apply_point(*p);
+    }
+    for n in n.iter_mut() {
+        *n = render_from_object.apply_normal(*n);
+        if reverse_orientation {
+            *n = *n * -1.0;
+        }
+    }
+}
+
+/// The vertices of a triangle mesh, in rendering space.
+pub struct TriangleMesh {
+    pub indices: Vec<i32>,
+    pub p: Vec<Point3f>,
+    pub n: Vec<Normal3f>,
+    pub s: Vec<Vector3f>,
+    pub uv: Vec<Point2f>,
+    pub face_indices: Vec<i32>,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+}
+
+impl TriangleMesh {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        render_from_object: &Transform,
+        reverse_orientation: bool,
+        indices: Vec<i32>,
+        mut p: Vec<Point3f>,
+        mut s: Vec<Vector3f>,
+        mut n: Vec<Normal3f>,
+        uv: Vec<Point2f>,
+        face_indices: Vec<i32>,
+    ) -> Self {
+        to_render_space(render_from_object, reverse_orientation, &mut p, &mut n);
+        for s in s.iter_mut() {
+            *s = render_from_object.apply_vector(*s);
+        }
+        Self {
+            indices,
+            p,
+            n,
+            s,
+            uv,
+            face_indices,
+            reverse_orientation,
+            transform_swaps_handedness: render_from_object.swaps_handedness(),
+        }
+    }
+
+    fn create(
+        render_from_object: &Transform,
+        reverse_orientation: bool,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Arc<Self>, ParseError> {
+        let m = MeshParameters::read("triangle mesh", 3, parameters, loc)?;
+        let mut s = parameters.get_vector3f_array("S")?;
+        if !s.is_empty() && s.len() != m.p.len() {
+            warning(
+                loc,
+                "Number of \"S\"s for triangle mesh must match \"P\"s. Discarding \"S\"s.",
+            );
+            s.clear();
+        }
+        Ok(Arc::new(Self::new(
+            render_from_object,
+            reverse_orientation,
+            m.indices,
+            m.p,
+            s,
+            m.n,
+            m.uv,
+            m.face_indices,
+        )))
+    }
+}
+
+pub struct Triangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub index: usize,
+}
+
+impl Triangle {
+    pub fn create_triangles(mesh: Arc<TriangleMesh>) -> Vec<Shape> {
+        (0..mesh.indices.len() / 3)
+            .map(|index| {
+                Shape::Triangle(Triangle {
+                    mesh: mesh.clone(),
+                    index,
+                })
+            })
+            .collect()
+    }
+
+    pub fn vertices(&self) -> [Point3f; 3] {
+        let v = &self.mesh.indices[3 * self.index..3 * self.index + 3];
+        [0, 1, 2].map(|i| self.mesh.p[v[i] as usize])
+    }
+}
+
+/// The vertices of a mesh of bilinear patches, in rendering space. Each
+/// patch's vertices are given in the order p00, p10, p01, p11.
+pub struct BilinearPatchMesh {
+    pub indices: Vec<i32>,
+    pub p: Vec<Point3f>,
+    pub n: Vec<Normal3f>,
+    pub uv: Vec<Point2f>,
+    pub face_indices: Vec<i32>,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+}
+
+impl BilinearPatchMesh {
+    pub fn new(
+        render_from_object: &Transform,
+        reverse_orientation: bool,
+        indices: Vec<i32>,
+        mut p: Vec<Point3f>,
+        mut n: Vec<Normal3f>,
+        uv: Vec<Point2f>,
+        face_indices: Vec<i32>,
+    ) -> Self {
+        to_render_space(render_from_object, reverse_orientation, &mut p, &mut n);
+        Self {
+            indices,
+            p,
+            n,
+            uv,
+            face_indices,
+            reverse_orientation,
+            transform_swaps_handedness: render_from_object.swaps_handedness(),
+        }
+    }
+
+    fn create(
+        render_from_object: &Transform,
+        reverse_orientation: bool,
+        parameters: &ParameterDictionary,
+        loc: &FileLoc,
+    ) -> Result<Arc<Self>, ParseError> {
+        let m = MeshParameters::read("bilinear patch mesh", 4, parameters, loc)?;
+        Ok(Arc::new(Self::new(
+            render_from_object,
+            reverse_orientation,
+            m.indices,
+            m.p,
+            m.n,
+            m.uv,
+            m.face_indices,
+        )))
+    }
+}
+
+pub struct BilinearPatch {
+    pub mesh: Arc<BilinearPatchMesh>,
+    pub index: usize,
+    pub area: Float,
+}
+
+impl BilinearPatch {
+    pub fn create_patches(mesh: Arc<BilinearPatchMesh>) -> Vec<Shape> {
+        (0..mesh.indices.len() / 4)
+            .map(|index| {
+                let mut patch = BilinearPatch {
+                    mesh: mesh.clone(),
+                    index,
+                    area: 0.0,
+                };
+                patch.area = patch.compute_area();
+                Shape::BilinearPatch(patch)
+            })
+            .collect()
+    }
+
+    pub fn vertices(&self) -> [Point3f; 4] {
+        let v = &self.mesh.indices[4 * self.index..4 * self.index + 4];
+        [0, 1, 2, 3].map(|i| self.mesh.p[v[i] as usize])
+    }
+
+    // Exact for rectangles; other patches are approximated by splitting
+    // them into a 3x3 grid of quads.
+    fn compute_area(&self) -> Float {
+        let [p00, p10, p01, p11] = self.vertices();
+        if is_rectangle(p00, p10, p01, p11) {
+            return length(p01 - p00) * length(p10 - p00);
+        }
+        const N: usize = 3;
+        let lerp = |t: Float, a: Point3f, b: Point3f| a + (b - a) * t;
+        let p = |i: usize, j: usize| {
+            let (u, v) = (i as Float / N as Float, j as Float / N as Float);
+            lerp(u, lerp(v, p00, p01), lerp(v, p10, p11))
+        };
+        let mut area = 0.0;
+        for i in 0..N {
+            for j in 0..N {
+                area += 0.5 * length(cross(p(i + 1, j + 1) - p(i, j), p(i + 1, j) - p(i, j + 1)));
+            }
+        }
+        area
+    }
+}
+
+fn is_rectangle(p00: Point3f, p10: Point3f, p01: Point3f, p11: Point3f) -> bool {
+    if p00 == p01 || p01 == p11 || p11 == p10 || p10 == p00 {
+        return false;
+    }
+    let n = normalize(cross(p10 - p00, p01 - p00));
+    if dot(normalize(p11 - p00), n).abs() > 1e-5 {
+        return false;
+    }
+    let center = (p00 + p01 + p10 + p11) / 4.0;
+    let d2 = [p00, p01, p10, p11].map(|p| {
+        let d = p - center;
+        dot(d, d)
+    });
+    d2[1..].iter().all(|d| ((d - d2[0]) / d2[0]).abs() <= 1e-4)
+}
+
+// A "plymesh" becomes triangles for the file's triangles and bilinear
+// patches for its quads.
+fn create_ply_mesh(
+    render_from_object: &Transform,
+    reverse_orientation: bool,
+    parameters: &ParameterDictionary,
+    loc: &FileLoc,
+) -> Result<Vec<Shape>, ParseError> {
+    let filename = parameters.get_one_filename("filename")?;
+    let mesh = TriQuadMesh::read_ply(&filename).map_err(|e| {
+        ParseError::new(
+            loc,
+            format!("unable to read PLY file \"{}\": {}", filename, e),
+        )
+    })?;
+    if parameters
+        .params()
+        .iter()
+        .any(|p| *p.name == "displacement")
+    {
+        return Err(ParseError::new(
+            loc,
+            "plymesh: displacement mapping is not supported yet.",
+        ));
+    }
+    // Only used for displacement.
+    parameters.get_one_float("edgelength", 1.0)?;
+
+    let n_faces = mesh.tri_indices.len() / 3 + mesh.quad_indices.len() / 4;
+    let face_indices = if mesh.face_indices.len() == n_faces {
+        mesh.face_indices
+    } else {
+        Vec::new()
+    };
+    // The file gives the face indices of triangles and quads together.
+    let (tri_face_indices, quad_face_indices) = if face_indices.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        let n_tris = mesh.tri_indices.len() / 3;
+        (
+            face_indices[..n_tris].to_vec(),
+            face_indices[n_tris..].to_vec(),
+        )
+    };
+    let mut shapes = Vec::new();
+    if !mesh.tri_indices.is_empty() {
+        let triangles = TriangleMesh::new(
+            render_from_object,
+            reverse_orientation,
+            mesh.tri_indices,
+            mesh.p.clone(),
+            Vec::new(),
+            mesh.n.clone(),
+            mesh.uv.clone(),
+            tri_face_indices,
+        );
+        shapes.extend(Triangle::create_triangles(Arc::new(triangles)));
+    }
+    if !mesh.quad_indices.is_empty() {
+        let patches = BilinearPatchMesh::new(
+            render_from_object,
+            reverse_orientation,
+            mesh.quad_indices,
+            mesh.p,
+            mesh.n,
+            mesh.uv,
+            quad_face_indices,
+        );
+        shapes.extend(BilinearPatch::create_patches(Arc::new(patches)));
+    }
+    Ok(shapes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paramdict::ParsedParameter;
+    use crate::scene::INTERNED_STRINGS;
+    use crate::util::colorspace::sRGB;
+    use crate::util::math::PI;
+    use crate::util::transform::translate;
+
+    fn param(type_name: &str, name: &str, floats: &[Float], ints: &[i32]) -> ParsedParameter {
+        let mut p = ParsedParameter::new();
+        p.type_name = INTERNED_STRINGS.lookup(&type_name.to_string());
+        p.name = INTERNED_STRINGS.lookup(&name.to_string());
+        p.floats = floats.to_vec();
+        p.ints = ints.to_vec();
+        p
+    }
+
+    fn float(name: &str, v: Float) -> ParsedParameter {
+        param("float", name, &[v], &[])
+    }
+
+    fn create(name: &str, params: Vec<ParsedParameter>) -> Result<Vec<Shape>, ParseError> {
+        let render_from_object = translate(Vector3f::new(0.0, 0.0, 5.0));
+        Shape::create(
+            name,
+            &Arc::new(render_from_object.clone()),
+            &Arc::new(render_from_object.inverse()),
+            false,
+            &ParameterDictionary::new(params, &sRGB),
+            &FileLoc::default(),
+        )
+    }
+
+    fn assert_near(a: Float, b: Float) {
+        assert!((a - b).abs() < 1e-4 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn quadrics() {
+        let sphere = create("sphere", vec![float("radius", 2.0)]).unwrap();
+        assert_near(sphere[0].area(), 16.0 * PI);
+        let bounds = sphere[0].bounds();
+        assert_eq!(bounds.p_min, Point3f::new(-2.0, -2.0, 3.0));
+        assert_eq!(bounds.p_max, Point3f::new(2.0, 2.0, 7.0));
+
+        let half = create("disk", vec![float("phimax", 180.0)]).unwrap();
+        assert_near(half[0].area(), PI / 2.0);
+        let cylinder = create("cylinder", vec![float("zmin", 0.0)]).unwrap();
+        assert_near(cylinder[0].area(), 2.0 * PI);
+    }
+
+    #[test]
+    fn meshes() {
+        let p = [0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 1.0, 0.0, 2.0, 1.0, 0.0];
+        let triangles = create(
+            "trianglemesh",
+            vec![
+                param("point3", "P", &p, &[]),
+                param("integer", "indices", &[], &[0, 1, 2, 2, 1, 3]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_near(triangles.iter().map(Shape::area).sum(), 2.0);
+        assert_eq!(triangles[1].bounds().p_max, Point3f::new(2.0, 1.0, 5.0));
+
+        let patches = create("bilinearmesh", vec![param("point3", "P", &p, &[])]).unwrap();
+        assert_near(patches[0].area(), 2.0);
+
+        let e = create(
+            "trianglemesh",
+            vec![param("integer", "indices", &[], &[0, 1, 2])],
+        )
+        .err()
+        .unwrap();
+        assert!(e.message.contains("\"P\" must be provided"), "{}", e);
+        let e = create(
+            "trianglemesh",
+            vec![
+                param("point3", "P", &[0.0; 9], &[]),
+                param("integer", "indices", &[], &[0, 1, 3]),
+            ],
+        )
+        .err()
+        .unwrap();
+        assert!(e.message.contains("out-of-bounds vertex index 3"), "{}", e);
+        let e = create("cone", vec![]).err().unwrap();
+        assert_eq!(e.message, "cone: shape type unknown.");
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::paramdict::{SpectrumType, TextureParameterDictionary};
+use crate::util::error::{FileLoc, ParseError};
+use crate::util::spectrum::{ConstantSpectrum, Spectrum};
+use crate::util::transform::Transform;
+use crate::util::vecmath::{Point2f, Point3f};
+use crate::Float;
+
+/// Where a texture is being looked up.
+pub struct TextureEvalContext {
+    pub p: Point3f,
+    pub uv: Point2f,
+}
+
+pub trait FloatTexture: Send + Sync {
+    fn evaluate(&self, ctx: &TextureEvalContext) -> Float;
+}
+
+pub trait SpectrumTexture: Send + Sync {
+    fn evaluate(&self, ctx: &TextureEvalContext, lambda: Float) -> Float;
+}
+
+pub struct FloatConstantTexture {
+    value: Float,
+}
+
+impl FloatConstantTexture {
+    pub fn new(value: Float) -> Self {
+        Self { value }
+    }
+}
+
+impl FloatTexture for FloatConstantTexture {
+    fn evaluate(&self, _ctx: &TextureEvalContext) -> Float {
+        self.value
+    }
+}
+
+pub struct SpectrumConstantTexture {
+    value: Arc<dyn Spectrum>,
+}
+
+impl SpectrumConstantTexture {
+    pub fn new(value: Arc<dyn Spectrum>) -> Self {
+        Self { value }
+    }
+}
+
+impl SpectrumTexture for SpectrumConstantTexture {
+    fn evaluate(&self, _ctx: &TextureEvalContext, lambda: Float) -> Float {
+        self.value.evaluate(lambda)
+    }
+}
+
+/// Creates a float texture of the type called `name` from the parameters
+/// of its Texture directive.
+pub fn create_float_texture(
+    name: &str,
+    _render_from_texture: &Transform,
+    parameters: &TextureParameterDictionary,
+    loc: &FileLoc,
+) -> Result<Arc<dyn FloatTexture>, ParseError> {
+    let texture: Arc<dyn FloatTexture> = match name {
+        "constant" => Arc::new(FloatConstantTexture::new(
+            parameters.dict().get_one_float("value", 1.0)?,
+        )),
+        _ => {
+            return Err(ParseError::new(
+                loc,
+                format!("{}: float texture type unknown.", name),
+            ))
+        }
+    };
+    parameters.dict().report_unused()?;
+    Ok(texture)
+}
+
+/// Creates a spectrum texture of the type called `name`, interpreting RGB
+/// values as `spectrum_type`.
+pub fn create_spectrum_texture(
+    name: &str,
+    _render_from_texture: &Transform,
+    parameters: &TextureParameterDictionary,
+    spectrum_type: SpectrumType,
+    loc: &FileLoc,
+) -> Result<Arc<dyn SpectrumTexture>, ParseError> {
+    let texture: Arc<dyn SpectrumTexture> = match name {
+        "constant" => {
+            let one: Arc<dyn Spectrum> = Arc::new(ConstantSpectrum::new(1.0));
+            let value = parameters
+                .dict()
+                .get_one_spectrum("value", Some(one), spectrum_type)?
+                .unwrap();
+            Arc::new(SpectrumConstantTexture::new(value))
+        }
+        _ => {
+            return Err(ParseError::new(
+                loc,
+                format!("{}: spectrum texture type unknown.", name),
+            ))
+        }
+    };
+    parameters.dict().report_unused()?;
+    Ok(texture)
+}
+
+/// The textures declared with the Texture directive, by name. Spectrum
+/// textures are created once for each way their RGB values can be
+/// interpreted, since which one a material needs depends on the parameter
+/// that uses it.
+#[derive(Default)]
+pub struct NamedTextures {
+    pub float_textures: HashMap<String, Arc<dyn FloatTexture>>,
+    pub albedo_spectrum_textures: HashMap<String, Arc<dyn SpectrumTexture>>,
+    pub unbounded_spectrum_textures: HashMap<String, Arc<dyn SpectrumTexture>>,
+    pub illuminant_spectrum_textures: HashMap<String, Arc<dyn SpectrumTexture>>,
+}
+
+impl NamedTextures {
+    pub fn spectrum_textures(
+        &self,
+        spectrum_type: SpectrumType,
+    ) -> &HashMap<String, Arc<dyn SpectrumTexture>> {
+        match spectrum_type {
+            SpectrumType::Albedo => &self.albedo_spectrum_textures,
+            SpectrumType::Unbounded => &self.unbounded_spectrum_textures,
+            SpectrumType::Illuminant => &self.illuminant_spectrum_textures,
+        }
+    }
+}
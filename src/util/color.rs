@@ -0,0 +1,298 @@
+use std::ops::Div;
+
+use super::spectrum::{cie_x, cie_y, cie_z, Spectrum, LAMBDA_MAX, LAMBDA_MIN};
+use crate::Float;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RGB {
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
+}
+
+impl RGB {
+    pub fn new(r: Float, g: Float, b: Float) -> Self {
+        Self { r, g, b }
+    }
+
+    pub fn max_component(&self) -> Float {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn min_component(&self) -> Float {
+        self.r.min(self.g).min(self.b)
+    }
+}
+
+impl Div<Float> for RGB {
+    type Output = Self;
+    fn div(self, rhs: Float) -> Self {
+        Self::new(self.r / rhs, self.g / rhs, self.b / rhs)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct XYZ {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl XYZ {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
+        Self { x, y, z }
+    }
+
+    /// The color with chromaticity `xy` and luminance `y`.
+    pub fn from_xyy(xy: (Float, Float), y: Float) -> Self {
+        if xy.1 == 0.0 {
+            return Self::new(0.0, 0.0, 0.0);
+        }
+        Self::new(xy.0 * y / xy.1, y, (1.0 - xy.0 - xy.1) * y / xy.1)
+    }
+
+    /// The chromaticity coordinates.
+    pub fn xy(&self) -> (Float, Float) {
+        let sum = self.x + self.y + self.z;
+        (self.x / sum, self.y / sum)
+    }
+}
+
+/// A smooth spectrum bounded by zero and one: a quadratic in wavelength
+/// passed through a sigmoid. See Jakob and Hanika, "A Low-Dimensional
+/// Function Space for Efficient Spectral Upsampling" (2019).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RGBSigmoidPolynomial {
+    c0: Float,
+    c1: Float,
+    c2: Float,
+}
+
+impl RGBSigmoidPolynomial {
+    pub fn new(c0: Float, c1: Float, c2: Float) -> Self {
+        Self { c0, c1, c2 }
+    }
+
+    pub fn evaluate(&self, lambda: Float) -> Float {
+        sigmoid((self.c0 * lambda + self.c1) * lambda + self.c2)
+    }
+
+    pub fn max_value(&self) -> Float {
+        let result = self.evaluate(LAMBDA_MIN).max(self.evaluate(LAMBDA_MAX));
+        let lambda = -self.c1 / (2.0 * self.c0);
+        if (LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+            result.max(self.evaluate(lambda))
+        } else {
+            result
+        }
+    }
+}
+
+fn sigmoid(x: Float) -> Float {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+/// Finds sigmoid polynomials that reproduce RGB colors under a color space's
+/// illuminant, by Gauss-Newton iteration on the difference in CIELAB, as
+/// Jakob and Hanika's rgb2spec_opt does when precomputing its tables.
+pub(crate) struct RGBToSpectrumSolver {
+    // Wavelengths mapped to [0, 1], which keeps the problem well conditioned.
+    lambdas: Vec<f64>,
+    // The RGB contribution of each wavelength, weighted by the illuminant
+    // and normalized so that a constant spectrum of one is white.
+    rgb_weights: Vec<[f64; 3]>,
+    xyz_from_rgb: [[f64; 3]; 3],
+    white: [f64; 3],
+}
+
+impl RGBToSpectrumSolver {
+    pub fn new(
+        illuminant: &dyn Spectrum,
+        rgb_from_xyz: [[Float; 3]; 3],
+        xyz_from_rgb: [[Float; 3]; 3],
+    ) -> Self {
+        let to_f64 = |m: [[Float; 3]; 3]| m.map(|r| r.map(|v| v as f64));
+        let rgb_from_xyz = to_f64(rgb_from_xyz);
+        let xyz_from_rgb = to_f64(xyz_from_rgb);
+        let lambdas: Vec<f64> = (LAMBDA_MIN as i32..=LAMBDA_MAX as i32)
+            .map(|l| l as f64)
+            .collect();
+        let illum: Vec<f64> = lambdas
+            .iter()
+            .map(|&l| illuminant.evaluate(l as Float) as f64)
+            .collect();
+        let norm: f64 = lambdas.iter().zip(&illum).map(|(&l, i)| cie_y(l) * i).sum();
+        let rgb_weights = lambdas
+            .iter()
+            .zip(&illum)
+            .map(|(&l, i)| {
+                let xyz = [cie_x(l), cie_y(l), cie_z(l)].map(|v| v * i / norm);
+                mul(&rgb_from_xyz, xyz)
+            })
+            .collect();
+        Self {
+            lambdas: lambdas
+                .iter()
+                .map(|l| (l - LAMBDA_MIN as f64) / (LAMBDA_MAX - LAMBDA_MIN) as f64)
+                .collect(),
+            rgb_weights,
+            white: mul(&xyz_from_rgb, [1.0; 3]),
+            xyz_from_rgb,
+        }
+    }
+
+    /// Returns the polynomial whose RGB is closest to `rgb`, which must be
+    /// in [0, 1].
+    pub fn solve(&self, rgb: RGB) -> RGBSigmoidPolynomial {
+        let target = [rgb.r as f64, rgb.g as f64, rgb.b as f64];
+        if target[0] == target[1] && target[1] == target[2] {
+            // Grays are constant, which only needs the constant term.
+            let v = target[0];
+            let c2 = if v <= 0.0 {
+                Float::NEG_INFINITY
+            } else if v >= 1.0 {
+                Float::INFINITY
+            } else {
+                ((v - 0.5) / (v * (1.0 - v)).sqrt()) as Float
+            };
+            return RGBSigmoidPolynomial::new(0.0, 0.0, c2);
+        }
+        // Zero coefficients give a constant 0.5; walk from that gray to the
+        // target so each solve starts close to its answer.
+        const STEPS: usize = 8;
+        let mut coeffs = [0.0; 3];
+        for step in 1..=STEPS {
+            let t = step as f64 / STEPS as f64;
+            let goal = target.map(|c| 0.5 + t * (c - 0.5));
+            self.gauss_newton(goal, &mut coeffs);
+        }
+        // Express the quadratic in nanometers rather than [0, 1].
+        let [a, b, c] = coeffs;
+        let c0 = LAMBDA_MIN as f64;
+        let c1 = 1.0 / (LAMBDA_MAX - LAMBDA_MIN) as f64;
+        RGBSigmoidPolynomial::new(
+            (a * c1 * c1) as Float,
+            (b * c1 - 2.0 * a * c0 * c1 * c1) as Float,
+            (c - b * c0 * c1 + a * (c0 * c1) * (c0 * c1)) as Float,
+        )
+    }
+
+    fn gauss_newton(&self, goal: [f64; 3], coeffs: &mut [f64; 3]) {
+        const ITERATIONS: usize = 15;
+        const DELTA: f64 = 1e-4;
+        for _ in 0..ITERATIONS {
+            let r = self.residual(goal, *coeffs);
+            let mut jacobian = [[0.0; 3]; 3];
+            for j in 0..3 {
+                let mut lo = *coeffs;
+                let mut hi = *coeffs;
+                lo[j] -= DELTA;
+                hi[j] += DELTA;
+                let (r_lo, r_hi) = (self.residual(goal, lo), self.residual(goal, hi));
+                for i in 0..3 {
+                    jacobian[i][j] = (r_hi[i] - r_lo[i]) / (2.0 * DELTA);
+                }
+            }
+            let Some(step) = solve3(jacobian, r) else {
+                break;
+            };
+            for j in 0..3 {
+                coeffs[j] -= step[j];
+            }
+            // Keep the sigmoid from saturating so far that the Jacobian
+            // vanishes.
+            let max = coeffs.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+            if max > 200.0 {
+                coeffs.iter_mut().for_each(|c| *c *= 200.0 / max);
+            }
+            if r.iter().map(|v| v * v).sum::<f64>() < 1e-12 {
+                break;
+            }
+        }
+    }
+
+    fn residual(&self, goal: [f64; 3], coeffs: [f64; 3]) -> [f64; 3] {
+        let mut rgb = [0.0; 3];
+        for (l, w) in self.lambdas.iter().zip(&self.rgb_weights) {
+            let x = (coeffs[0] * l + coeffs[1]) * l + coeffs[2];
+            let s = 0.5 + x / (2.0 * (1.0 + x * x).sqrt());
+            for i in 0..3 {
+                rgb[i] += s * w[i];
+            }
+        }
+        let lab = self.lab(rgb);
+        let goal = self.lab(goal);
+        [lab[0] - goal[0], lab[1] - goal[1], lab[2] - goal[2]]
+    }
+
+    fn lab(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let xyz = mul(&self.xyz_from_rgb, rgb);
+        let f = |t: f64| {
+            const DELTA: f64 = 6.0 / 29.0;
+            if t > DELTA * DELTA * DELTA {
+                t.cbrt()
+            } else {
+                t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+            }
+        };
+        let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / self.white[i]));
+        [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+    }
+}
+
+fn mul(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    m.map(|r| r[0] * v[0] + r[1] * v[1] + r[2] * v[2])
+}
+
+// Solves m x = b by Cramer's rule.
+fn solve3(m: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&m);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+    Some([0, 1, 2].map(|j| {
+        let mut mj = m;
+        for i in 0..3 {
+            mj[i][j] = b[i];
+        }
+        det(&mj) / d
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sigmoid_polynomial_bounds() {
+        assert_eq!(RGBSigmoidPolynomial::new(0., 0., 0.).evaluate(500.), 0.5);
+        let p = RGBSigmoidPolynomial::new(0., 0., Float::INFINITY);
+        assert_eq!(p.evaluate(500.), 1.);
+        let p = RGBSigmoidPolynomial::new(-1e-4, 0.1, -20.);
+        assert!((p.max_value() - p.evaluate(500.)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn xyy_round_trip() {
+        let xyz = XYZ::from_xyy((0.3127, 0.329), 1.);
+        let xy = xyz.xy();
+        assert!((xy.0 - 0.3127).abs() < 1e-6 && (xy.1 - 0.329).abs() < 1e-6);
+        assert_eq!(xyz.y, 1.);
+    }
+
+    #[test]
+    fn solve3_solves() {
+        let x = solve3([[2., 1., 0.], [0., 3., 1.], [1., 0., 4.]], [3., 4., 5.]).unwrap();
+        assert!(x.iter().all(|v| (v - 1.).abs() < 1e-12));
+    }
+}
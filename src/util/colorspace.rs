@@ -1,10 +1,187 @@
-pub struct RGBColorSpace {}
+use std::sync::Arc;
 
-pub static sRGB: RGBColorSpace = RGBColorSpace {};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
 
-pub fn get_named(name: &String) -> Option<&'static RGBColorSpace> {
-    match name.as_str() {
-        "sRGB" => Some(&sRGB),
+use super::color::{RGBSigmoidPolynomial, RGBToSpectrumSolver, RGB, XYZ};
+use super::math::square_matrix::SquareMatrix;
+use super::spectrum::{get_named_spectrum, spectrum_to_xyz, Spectrum};
+use crate::Float;
+
+/// An RGB color space, given by the chromaticities of its primaries and the
+/// spectrum of its white.
+pub struct RGBColorSpace {
+    pub r: (Float, Float),
+    pub g: (Float, Float),
+    pub b: (Float, Float),
+    pub w: (Float, Float),
+    pub illuminant: Arc<dyn Spectrum>,
+    pub xyz_from_rgb: SquareMatrix<3>,
+    pub rgb_from_xyz: SquareMatrix<3>,
+    solver: RGBToSpectrumSolver,
+    // Solved coefficients by the bits of their RGB, since scenes repeat
+    // the same few colors many times over and each solve is costly.
+    coeffs: DashMap<[u32; 3], RGBSigmoidPolynomial>,
+}
+
+impl RGBColorSpace {
+    pub fn new(
+        r: (Float, Float),
+        g: (Float, Float),
+        b: (Float, Float),
+        illuminant: Arc<dyn Spectrum>,
+    ) -> Self {
+        let w = spectrum_to_xyz(&*illuminant).xy();
+        let [r_xyz, g_xyz, b_xyz] = [r, g, b].map(|c| XYZ::from_xyy(c, 1.0));
+        let rgb = SquareMatrix([
+            [r_xyz.x, g_xyz.x, b_xyz.x],
+            [r_xyz.y, g_xyz.y, b_xyz.y],
+            [r_xyz.z, g_xyz.z, b_xyz.z],
+        ]);
+        // Scale the primaries so that they sum to the white point.
+        let white = XYZ::from_xyy(w, 1.0);
+        let c = rgb
+            .inverse()
+            .expect("color space primaries are collinear")
+            .mul_vec([white.x, white.y, white.z]);
+        let xyz_from_rgb = rgb.mul(&SquareMatrix::diag(c));
+        let rgb_from_xyz = xyz_from_rgb.inverse().unwrap();
+        let solver = RGBToSpectrumSolver::new(&*illuminant, rgb_from_xyz.0, xyz_from_rgb.0);
+        Self {
+            r,
+            g,
+            b,
+            w,
+            illuminant,
+            xyz_from_rgb,
+            rgb_from_xyz,
+            solver,
+            coeffs: DashMap::new(),
+        }
+    }
+
+    pub fn to_rgb(&self, xyz: XYZ) -> RGB {
+        let [r, g, b] = self.rgb_from_xyz.mul_vec([xyz.x, xyz.y, xyz.z]);
+        RGB::new(r, g, b)
+    }
+
+    /// Returns the spectrum with reflectance `rgb` under this color space's
+    /// illuminant. Components must be in [0, 1].
+    pub fn to_rgb_coeffs(&self, rgb: RGB) -> RGBSigmoidPolynomial {
+        debug_assert!(rgb.min_component() >= 0.0 && rgb.max_component() <= 1.0);
+        let key = [rgb.r.to_bits(), rgb.g.to_bits(), rgb.b.to_bits()];
+        if let Some(coeffs) = self.coeffs.get(&key) {
+            return *coeffs;
+        }
+        // Solved without holding the map's lock, so that other threads
+        // aren't kept waiting; two threads may solve the same color.
+        let coeffs = self.solver.solve(rgb);
+        self.coeffs.insert(key, coeffs);
+        coeffs
+    }
+}
+
+fn d65() -> Arc<dyn Spectrum> {
+    get_named_spectrum("stdillum-D65").unwrap()
+}
+
+#[allow(non_upper_case_globals)]
+pub static sRGB: Lazy<RGBColorSpace> =
+    Lazy::new(|| RGBColorSpace::new((0.64, 0.33), (0.3, 0.6), (0.15, 0.06), d65()));
+
+pub static DCI_P3: Lazy<RGBColorSpace> =
+    Lazy::new(|| RGBColorSpace::new((0.68, 0.32), (0.265, 0.69), (0.15, 0.06), d65()));
+
+pub static REC2020: Lazy<RGBColorSpace> =
+    Lazy::new(|| RGBColorSpace::new((0.708, 0.292), (0.170, 0.797), (0.131, 0.046), d65()));
+
+pub fn get_named(name: &str) -> Option<&'static RGBColorSpace> {
+    match name {
+        "srgb" | "sRGB" => Some(&sRGB),
+        "dci-p3" => Some(&DCI_P3),
+        "rec2020" => Some(&REC2020),
         _ => None,
     }
 }
+
+/// The name `get_named` knows `color_space` by, if it's one of those.
+pub fn name_of(color_space: &RGBColorSpace) -> Option<&'static str> {
+    [
+        ("srgb", &*sRGB),
+        ("dci-p3", &*DCI_P3),
+        ("rec2020", &*REC2020),
+    ]
+    .into_iter()
+    .find(|(_, cs)| std::ptr::eq(*cs, color_space))
+    .map(|(name, _)| name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::color::RGB;
+    use crate::util::spectrum::{RGBAlbedoSpectrum, RGBUnboundedSpectrum};
+
+    // The RGB of a reflectance lit by the color space's illuminant.
+    fn reflected_rgb(cs: &RGBColorSpace, s: &dyn Spectrum) -> RGB {
+        struct Product<'a>(&'a dyn Spectrum, &'a dyn Spectrum);
+        impl Spectrum for Product<'_> {
+            fn evaluate(&self, lambda: Float) -> Float {
+                self.0.evaluate(lambda) * self.1.evaluate(lambda)
+            }
+            fn max_value(&self) -> Float {
+                self.0.max_value() * self.1.max_value()
+            }
+        }
+        let xyz = spectrum_to_xyz(&Product(s, &*cs.illuminant));
+        let white = spectrum_to_xyz(&*cs.illuminant).y;
+        cs.to_rgb(XYZ::new(xyz.x / white, xyz.y / white, xyz.z / white))
+    }
+
+    fn assert_close(a: RGB, b: RGB, tolerance: Float) {
+        let d = (a.r - b.r)
+            .abs()
+            .max((a.g - b.g).abs())
+            .max((a.b - b.b).abs());
+        assert!(d < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn white_is_white() {
+        let rgb = sRGB.to_rgb(XYZ::from_xyy(sRGB.w, 1.));
+        assert_close(rgb, RGB::new(1., 1., 1.), 1e-4);
+        assert!((sRGB.w.0 - 0.3127).abs() < 0.005);
+    }
+
+    #[test]
+    fn albedo_round_trips() {
+        for rgb in [
+            RGB::new(0.2, 0.5, 0.8),
+            RGB::new(0.8, 0.1, 0.1),
+            RGB::new(0.5, 0.5, 0.5),
+            RGB::new(0., 0., 0.),
+        ] {
+            let s = RGBAlbedoSpectrum::new(&sRGB, rgb);
+            assert_close(reflected_rgb(&sRGB, &s), rgb, 0.01);
+            assert!(s.max_value() <= 1.);
+        }
+    }
+
+    #[test]
+    fn coeffs_are_solved_once() {
+        let cs = RGBColorSpace::new((0.64, 0.33), (0.3, 0.6), (0.15, 0.06), d65());
+        let rgb = RGB::new(0.3, 0.6, 0.9);
+        let coeffs = cs.to_rgb_coeffs(rgb);
+        assert_eq!(cs.to_rgb_coeffs(rgb), coeffs);
+        assert_eq!(cs.coeffs.len(), 1);
+        cs.to_rgb_coeffs(RGB::new(0.9, 0.6, 0.3));
+        assert_eq!(cs.coeffs.len(), 2);
+    }
+
+    #[test]
+    fn unbounded_round_trips() {
+        let rgb = RGB::new(4., 2., 1.);
+        let s = RGBUnboundedSpectrum::new(&sRGB, rgb);
+        assert_close(reflected_rgb(&sRGB, &s), rgb, 0.05);
+    }
+}
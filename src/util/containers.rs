@@ -14,10 +14,15 @@ impl<T: Eq + Hash + Clone> InternCache<T> {
         Self(DashSet::with_capacity(256))
     }
 
-    fn len(&self) -> usize {
+    /// The number of distinct items looked up so far.
+    pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn lookup(&self, item: &T) -> Arc<T> {
         if !self.0.contains(item) {
             self.0.insert(Arc::new(item.clone()));
@@ -26,6 +31,12 @@ impl<T: Eq + Hash + Clone> InternCache<T> {
     }
 }
 
+impl<T: Eq + Hash + Clone> Default for InternCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // impl<T: Display + Eq + Hash> ToString for InternCache<T> {
 //     fn to_string(&self) -> String {
 //         self.0
@@ -1,8 +1,9 @@
+use std::fmt;
 use std::str;
 
-use super::print::red;
+use super::print::{red, yellow};
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, PartialEq)]
 pub struct FileLoc {
     pub filename: String,
     pub line: i32,
@@ -10,28 +11,206 @@ pub struct FileLoc {
 }
 
 impl FileLoc {
+    /// Returns the location of the first character of `filename`. Lines and
+    /// columns count from 1, as in text editors.
     pub fn new(filename: String) -> Self {
-        let mut loc = FileLoc::default();
-        loc.filename = filename;
-        loc
+        FileLoc {
+            filename,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl fmt::Display for FileLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.filename, self.line, self.column)
+    }
+}
+
+/// An error in a scene description, located at the place in the file where
+/// it was detected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub loc: FileLoc,
+    pub message: String,
+    /// The token that caused the error, if there is a single culprit.
+    pub token: Option<String>,
+    /// Where the error sits in the scene files, once the parser has filled
+    /// it in.
+    pub context: Option<Box<ErrorContext>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorContext {
+    /// The text of the line at the error's location.
+    pub source_line: Option<String>,
+    /// Locations of the Include/Import directives that led to the error's
+    /// file, innermost first.
+    pub include_trail: Vec<FileLoc>,
+}
+
+impl ParseError {
+    pub fn new(loc: &FileLoc, message: impl Into<String>) -> Self {
+        Self {
+            loc: loc.clone(),
+            message: message.into(),
+            token: None,
+            context: None,
+        }
+    }
+
+    pub fn with_token(loc: &FileLoc, token: &[u8], message: impl Into<String>) -> Self {
+        Self {
+            loc: loc.clone(),
+            message: message.into(),
+            token: Some(String::from_utf8_lossy(token).into_owned()),
+            context: None,
+        }
+    }
+
+    /// Fills in the source line and include trail from the contents of the
+    /// file the error is in. Errors that already have context are left as
+    /// they are, so that nested parses keep the most specific one.
+    pub(crate) fn add_context(&mut self, contents: &[u8], include_chain: &[FileLoc]) {
+        if self.context.is_none() {
+            self.context = Some(Box::new(ErrorContext {
+                source_line: source_line(contents, self.loc.line),
+                include_trail: include_chain.iter().rev().cloned().collect(),
+            }));
+        }
     }
 
-    fn to_string(&self) -> String {
-        format!("{}:{}:{}", self.filename, self.line, self.column)
+    /// Prints the error to standard error in the same form pbrt uses,
+    /// followed by the offending line with a caret under the column and a
+    /// note for each file that included it.
+    pub fn report(&self) {
+        // Errors about the scene as a whole, rather than a place in it,
+        // have no file.
+        let loc = (!self.loc.filename.is_empty()).then_some(&self.loc);
+        process_error(&red("Error"), loc, &self.to_string_without_loc());
+        let Some(context) = &self.context else {
+            return;
+        };
+        if let Some(line) = &context.source_line {
+            eprint!(
+                "{}",
+                format_source_line(line, self.loc.line, self.loc.column)
+            );
+        }
+        for loc in &context.include_trail {
+            eprintln!("note: included from {}", loc);
+        }
+    }
+
+    fn to_string_without_loc(&self) -> String {
+        match &self.token {
+            Some(token) => format!("\"{}\": {}", token, self.message),
+            None => self.message.clone(),
+        }
     }
 }
 
-fn process_error(error_type: &str, loc: Option<&FileLoc>, message: &String) {
-    let mut error_string = red(error_type);
-    if loc.is_some() {
-        error_string += &(": ".to_owned() + &loc.unwrap().to_string());
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.loc, self.to_string_without_loc())
     }
-    error_string += &(": ".to_owned() + message);
+}
+
+impl std::error::Error for ParseError {}
+
+// Lets `?` turn a single error into the list of errors that parsing returns.
+impl From<ParseError> for Vec<ParseError> {
+    fn from(e: ParseError) -> Self {
+        vec![e]
+    }
+}
+
+/// Puts `errors` in the order of their locations, by file and then by
+/// position in it, and drops repeats of the same error.
+pub fn sort_errors(errors: &mut Vec<ParseError>) {
+    errors.sort_by(|a, b| {
+        (&a.loc.filename, a.loc.line, a.loc.column).cmp(&(
+            &b.loc.filename,
+            b.loc.line,
+            b.loc.column,
+        ))
+    });
+    errors.dedup();
+}
+
+// Returns the text of the 1-based `line` of `contents`, without its line
+// ending.
+fn source_line(contents: &[u8], line: i32) -> Option<String> {
+    let index = usize::try_from(line).ok()?.checked_sub(1)?;
+    let text = contents.split(|&c| c == b'\n').nth(index)?;
+    let text = text.strip_suffix(b"\r").unwrap_or(text);
+    Some(String::from_utf8_lossy(text).into_owned())
+}
+
+// Lays out a source line under its line number with a caret below the
+// 1-based `column`. Tabs before the column are kept so the caret lines up
+// however the terminal expands them.
+fn format_source_line(text: &str, line: i32, column: i32) -> String {
+    let gutter = line.to_string();
+    let padding: String = text
+        .chars()
+        .take((column - 1).max(0) as usize)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "{:w$} |\n{} | {}\n{:w$} | {}^\n",
+        "",
+        gutter,
+        text,
+        "",
+        padding,
+        w = gutter.len()
+    )
+}
+
+/// Prints a warning about the scene description at `loc` to standard error.
+pub fn warning(loc: &FileLoc, message: &str) {
+    process_error(&yellow("Warning"), Some(loc), message);
+}
+
+fn process_error(error_type: &str, loc: Option<&FileLoc>, message: &str) {
+    let mut error_string = error_type.to_owned();
+    if let Some(loc) = loc {
+        error_string += &format!(": {}", loc);
+    }
+    error_string += &format!(": {}", message);
     // TODO: multi-thread mutex
-    print!("{}", error_string);
-    std::process::exit(1)
+    eprintln!("{}", error_string);
 }
 
-pub fn error_exit(loc: Option<&FileLoc>, message: &String) {
-    process_error("Error", loc, message);
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn source_line_is_one_based() {
+        let contents = b"WorldBegin\r\nShape \"sphere\"\n\nAttributeEnd";
+        assert_eq!(source_line(contents, 1).as_deref(), Some("WorldBegin"));
+        assert_eq!(
+            source_line(contents, 2).as_deref(),
+            Some("Shape \"sphere\"")
+        );
+        assert_eq!(source_line(contents, 3).as_deref(), Some(""));
+        assert_eq!(source_line(contents, 4).as_deref(), Some("AttributeEnd"));
+        assert_eq!(source_line(contents, 5), None);
+        assert_eq!(source_line(contents, 0), None);
+    }
+
+    #[test]
+    fn caret_under_column() {
+        assert_eq!(
+            format_source_line("Shape \"sphere\"", 12, 7),
+            "   |\n12 | Shape \"sphere\"\n   |       ^\n"
+        );
+        assert_eq!(
+            format_source_line("\tFoo", 3, 2),
+            "  |\n3 | \tFoo\n  | \t^\n"
+        );
+    }
 }
@@ -1,6 +1,80 @@
 use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
-pub fn read_file_contents(filename: &String) -> Vec<u8> {
-    // TODO: error_exit
-    fs::read(filename).unwrap()
+use flate2::read::MultiGzDecoder;
+
+use crate::Float;
+
+// Directories searched for relative filenames that can't be found next to
+// the file that refers to them.
+static SEARCH_DIRECTORIES: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+pub fn set_search_directories(directories: Vec<PathBuf>) {
+    *SEARCH_DIRECTORIES.write().unwrap() = directories;
+}
+
+/// Reads the whole file, or standard input if `filename` is "-". Gzip
+/// compressed contents are recognized by their magic number and decompressed.
+pub fn read_file_contents(filename: &str) -> io::Result<Vec<u8>> {
+    let contents = if filename == "-" {
+        let mut contents = Vec::new();
+        io::stdin().read_to_end(&mut contents).map(|_| contents)
+    } else {
+        fs::read(filename)
+    };
+    contents.and_then(decompress_if_gzipped)
+}
+
+fn decompress_if_gzipped(contents: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !contents.starts_with(&[0x1f, 0x8b]) {
+        return Ok(contents);
+    }
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(contents.as_slice()).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Resolves `filename` as it appears in `relative_to`: absolute paths are
+/// returned as is, relative ones are looked up next to `relative_to` first
+/// and then in each search directory.
+pub fn resolve_filename(filename: &str, relative_to: &str) -> String {
+    let path = Path::new(filename);
+    if filename.is_empty() || path.is_absolute() {
+        return filename.to_string();
+    }
+    let base = Path::new(relative_to).parent().map(Path::to_path_buf);
+    for dir in base.iter().chain(SEARCH_DIRECTORIES.read().unwrap().iter()) {
+        let candidate = dir.join(path);
+        if candidate.exists() {
+            return candidate.to_string_lossy().into_owned();
+        }
+    }
+    filename.to_string()
+}
+
+/// Reads the whitespace-separated numbers in a file, skipping `#` comments
+/// that run to the end of a line.
+pub fn read_float_file(filename: &str) -> Result<Vec<Float>, String> {
+    let contents = read_file_contents(filename).map_err(|e| e.to_string())?;
+    let contents = String::from_utf8_lossy(&contents);
+    let mut values = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        for word in line.split_whitespace() {
+            let v = word
+                .parse()
+                .map_err(|_| format!("line {}: \"{}\" is not a number", i + 1, word))?;
+            values.push(v);
+        }
+    }
+    Ok(values)
+}
+
+pub fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
 }
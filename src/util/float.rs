@@ -53,8 +53,8 @@ impl Num for Float {
     #[inline]
     fn difference_of_products(a: Self, b: Self, c: Self, d: Self) -> Self {
         let cd = c * d;
-        let sum_of_products = fma(a, b, cd);
-        let error = fma(c, d, -cd);
-        sum_of_products + error
+        let difference_of_products = fma(a, b, -cd);
+        let error = fma(-c, d, cd);
+        difference_of_products + error
     }
 }
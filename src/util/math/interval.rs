@@ -1,22 +1,138 @@
 use crate::Float;
 
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, Mul, Sub};
 
-struct Interval {
+use super::PI;
+
+/// A range of values that a quantity is known to lie within.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
     low: Float,
     high: Float,
 }
 
-impl Interval {}
+impl Interval {
+    pub fn new(low: Float, high: Float) -> Self {
+        Self {
+            low: low.min(high),
+            high: low.max(high),
+        }
+    }
+
+    pub fn from_value(v: Float) -> Self {
+        Self { low: v, high: v }
+    }
+
+    pub fn lower_bound(&self) -> Float {
+        self.low
+    }
+
+    pub fn upper_bound(&self) -> Float {
+        self.high
+    }
+
+    pub fn contains(&self, v: Float) -> bool {
+        v >= self.low && v <= self.high
+    }
+}
 
 impl Add<Interval> for Interval {
     type Output = Interval;
     fn add(self, rhs: Self) -> Self::Output {
         Interval {
-            low: self.low + rhs.low,
-            high: self.high + rhs.high,
+            low: (self.low + rhs.low).next_down(),
+            high: (self.high + rhs.high).next_up(),
+        }
+    }
+}
+
+impl Sub<Interval> for Interval {
+    type Output = Interval;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Interval {
+            low: (self.low - rhs.high).next_down(),
+            high: (self.high - rhs.low).next_up(),
+        }
+    }
+}
+
+impl Mul<Float> for Interval {
+    type Output = Interval;
+    fn mul(self, rhs: Float) -> Self::Output {
+        Interval::new((self.low * rhs).next_down(), (self.high * rhs).next_up())
+    }
+}
+
+impl Mul<Interval> for Interval {
+    type Output = Interval;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let products = [
+            self.low * rhs.low,
+            self.high * rhs.low,
+            self.low * rhs.high,
+            self.high * rhs.high,
+        ];
+        Interval {
+            low: products
+                .iter()
+                .copied()
+                .fold(Float::INFINITY, Float::min)
+                .next_down(),
+            high: products
+                .iter()
+                .copied()
+                .fold(Float::NEG_INFINITY, Float::max)
+                .next_up(),
         }
     }
 }
 
-mod test {}
+/// The range of sines of angles in `i`, which must lie in [0, 2pi].
+pub fn sin(i: Interval) -> Interval {
+    debug_assert!(i.low >= -1e-6 && i.high <= 2.0001 * PI);
+    let mut r = Interval::new(i.low.sin(), i.high.sin());
+    if i.contains(PI / 2.0) {
+        r.high = 1.0;
+    }
+    if i.contains(3.0 * PI / 2.0) {
+        r.low = -1.0;
+    }
+    Interval::new(r.low.next_down(), r.high.next_up())
+}
+
+/// The range of cosines of angles in `i`, which must lie in [0, 2pi].
+pub fn cos(i: Interval) -> Interval {
+    debug_assert!(i.low >= -1e-6 && i.high <= 2.0001 * PI);
+    let mut r = Interval::new(i.low.cos(), i.high.cos());
+    if i.contains(PI) {
+        r.low = -1.0;
+    }
+    Interval::new(r.low.next_down(), r.high.next_up())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arithmetic_bounds_every_combination() {
+        let a = Interval::new(-1.0, 2.0);
+        let b = Interval::new(3.0, -4.0);
+        let sum = a + b;
+        assert!(sum.contains(-5.0) && sum.contains(5.0));
+        let product = a * b;
+        assert!(product.contains(-8.0) && product.contains(6.0));
+        assert!(!product.contains(8.1));
+    }
+
+    #[test]
+    fn trigonometric_extrema() {
+        let s = sin(Interval::new(0.0, PI));
+        assert!(s.contains(1.0) && s.contains(0.0) && !s.contains(-0.01));
+        let c = cos(Interval::new(PI / 2.0, 3.0 * PI / 2.0));
+        assert!(c.contains(-1.0) && !c.contains(0.01));
+        let c = cos(Interval::new(0.1, 0.2));
+        assert!(c.contains(0.1f32.cos()) && c.contains(0.2f32.cos()));
+        assert!(!c.contains(1.0));
+    }
+}
@@ -2,6 +2,11 @@ use crate::Float;
 
 pub const PI: Float = std::f64::consts::PI as Float;
 
+#[inline]
+pub fn radians(deg: Float) -> Float {
+    (PI / 180.0) * deg
+}
+
 #[inline]
 pub fn lerp(x: Float, a: Float, b: Float) -> Float {
     (1.0 - x) * a + x * b
@@ -13,5 +18,5 @@ pub fn fma(x: Float, y: Float, z: Float) -> Float {
 }
 
 pub mod compensated_float;
-mod interval;
+pub mod interval;
 pub mod square_matrix;
@@ -2,7 +2,7 @@ use std::ops::{Index, IndexMut};
 
 use crate::Float;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SquareMatrix<const N: usize>(pub [[Float; N]; N]);
 
 impl<const N: usize> SquareMatrix<N> {
@@ -28,3 +28,73 @@ impl<const N: usize> IndexMut<usize> for SquareMatrix<N> {
         &mut self.0[index]
     }
 }
+
+impl<const N: usize> SquareMatrix<N> {
+    pub fn diag(v: [Float; N]) -> Self {
+        let mut m = Self([[0 as Float; N]; N]);
+        for (i, v) in v.into_iter().enumerate() {
+            m.0[i][i] = v;
+        }
+        m
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        let mut m = Self([[0 as Float; N]; N]);
+        for i in 0..N {
+            for j in 0..N {
+                m.0[i][j] = (0..N).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        m
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = Self([[0 as Float; N]; N]);
+        for i in 0..N {
+            for j in 0..N {
+                m.0[i][j] = self.0[j][i];
+            }
+        }
+        m
+    }
+
+    pub fn mul_vec(&self, v: [Float; N]) -> [Float; N] {
+        let mut result = [0 as Float; N];
+        for (i, r) in result.iter_mut().enumerate() {
+            *r = (0..N).map(|k| self.0[i][k] * v[k]).sum();
+        }
+        result
+    }
+}
+
+impl SquareMatrix<3> {
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.0;
+        let minor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let det =
+            m[0][0] * minor(1, 2, 1, 2) - m[0][1] * minor(1, 2, 0, 2) + m[0][2] * minor(1, 2, 0, 1);
+        if det == 0.0 {
+            return None;
+        }
+        let s = 1.0 / det;
+        Some(Self([
+            [
+                s * minor(1, 2, 1, 2),
+                -s * minor(0, 2, 1, 2),
+                s * minor(0, 1, 1, 2),
+            ],
+            [
+                -s * minor(1, 2, 0, 2),
+                s * minor(0, 2, 0, 2),
+                -s * minor(0, 1, 0, 2),
+            ],
+            [
+                s * minor(1, 2, 0, 1),
+                -s * minor(0, 2, 0, 1),
+                s * minor(0, 1, 0, 1),
+            ],
+        ]))
+    }
+}
@@ -0,0 +1,373 @@
+use std::str::{self, SplitAsciiWhitespace};
+
+use super::error::{warning, FileLoc};
+use super::file::read_file_contents;
+use super::vecmath::{Normal3f, Point2f, Point3f, Tuple2, Tuple3};
+use crate::Float;
+
+/// A mesh of triangles and quads as PLY files give them. Quads have their
+/// last two vertices swapped, which is the order bilinear patches use.
+#[derive(Debug, Default)]
+pub struct TriQuadMesh {
+    pub p: Vec<Point3f>,
+    pub n: Vec<Normal3f>,
+    pub uv: Vec<Point2f>,
+    pub face_indices: Vec<i32>,
+    pub tri_indices: Vec<i32>,
+    pub quad_indices: Vec<i32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(format!("\"{}\": unknown property type", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    value_type: ScalarType,
+    // The type of the count that precedes the values of a list.
+    count_type: Option<ScalarType>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// The data that follows the header, read one value at a time.
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, value_type: ScalarType) -> Result<f64, String> {
+        const TRUNCATED: &str = "file ends before all of its elements";
+        match self {
+            Body::Ascii(words) => {
+                let word = words.next().ok_or(TRUNCATED)?;
+                word.parse()
+                    .map_err(|_| format!("\"{}\": expected a number", word))
+            }
+            Body::Binary { data, big_endian } => {
+                let size = value_type.size();
+                if data.len() < size {
+                    return Err(TRUNCATED.to_string());
+                }
+                let mut bytes = [0; 8];
+                bytes[..size].copy_from_slice(&data[..size]);
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+                *data = &data[size..];
+                let b2 = [bytes[0], bytes[1]];
+                let b4 = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                Ok(match value_type {
+                    ScalarType::I8 => bytes[0] as i8 as f64,
+                    ScalarType::U8 => bytes[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes(b2) as f64,
+                    ScalarType::U16 => u16::from_le_bytes(b2) as f64,
+                    ScalarType::I32 => i32::from_le_bytes(b4) as f64,
+                    ScalarType::U32 => u32::from_le_bytes(b4) as f64,
+                    ScalarType::F32 => f32::from_le_bytes(b4) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(bytes),
+                })
+            }
+        }
+    }
+}
+
+impl TriQuadMesh {
+    /// Reads the vertices and faces of a PLY file, in either its text or
+    /// binary form. Faces that are neither triangles nor quads are skipped
+    /// with a warning.
+    pub fn read_ply(filename: &str) -> Result<Self, String> {
+        let contents = read_file_contents(filename).map_err(|e| e.to_string())?;
+        let header_end = contents
+            .windows(b"end_header".len())
+            .position(|w| w == b"end_header")
+            .ok_or("not a PLY file")?;
+        let header = str::from_utf8(&contents[..header_end]).map_err(|e| e.to_string())?;
+        let (format, elements) = parse_header(header)?;
+        // The data starts after the end of the end_header line.
+        let body = &contents[header_end..];
+        let body = match body.iter().position(|&c| c == b'\n') {
+            Some(i) => &body[i + 1..],
+            None => &[],
+        };
+        let mut body = match format {
+            Format::Ascii => Body::Ascii(
+                str::from_utf8(body)
+                    .map_err(|e| e.to_string())?
+                    .split_ascii_whitespace(),
+            ),
+            _ => Body::Binary {
+                data: body,
+                big_endian: format == Format::BinaryBigEndian,
+            },
+        };
+
+        let mut mesh = TriQuadMesh::default();
+        let mut skipped_faces = 0;
+        for element in &elements {
+            for _ in 0..element.count {
+                let mut values = Vec::with_capacity(element.properties.len());
+                for property in &element.properties {
+                    let mut list = Vec::new();
+                    match property.count_type {
+                        Some(count_type) => {
+                            let count = body.read(count_type)? as usize;
+                            for _ in 0..count {
+                                list.push(body.read(property.value_type)?);
+                            }
+                        }
+                        None => list.push(body.read(property.value_type)?),
+                    }
+                    values.push((property.name.as_str(), list));
+                }
+                match element.name.as_str() {
+                    "vertex" => mesh.add_vertex(&values),
+                    "face" if !mesh.add_face(&values) => skipped_faces += 1,
+                    _ => {}
+                }
+            }
+        }
+        if skipped_faces > 0 {
+            warning(
+                &FileLoc::new(filename.to_string()),
+                &format!(
+                    "ignoring {} faces that are neither triangles nor quads",
+                    skipped_faces
+                ),
+            );
+        }
+
+        let n_vertices = mesh.p.len();
+        if let Some(&i) = (mesh.tri_indices.iter())
+            .chain(mesh.quad_indices.iter())
+            .find(|&&i| i < 0 || i as usize >= n_vertices)
+        {
+            return Err(format!(
+                "vertex index {} is out of bounds; the file has {} vertices",
+                i, n_vertices
+            ));
+        }
+        Ok(mesh)
+    }
+
+    fn add_vertex(&mut self, values: &[(&str, Vec<f64>)]) {
+        let get = |names: &[&str]| {
+            values
+                .iter()
+                .find(|(name, _)| names.contains(name))
+                .and_then(|(_, v)| v.first())
+                .map(|&v| v as Float)
+        };
+        let p = [get(&["x"]), get(&["y"]), get(&["z"])];
+        self.p.push(Point3f::new(
+            p[0].unwrap_or(0.0),
+            p[1].unwrap_or(0.0),
+            p[2].unwrap_or(0.0),
+        ));
+        if let [Some(x), Some(y), Some(z)] = [get(&["nx"]), get(&["ny"]), get(&["nz"])] {
+            self.n.push(Normal3f::new(x, y, z));
+        }
+        let u = get(&["u", "s", "texture_u", "texture_s"]);
+        let v = get(&["v", "t", "texture_v", "texture_t"]);
+        if let (Some(u), Some(v)) = (u, v) {
+            self.uv.push(Point2f::new(u, v));
+        }
+    }
+
+    // Returns whether the face was a triangle or a quad.
+    fn add_face(&mut self, values: &[(&str, Vec<f64>)]) -> bool {
+        let Some((_, indices)) = values
+            .iter()
+            .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+        else {
+            return false;
+        };
+        let indices: Vec<i32> = indices.iter().map(|&i| i as i32).collect();
+        match indices[..] {
+            [v0, v1, v2] => self.tri_indices.extend([v0, v1, v2]),
+            [v0, v1, v2, v3] => self.quad_indices.extend([v0, v1, v3, v2]),
+            _ => return false,
+        }
+        if let Some((_, face)) = values.iter().find(|(name, _)| *name == "face_indices") {
+            self.face_indices.extend(face.first().map(|&f| f as i32));
+        }
+        true
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        match words[..] {
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("\"{}\": unknown format", name)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("\"{}\": bad element count", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, value_type, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    value_type: ScalarType::parse(value_type)?,
+                    count_type: Some(ScalarType::parse(count_type)?),
+                });
+            }
+            ["property", value_type, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    value_type: ScalarType::parse(value_type)?,
+                    count_type: None,
+                });
+            }
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(format!("\"{}\": unexpected line in header", line)),
+        }
+    }
+    Ok((format.ok_or("header has no format")?, elements))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_temp(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("rpbrt-mesh-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn ascii_triangles_and_quads() {
+        let filename = write_temp(
+            "ascii.ply",
+            b"ply\nformat ascii 1.0\ncomment made by hand\nelement vertex 5\n\
+              property float x\nproperty float y\nproperty float z\n\
+              property float s\nproperty float t\n\
+              element face 2\nproperty list uchar int vertex_indices\nend_header\n\
+              0 0 0 0 0\n1 0 0 1 0\n0 1 0 0 1\n1 1 0 1 1\n2 2 2 0 0\n\
+              3 0 1 2\n4 0 1 2 3\n",
+        );
+        let mesh = TriQuadMesh::read_ply(&filename).unwrap();
+        assert_eq!(mesh.p.len(), 5);
+        assert_eq!(mesh.p[4], Point3f::new(2.0, 2.0, 2.0));
+        assert_eq!(mesh.uv.len(), 5);
+        assert!(mesh.n.is_empty());
+        assert_eq!(mesh.tri_indices, [0, 1, 2]);
+        assert_eq!(mesh.quad_indices, [0, 1, 3, 2]);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn binary_with_other_elements() {
+        let mut contents = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+              property double x\nproperty double y\nproperty double z\n\
+              property float nx\nproperty float ny\nproperty float nz\n\
+              element material 1\nproperty uchar red\n\
+              element face 1\nproperty list uchar uint vertex_indices\n\
+              property int face_indices\nend_header\n"
+            .to_vec();
+        for i in 0..3 {
+            for v in [i as f64, 0.5, -1.0] {
+                contents.extend(v.to_be_bytes());
+            }
+            for v in [0.0f32, 0.0, 1.0] {
+                contents.extend(v.to_be_bytes());
+            }
+        }
+        contents.push(255);
+        contents.push(3);
+        for i in [2u32, 1, 0] {
+            contents.extend(i.to_be_bytes());
+        }
+        contents.extend(7i32.to_be_bytes());
+        let filename = write_temp("binary.ply", &contents);
+        let mesh = TriQuadMesh::read_ply(&filename).unwrap();
+        assert_eq!(mesh.p[2], Point3f::new(2.0, 0.5, -1.0));
+        assert_eq!(mesh.n[1], Normal3f::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.tri_indices, [2, 1, 0]);
+        assert_eq!(mesh.face_indices, [7]);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn bad_files_are_reported() {
+        let filename = write_temp(
+            "bad.ply",
+            b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+              element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+              0\n3 0 1 2\n",
+        );
+        let e = TriQuadMesh::read_ply(&filename).unwrap_err();
+        assert!(e.contains("out of bounds"), "{}", e);
+        std::fs::write(
+            &filename,
+            b"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nend_header\n0\n",
+        )
+        .unwrap();
+        let e = TriQuadMesh::read_ply(&filename).unwrap_err();
+        assert!(e.contains("ends before"), "{}", e);
+        std::fs::remove_file(filename).unwrap();
+    }
+}
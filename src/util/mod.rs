@@ -1,11 +1,16 @@
+pub mod color;
 pub mod colorspace;
 pub mod containers;
 pub mod error;
 pub mod file;
 pub mod float;
-mod math;
+pub mod math;
+pub mod mesh;
+pub mod parallel;
 pub mod print;
+pub mod quaternion;
 pub mod spectrum;
+pub mod stats;
 pub mod string;
 pub mod transform;
 pub mod vecmath;
@@ -0,0 +1,70 @@
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::pbrt::options;
+
+/// The number of threads to run work on: the nthreads option, or one per
+/// core if it is zero.
+pub fn max_concurrency() -> usize {
+    match options().n_threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Calls `func` on each of `items` using up to `n_threads` threads, and
+/// returns the results in the order of the items. Each thread takes the
+/// next item that hasn't been started whenever it finishes one, so which
+/// thread handles which item varies from run to run, but the results
+/// don't.
+pub fn parallel_map<T, R, F>(n_threads: usize, items: &[T], func: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let n_threads = n_threads.clamp(1, items.len().max(1));
+    if n_threads == 1 {
+        return items.iter().map(func).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..n_threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return results;
+                        };
+                        results.push((i, func(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+    results.sort_unstable_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn results_follow_the_items() {
+        let items: Vec<u64> = (0..1000).collect();
+        let square = |&i: &u64| i * i;
+        let expected: Vec<u64> = items.iter().map(square).collect();
+        for n_threads in [0, 1, 3, 16, 2000] {
+            assert_eq!(parallel_map(n_threads, &items, square), expected);
+        }
+        assert!(parallel_map(4, &[] as &[u64], square).is_empty());
+    }
+}
@@ -2,3 +2,8 @@
 pub fn red(s: &str) -> String {
     "\x1b[1m\x1b[31m".to_owned() + s + "\x1b[0m"
 }
+
+#[inline]
+pub fn yellow(s: &str) -> String {
+    "\x1b[1m\x1b[33m".to_owned() + s + "\x1b[0m"
+}
@@ -0,0 +1,215 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use super::{
+    math::square_matrix::SquareMatrix,
+    transform::Transform,
+    vecmath::{
+        vector::{cross, dot},
+        Tuple3, Vector3f,
+    },
+};
+use crate::Float;
+
+/// A quaternion `w + v`, used for the rotation part of animated transforms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub v: Vector3f,
+    pub w: Float,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self {
+            v: Vector3f::new(0.0, 0.0, 0.0),
+            w: 1.0,
+        }
+    }
+}
+
+impl Quaternion {
+    pub fn new(v: Vector3f, w: Float) -> Self {
+        Self { v, w }
+    }
+
+    pub fn dot(&self, q: &Quaternion) -> Float {
+        dot(self.v, q.v) + self.w * q.w
+    }
+
+    pub fn length(&self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Self {
+        *self / self.length()
+    }
+
+    pub fn conj(&self) -> Self {
+        Self::new(-1.0 * self.v, self.w)
+    }
+
+    /// The rotation part of `t`, which must not contain any scale.
+    pub fn from_transform(t: &Transform) -> Self {
+        let m = t.matrix();
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt();
+            let w = s / 2.0;
+            let s = 0.5 / s;
+            let v = Vector3f::new(
+                (m[2][1] - m[1][2]) * s,
+                (m[0][2] - m[2][0]) * s,
+                (m[1][0] - m[0][1]) * s,
+            );
+            Self::new(v, w)
+        } else {
+            // Work from the largest diagonal entry to keep the square root
+            // away from zero.
+            const NEXT: [usize; 3] = [1, 2, 0];
+            let mut i = 0;
+            if m[1][1] > m[0][0] {
+                i = 1;
+            }
+            if m[2][2] > m[i][i] {
+                i = 2;
+            }
+            let j = NEXT[i];
+            let k = NEXT[j];
+            let mut s = ((m[i][i] - (m[j][j] + m[k][k])) + 1.0).sqrt();
+            let mut q = [0.0; 3];
+            q[i] = s * 0.5;
+            if s != 0.0 {
+                s = 0.5 / s;
+            }
+            let w = (m[k][j] - m[j][k]) * s;
+            q[j] = (m[j][i] + m[i][j]) * s;
+            q[k] = (m[k][i] + m[i][k]) * s;
+            Self::new(Vector3f::new(q[0], q[1], q[2]), w)
+        }
+    }
+
+    pub fn to_transform(self) -> Transform {
+        let (x, y, z, w) = (self.v.x(), self.v.y(), self.v.z(), self.w);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (x * w, y * w, z * w);
+        let m = SquareMatrix([
+            [1.0 - 2.0 * (yy + zz), 2.0 * (xy - wz), 2.0 * (xz + wy), 0.0],
+            [2.0 * (xy + wz), 1.0 - 2.0 * (xx + zz), 2.0 * (yz - wx), 0.0],
+            [2.0 * (xz - wy), 2.0 * (yz + wx), 1.0 - 2.0 * (xx + yy), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // Rotations are orthogonal, so the inverse is the transpose.
+        Transform::from_matrices(m, m.transpose())
+    }
+}
+
+/// Spherical linear interpolation from `q1` at `t = 0` to `q2` at `t = 1`.
+pub fn slerp(t: Float, q1: &Quaternion, q2: &Quaternion) -> Quaternion {
+    let cos_theta = q1.dot(q2);
+    if cos_theta > 0.9995 {
+        // Nearly parallel, where the perpendicular below is ill-defined.
+        return (*q1 * (1.0 - t) + *q2 * t).normalize();
+    }
+    let theta = cos_theta.clamp(-1.0, 1.0).acos();
+    let q_perp = (*q2 - *q1 * cos_theta).normalize();
+    *q1 * (theta * t).cos() + q_perp * (theta * t).sin()
+}
+
+impl Add for Quaternion {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.v + rhs.v, self.w + rhs.w)
+    }
+}
+
+impl Sub for Quaternion {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.v - rhs.v, self.w - rhs.w)
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-1.0 * self.v, -self.w)
+    }
+}
+
+impl Mul<Float> for Quaternion {
+    type Output = Self;
+    fn mul(self, rhs: Float) -> Self {
+        Self::new(self.v * rhs, self.w * rhs)
+    }
+}
+
+impl Div<Float> for Quaternion {
+    type Output = Self;
+    fn div(self, rhs: Float) -> Self {
+        Self::new(self.v / rhs, self.w / rhs)
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+
+    /// The Hamilton product: the rotation `rhs` followed by `self`.
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.w * rhs.v + rhs.w * self.v + cross(self.v, rhs.v),
+            self.w * rhs.w - dot(self.v, rhs.v),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::transform::rotate;
+    use crate::util::vecmath::Point3f;
+
+    fn assert_point_close(a: Point3f, b: Point3f) {
+        assert!(
+            (a.x() - b.x()).abs() < 1e-4
+                && (a.y() - b.y()).abs() < 1e-4
+                && (a.z() - b.z()).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn transform_round_trip() {
+        let p = Point3f::new(0.3, -1.0, 2.0);
+        for (theta, axis) in [
+            (30.0, Vector3f::new(0.0, 0.0, 1.0)),
+            (170.0, Vector3f::new(1.0, 2.0, -0.5)),
+            (-90.0, Vector3f::new(0.0, 1.0, 0.0)),
+        ] {
+            let r = rotate(theta, axis);
+            let q = Quaternion::from_transform(&r);
+            assert!((q.length() - 1.0).abs() < 1e-5);
+            assert_point_close(q.to_transform().apply_point(p), r.apply_point(p));
+        }
+    }
+
+    #[test]
+    fn product_composes_rotations() {
+        let a = rotate(40.0, Vector3f::new(1.0, 0.0, 0.0));
+        let b = rotate(75.0, Vector3f::new(0.0, 1.0, 1.0));
+        let q = Quaternion::from_transform(&a) * Quaternion::from_transform(&b);
+        let p = Point3f::new(1.0, 2.0, 3.0);
+        assert_point_close(q.to_transform().apply_point(p), (&a * &b).apply_point(p));
+    }
+
+    #[test]
+    fn slerp_halfway() {
+        let q0 = Quaternion::default();
+        let q1 = Quaternion::from_transform(&rotate(90.0, Vector3f::new(0.0, 0.0, 1.0)));
+        let half = slerp(0.5, &q0, &q1).to_transform();
+        let p = half.apply_point(Point3f::new(1.0, 0.0, 0.0));
+        let s = (0.5 as Float).sqrt();
+        assert_point_close(p, Point3f::new(s, s, 0.0));
+    }
+}
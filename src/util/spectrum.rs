@@ -1 +1,576 @@
-pub trait Spectrum {}
\ No newline at end of file
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+
+use super::color::{RGBSigmoidPolynomial, RGB, XYZ};
+use super::colorspace::RGBColorSpace;
+use crate::Float;
+
+pub const LAMBDA_MIN: Float = 360.0;
+pub const LAMBDA_MAX: Float = 830.0;
+
+/// A spectral distribution: a value for each visible wavelength, given in
+/// nanometers.
+pub trait Spectrum: Send + Sync {
+    fn evaluate(&self, lambda: Float) -> Float;
+    fn max_value(&self) -> Float;
+
+    /// For emission given as a color, the illuminant the color is relative
+    /// to.
+    fn illuminant(&self) -> Option<&dyn Spectrum> {
+        None
+    }
+}
+
+pub struct ConstantSpectrum {
+    c: Float,
+}
+
+impl ConstantSpectrum {
+    pub fn new(c: Float) -> Self {
+        Self { c }
+    }
+}
+
+impl Spectrum for ConstantSpectrum {
+    fn evaluate(&self, _lambda: Float) -> Float {
+        self.c
+    }
+    fn max_value(&self) -> Float {
+        self.c
+    }
+}
+
+/// Linear interpolation between sampled wavelengths, zero outside them.
+pub struct PiecewiseLinearSpectrum {
+    lambdas: Vec<Float>,
+    values: Vec<Float>,
+}
+
+impl PiecewiseLinearSpectrum {
+    /// `lambdas` must be increasing and as long as `values`.
+    pub fn new(lambdas: Vec<Float>, values: Vec<Float>) -> Self {
+        debug_assert_eq!(lambdas.len(), values.len());
+        debug_assert!(lambdas.windows(2).all(|w| w[0] < w[1]));
+        Self { lambdas, values }
+    }
+
+    /// Builds the spectrum from (wavelength, value) pairs. When `normalize`
+    /// is set, it is scaled to have a luminance (CIE Y) of one.
+    pub fn from_interleaved(data: &[Float], normalize: bool) -> Self {
+        debug_assert_eq!(data.len() % 2, 0);
+        let (lambdas, values) = data.chunks(2).map(|p| (p[0], p[1])).unzip();
+        let mut spectrum = Self::new(lambdas, values);
+        if normalize {
+            let scale = CIE_Y_INTEGRAL / inner_product(&spectrum, &*CIE_Y);
+            spectrum.values.iter_mut().for_each(|v| *v *= scale);
+        }
+        spectrum
+    }
+}
+
+impl Spectrum for PiecewiseLinearSpectrum {
+    fn evaluate(&self, lambda: Float) -> Float {
+        let (first, last) = match (self.lambdas.first(), self.lambdas.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return 0.0,
+        };
+        if lambda < first || lambda > last {
+            return 0.0;
+        }
+        // The last sample at or before lambda.
+        let o = self
+            .lambdas
+            .partition_point(|&l| l <= lambda)
+            .clamp(1, self.lambdas.len() - 1)
+            - 1;
+        if o + 1 == self.lambdas.len() {
+            return self.values[o];
+        }
+        let t = (lambda - self.lambdas[o]) / (self.lambdas[o + 1] - self.lambdas[o]);
+        (1.0 - t) * self.values[o] + t * self.values[o + 1]
+    }
+    fn max_value(&self) -> Float {
+        self.values.iter().copied().fold(0.0, Float::max)
+    }
+}
+
+/// Emission of a blackbody at temperature `t` kelvin, scaled so that its
+/// peak is one.
+pub struct BlackbodySpectrum {
+    t: Float,
+    normalization_factor: Float,
+}
+
+impl BlackbodySpectrum {
+    pub fn new(t: Float) -> Self {
+        // Wien's displacement law gives the wavelength of the peak.
+        let lambda_max = 2.8977721e-3 / t as f64;
+        let normalization_factor = 1.0 / blackbody(lambda_max * 1e9, t as f64) as Float;
+        Self {
+            t,
+            normalization_factor,
+        }
+    }
+}
+
+impl Spectrum for BlackbodySpectrum {
+    fn evaluate(&self, lambda: Float) -> Float {
+        blackbody(lambda as f64, self.t as f64) as Float * self.normalization_factor
+    }
+    fn max_value(&self) -> Float {
+        1.0
+    }
+}
+
+/// Planck's law: emitted radiance at `lambda` nanometers for temperature `t`.
+pub fn blackbody(lambda: f64, t: f64) -> f64 {
+    if t <= 0.0 {
+        return 0.0;
+    }
+    const C: f64 = 299792458.0;
+    const H: f64 = 6.62606957e-34;
+    const KB: f64 = 1.3806488e-23;
+    let l = lambda * 1e-9;
+    (2.0 * H * C * C) / (l.powi(5) * (((H * C) / (l * KB * t)).exp() - 1.0))
+}
+
+/// A reflectance given as RGB, so at most one at every wavelength.
+pub struct RGBAlbedoSpectrum {
+    rsp: RGBSigmoidPolynomial,
+}
+
+impl RGBAlbedoSpectrum {
+    pub fn new(cs: &RGBColorSpace, rgb: RGB) -> Self {
+        debug_assert!(rgb.max_component() <= 1.0);
+        debug_assert!(rgb.min_component() >= 0.0);
+        Self {
+            rsp: cs.to_rgb_coeffs(rgb),
+        }
+    }
+}
+
+impl Spectrum for RGBAlbedoSpectrum {
+    fn evaluate(&self, lambda: Float) -> Float {
+        self.rsp.evaluate(lambda)
+    }
+    fn max_value(&self) -> Float {
+        self.rsp.max_value()
+    }
+}
+
+/// An RGB value with no upper bound, such as a scattering coefficient. The
+/// color is scaled into the range a sigmoid polynomial can represent.
+pub struct RGBUnboundedSpectrum {
+    scale: Float,
+    rsp: RGBSigmoidPolynomial,
+}
+
+impl RGBUnboundedSpectrum {
+    pub fn new(cs: &RGBColorSpace, rgb: RGB) -> Self {
+        let m = rgb.max_component();
+        let scale = 2.0 * m;
+        let rsp = cs.to_rgb_coeffs(if scale > 0.0 {
+            rgb / scale
+        } else {
+            RGB::new(0.0, 0.0, 0.0)
+        });
+        Self { scale, rsp }
+    }
+}
+
+impl Spectrum for RGBUnboundedSpectrum {
+    fn evaluate(&self, lambda: Float) -> Float {
+        self.scale * self.rsp.evaluate(lambda)
+    }
+    fn max_value(&self) -> Float {
+        self.scale * self.rsp.max_value()
+    }
+}
+
+/// Emission given as RGB: an unbounded spectrum multiplied by the color
+/// space's illuminant, so that RGB white emits the color space's white.
+pub struct RGBIlluminantSpectrum {
+    scale: Float,
+    rsp: RGBSigmoidPolynomial,
+    illuminant: Arc<dyn Spectrum>,
+}
+
+impl RGBIlluminantSpectrum {
+    pub fn new(cs: &RGBColorSpace, rgb: RGB) -> Self {
+        let unbounded = RGBUnboundedSpectrum::new(cs, rgb);
+        Self {
+            scale: unbounded.scale,
+            rsp: unbounded.rsp,
+            illuminant: cs.illuminant.clone(),
+        }
+    }
+}
+
+impl Spectrum for RGBIlluminantSpectrum {
+    fn evaluate(&self, lambda: Float) -> Float {
+        self.scale * self.rsp.evaluate(lambda) * self.illuminant.evaluate(lambda)
+    }
+    fn max_value(&self) -> Float {
+        self.scale * self.rsp.max_value() * self.illuminant.max_value()
+    }
+    fn illuminant(&self) -> Option<&dyn Spectrum> {
+        Some(&*self.illuminant)
+    }
+}
+
+// The CIE 1931 color matching functions, using the multi-lobe Gaussian fit
+// of Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE
+// XYZ Color Matching Functions" (JCGT 2013).
+fn piecewise_gaussian(lambda: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let sigma = if lambda < mu { sigma1 } else { sigma2 };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+pub fn cie_x(lambda: f64) -> f64 {
+    1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2)
+}
+
+pub fn cie_y(lambda: f64) -> f64 {
+    0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1)
+}
+
+pub fn cie_z(lambda: f64) -> f64 {
+    1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8)
+}
+
+struct CieMatchingFunction(fn(f64) -> f64);
+
+impl Spectrum for CieMatchingFunction {
+    fn evaluate(&self, lambda: Float) -> Float {
+        (self.0)(lambda as f64) as Float
+    }
+    fn max_value(&self) -> Float {
+        (LAMBDA_MIN as i32..=LAMBDA_MAX as i32)
+            .map(|l| self.evaluate(l as Float))
+            .fold(0.0, Float::max)
+    }
+}
+
+static CIE_Y: Lazy<CieMatchingFunction> = Lazy::new(|| CieMatchingFunction(cie_y));
+
+/// The integral of the Y matching function, as `inner_product` computes it.
+/// The analytic fit comes out slightly above the tabulated 106.856895.
+pub const CIE_Y_INTEGRAL: Float = 106.92209;
+
+/// Sums the product of two spectra at each nanometer of the visible range.
+pub fn inner_product(a: &dyn Spectrum, b: &dyn Spectrum) -> Float {
+    (LAMBDA_MIN as i32..=LAMBDA_MAX as i32)
+        .map(|l| a.evaluate(l as Float) as f64 * b.evaluate(l as Float) as f64)
+        .sum::<f64>() as Float
+}
+
+/// The luminance of an emission spectrum, which lights divide their scale
+/// by so that it is given in photometric units. For a color, that of the
+/// illuminant it's relative to, so that the color itself still counts.
+pub fn spectrum_to_photometric(s: &dyn Spectrum) -> Float {
+    inner_product(&*CIE_Y, s.illuminant().unwrap_or(s))
+}
+
+/// The XYZ color of a spectrum, normalized so that a spectrum with the
+/// luminance of the Y matching function has Y of one.
+pub fn spectrum_to_xyz(s: &dyn Spectrum) -> XYZ {
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for l in LAMBDA_MIN as i32..=LAMBDA_MAX as i32 {
+        let v = s.evaluate(l as Float) as f64;
+        x += v * cie_x(l as f64);
+        y += v * cie_y(l as f64);
+        z += v * cie_z(l as f64);
+    }
+    let scale = 1.0 / CIE_Y_INTEGRAL as f64;
+    XYZ::new(
+        (x * scale) as Float,
+        (y * scale) as Float,
+        (z * scale) as Float,
+    )
+}
+
+/// Returns the built-in spectrum called `name`, such as "stdillum-D65" or
+/// "metal-Au-eta".
+pub fn get_named_spectrum(name: &str) -> Option<Arc<dyn Spectrum>> {
+    NAMED_SPECTRA.get(name).cloned()
+}
+
+static NAMED_SPECTRA: Lazy<HashMap<&'static str, Arc<dyn Spectrum>>> = Lazy::new(|| {
+    let mut spectra: HashMap<&'static str, Arc<dyn Spectrum>> = HashMap::new();
+    spectra.insert(
+        "stdillum-D65",
+        Arc::new(PiecewiseLinearSpectrum::from_interleaved(
+            &interleave(300.0, 5.0, &CIE_ILLUM_D65),
+            true,
+        )),
+    );
+    // Illuminant A is defined as a blackbody at 2856K.
+    let a: Vec<Float> = (300..=830)
+        .step_by(5)
+        .map(|l| blackbody(l as f64, 2856.0) as Float)
+        .collect();
+    spectra.insert(
+        "stdillum-A",
+        Arc::new(PiecewiseLinearSpectrum::from_interleaved(
+            &interleave(300.0, 5.0, &a),
+            true,
+        )),
+    );
+    let metals: [(&'static str, &'static str, &[Float]); 3] = [
+        ("metal-Ag-eta", "metal-Ag-k", &AG_ETA_K),
+        ("metal-Au-eta", "metal-Au-k", &AU_ETA_K),
+        ("metal-Cu-eta", "metal-Cu-k", &CU_ETA_K),
+    ];
+    for (eta_name, k_name, data) in metals {
+        let mut eta = Vec::new();
+        let mut k = Vec::new();
+        // Stored as (wavelength, eta, k), with decreasing wavelengths.
+        for v in data.chunks(3).rev() {
+            eta.extend([v[0], v[1]]);
+            k.extend([v[0], v[2]]);
+        }
+        spectra.insert(
+            eta_name,
+            Arc::new(PiecewiseLinearSpectrum::from_interleaved(&eta, false)),
+        );
+        spectra.insert(
+            k_name,
+            Arc::new(PiecewiseLinearSpectrum::from_interleaved(&k, false)),
+        );
+    }
+    spectra.insert("glass-BK7", Arc::new(sellmeier(&BK7_SELLMEIER)));
+    spectra.insert(
+        "glass-fused-silica",
+        Arc::new(sellmeier(&FUSED_SILICA_SELLMEIER)),
+    );
+    spectra
+});
+
+fn interleave(lambda_start: Float, lambda_step: Float, values: &[Float]) -> Vec<Float> {
+    values
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &v)| [lambda_start + i as Float * lambda_step, v])
+        .collect()
+}
+
+// The index of refraction of a glass from its Sellmeier coefficients
+// (B1, B2, B3, C1, C2, C3), with C in square micrometers.
+fn sellmeier(c: &[f64; 6]) -> PiecewiseLinearSpectrum {
+    let data: Vec<Float> = (LAMBDA_MIN as i32..=LAMBDA_MAX as i32)
+        .step_by(5)
+        .flat_map(|l| {
+            let l2 = (l as f64 * 1e-3).powi(2);
+            let n2 = 1.0 + (0..3).map(|i| c[i] * l2 / (l2 - c[i + 3])).sum::<f64>();
+            [l as Float, n2.sqrt() as Float]
+        })
+        .collect();
+    PiecewiseLinearSpectrum::from_interleaved(&data, false)
+}
+
+const BK7_SELLMEIER: [f64; 6] = [
+    1.03961212,
+    0.231792344,
+    1.01046945,
+    0.00600069867,
+    0.0200179144,
+    103.560653,
+];
+
+const FUSED_SILICA_SELLMEIER: [f64; 6] = [
+    0.6961663,
+    0.4079426,
+    0.8974794,
+    0.0684043 * 0.0684043,
+    0.1162414 * 0.1162414,
+    9.896161 * 9.896161,
+];
+
+// CIE standard illuminant D65, from 300nm to 830nm in 5nm steps.
+#[rustfmt::skip]
+const CIE_ILLUM_D65: [Float; 107] = [
+    0.0341, 1.6643, 3.2945, 11.7652, 20.236, 28.6447, 37.0535, 38.5011,
+    39.9488, 42.4302, 44.9117, 45.775, 46.6383, 49.3637, 52.0891, 51.0323,
+    49.9755, 52.3118, 54.6482, 68.7015, 82.7549, 87.1204, 91.486, 92.4589,
+    93.4318, 90.057, 86.6823, 95.7736, 104.865, 110.936, 117.008, 117.41,
+    117.812, 116.336, 114.861, 115.392, 115.923, 112.367, 108.811, 109.082,
+    109.354, 108.578, 107.802, 106.296, 104.79, 106.239, 107.689, 106.047,
+    104.405, 104.225, 104.046, 102.023, 100.0, 98.1671, 96.3342, 96.0611,
+    95.788, 92.2368, 88.6856, 89.3459, 90.0062, 89.8026, 89.5991, 88.6489,
+    87.6987, 85.4936, 83.2886, 83.4939, 83.6992, 81.863, 80.0268, 80.1207,
+    80.2146, 81.2462, 82.2778, 80.281, 78.2842, 74.0027, 69.7213, 70.6652,
+    71.6091, 72.979, 74.349, 67.9765, 61.604, 65.7448, 69.8856, 72.4863,
+    75.087, 69.3398, 63.5927, 55.0054, 46.4182, 56.6118, 66.8054, 65.0941,
+    63.3828, 63.8434, 64.304, 61.8779, 59.4519, 55.7054, 51.959, 54.6998,
+    57.4406, 58.8765, 60.3125,
+];
+
+// Complex indices of refraction of metals as (wavelength, eta, k). Silver
+// and gold are the measurements of Johnson and Christy, "Optical Constants
+// of the Noble Metals" (1972), at the photon energies they were taken at.
+#[rustfmt::skip]
+const AG_ETA_K: [Float; 54] = [
+    891.98, 0.04, 6.492,
+    821.09, 0.03, 5.727,
+    755.99, 0.03, 5.242,
+    704.45, 0.04, 4.838,
+    659.49, 0.05, 4.483,
+    616.84, 0.06, 4.152,
+    582.09, 0.05, 3.858,
+    548.60, 0.06, 3.586,
+    520.94, 0.05, 3.324,
+    495.94, 0.05, 3.093,
+    471.42, 0.05, 2.869,
+    450.85, 0.04, 2.657,
+    430.50, 0.04, 2.462,
+    413.28, 0.05, 2.275,
+    397.38, 0.05, 2.070,
+    381.49, 0.05, 1.864,
+    367.91, 0.07, 1.657,
+    354.24, 0.10, 1.419,
+];
+
+#[rustfmt::skip]
+const AU_ETA_K: [Float; 54] = [
+    891.98, 0.17, 5.663,
+    821.09, 0.16, 5.083,
+    755.99, 0.14, 4.542,
+    704.45, 0.13, 4.103,
+    659.49, 0.14, 3.697,
+    616.84, 0.21, 3.272,
+    582.09, 0.29, 2.863,
+    548.60, 0.43, 2.455,
+    520.94, 0.62, 2.081,
+    495.94, 1.04, 1.833,
+    471.42, 1.31, 1.849,
+    450.85, 1.38, 1.914,
+    430.50, 1.45, 1.948,
+    413.28, 1.46, 1.958,
+    397.38, 1.47, 1.952,
+    381.49, 1.46, 1.933,
+    367.91, 1.48, 1.895,
+    354.24, 1.50, 1.866,
+];
+
+// The copper data pbrt-v3 used for its default metal.
+#[rustfmt::skip]
+#[allow(clippy::excessive_precision)]
+const CU_ETA_K: [Float; 168] = [
+    885.6012089, 0.3, 5.717,
+    855.0632391, 0.28, 5.485625,
+    826.5611045, 0.26, 5.26,
+    799.8979061, 0.254188, 5.034125,
+    774.9011523, 0.25, 4.817,
+    751.4192053, 0.2365, 4.619563,
+    729.3186941, 0.223, 4.43,
+    708.4810171, 0.21625, 4.239563,
+    688.8009889, 0.213, 4.05,
+    670.1847459, 0.20925, 3.863125,
+    652.5483053, 0.214, 3.67,
+    635.8162974, 0.230813, 3.458187,
+    619.92089, 0.272, 3.24,
+    604.8008683, 0.35125, 3.01075,
+    590.4008476, 0.468, 2.809,
+    576.6705953, 0.645875, 2.678062,
+    563.5644455, 0.826, 2.599,
+    551.0407911, 0.950375, 2.5765,
+    539.0616435, 1.04, 2.583,
+    527.5922468, 1.092437, 2.595562,
+    516.6007417, 1.12, 2.605,
+    506.0578694, 1.131562, 2.589625,
+    495.936712, 1.135, 2.564,
+    486.2124627, 1.142812, 2.535875,
+    476.8622231, 1.15, 2.504,
+    467.8648226, 1.155312, 2.469187,
+    459.2006593, 1.16, 2.433,
+    450.8515564, 1.165312, 2.397625,
+    442.8006357, 1.17, 2.362,
+    435.0322035, 1.172812, 2.326,
+    427.5316483, 1.175, 2.289,
+    420.2853492, 1.178125, 2.249938,
+    413.2805933, 1.18, 2.21,
+    406.5055016, 1.1775, 2.160063,
+    399.9489613, 1.175, 2.13,
+    393.6005651, 1.174375, 2.177188,
+    387.4505563, 1.18, 2.21,
+    381.4897785, 1.2, 2.121562,
+    375.7096303, 1.225, 2.015,
+    370.1020239, 1.249062, 1.972438,
+    364.6593471, 1.27, 1.95,
+    359.374429, 1.281563, 1.931688,
+    354.2405086, 1.29, 1.916,
+    349.2512056, 1.300313, 1.89425,
+    344.4004944, 1.31, 1.872,
+    339.6826795, 1.317812, 1.85175,
+    335.092373, 1.325, 1.834,
+    330.6244747, 1.334375, 1.822125,
+    326.2741526, 1.34, 1.81,
+    322.036826, 1.3325, 1.791625,
+    317.9081487, 1.325, 1.77,
+    313.8839949, 1.329063, 1.744563,
+    309.960445, 1.34, 1.72,
+    306.1337728, 1.358438, 1.703313,
+    302.4004341, 1.38, 1.687,
+    298.7570554, 1.400313, 1.662125,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn piecewise_linear_interpolates() {
+        let s = PiecewiseLinearSpectrum::from_interleaved(&[400., 1., 500., 3., 600., 2.], false);
+        assert_eq!(s.evaluate(400.), 1.);
+        assert_eq!(s.evaluate(450.), 2.);
+        assert_eq!(s.evaluate(550.), 2.5);
+        assert_eq!(s.evaluate(600.), 2.);
+        assert_eq!(s.evaluate(399.), 0.);
+        assert_eq!(s.evaluate(601.), 0.);
+        assert_eq!(s.max_value(), 3.);
+    }
+
+    #[test]
+    fn cie_y_integral_matches_inner_product() {
+        let y = inner_product(&*CIE_Y, &ConstantSpectrum::new(1.));
+        assert!((y - CIE_Y_INTEGRAL).abs() < 1e-3, "{}", y);
+    }
+
+    #[test]
+    fn blackbody_peaks_at_one() {
+        let s = BlackbodySpectrum::new(6500.);
+        let peak = 2.897_772e6 / 6500.;
+        assert!((s.evaluate(peak) - 1.).abs() < 1e-4);
+        assert!(s.evaluate(peak - 20.) < 1.);
+        assert!(s.evaluate(peak + 20.) < 1.);
+    }
+
+    #[test]
+    fn named_illuminants_have_unit_luminance() {
+        for name in ["stdillum-D65", "stdillum-A"] {
+            let y = spectrum_to_xyz(&*get_named_spectrum(name).unwrap()).y;
+            assert!((y - 1.).abs() < 1e-3, "{}: {}", name, y);
+        }
+        // D65 is close to its chromaticity of (0.3127, 0.3290).
+        let xy = spectrum_to_xyz(&*get_named_spectrum("stdillum-D65").unwrap()).xy();
+        assert!((xy.0 - 0.3127).abs() < 0.005 && (xy.1 - 0.3290).abs() < 0.005);
+    }
+
+    #[test]
+    fn named_metals_and_glasses() {
+        let eta = get_named_spectrum("metal-Au-eta").unwrap();
+        let k = get_named_spectrum("metal-Au-k").unwrap();
+        assert!(eta.evaluate(700.) < 0.2 && k.evaluate(700.) > 3.);
+        assert!(eta.evaluate(LAMBDA_MIN) > 1. && eta.evaluate(LAMBDA_MAX) > 0.);
+        let bk7 = get_named_spectrum("glass-BK7").unwrap();
+        assert!((bk7.evaluate(590.) - 1.5168).abs() < 1e-3);
+        assert!(get_named_spectrum("metal-unobtainium-eta").is_none());
+    }
+}
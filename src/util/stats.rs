@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+// Counters by category and then by name, so that they print in a stable
+// order.
+static COUNTERS: Lazy<Mutex<BTreeMap<String, BTreeMap<String, i64>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Sets the counter `name` under `category`, replacing any earlier value.
+pub fn set_counter(category: &str, name: &str, value: i64) {
+    let mut counters = COUNTERS.lock().unwrap();
+    counters
+        .entry(category.to_string())
+        .or_default()
+        .insert(name.to_string(), value);
+}
+
+/// Adds `delta` to the counter `name` under `category`.
+pub fn add_to_counter(category: &str, name: &str, delta: i64) {
+    let mut counters = COUNTERS.lock().unwrap();
+    *counters
+        .entry(category.to_string())
+        .or_default()
+        .entry(name.to_string())
+        .or_default() += delta;
+}
+
+pub fn counter(category: &str, name: &str) -> Option<i64> {
+    let counters = COUNTERS.lock().unwrap();
+    counters.get(category)?.get(name).copied()
+}
+
+pub fn print_stats(out: &mut impl Write) -> io::Result<()> {
+    let counters = COUNTERS.lock().unwrap();
+    writeln!(out, "Statistics:")?;
+    for (category, names) in counters.iter() {
+        writeln!(out, "  {}", category)?;
+        for (name, value) in names {
+            writeln!(out, "    {:<42}{:>12}", name, value)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn clear_stats() {
+    COUNTERS.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counters_print_by_category() {
+        // Other tests may report their own counters concurrently, so only
+        // look at this test's categories.
+        set_counter("Test/Stats B", "Things", 3);
+        add_to_counter("Test/Stats A", "Widgets", 2);
+        add_to_counter("Test/Stats A", "Widgets", 5);
+        assert_eq!(counter("Test/Stats A", "Widgets"), Some(7));
+        assert_eq!(counter("Test/Stats A", "Gadgets"), None);
+
+        let mut out = Vec::new();
+        print_stats(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let a = out.find("  Test/Stats A\n    Widgets").unwrap();
+        let b = out.find("  Test/Stats B\n    Things").unwrap();
+        assert!(out.starts_with("Statistics:\n") && a < b);
+        assert!(out.contains(&format!("    {:<42}{:>12}\n", "Things", 3)));
+    }
+}
@@ -4,21 +4,252 @@ use super::{
     float::Num,
     math::{
         compensated_float::{inner_product_internal_12, inner_product_internal_6},
+        interval::{self, Interval},
+        lerp, radians,
         square_matrix::SquareMatrix,
     },
+    quaternion::{slerp, Quaternion},
     vecmath::{
-        vector::{cross, normalize},
-        Point3f, Tuple3, Vector3f,
+        vector::{cross, length, normalize},
+        Bounds3f, Normal3f, Point3f, Tuple3, Vector3f,
     },
 };
+use std::hash::{Hash, Hasher};
+use std::ops::Mul;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transform {
     m: SquareMatrix<4>,
     m_inv: SquareMatrix<4>,
 }
 
-pub fn look_at(pos: Point3f, look: Point3f, up: Vector3f) -> Transform {
+impl Transform {
+    // Transforms are compared by bit pattern rather than with float
+    // equality, so that a singular transform (whose inverse is NaN) still
+    // equals itself and can be interned. Negative zero is folded into zero,
+    // since the two are interchangeable in a matrix.
+    fn bits(&self) -> impl Iterator<Item = u32> + '_ {
+        self.m
+            .0
+            .iter()
+            .chain(self.m_inv.0.iter())
+            .flatten()
+            .map(|&v| if v == 0.0 { 0 } else { v.to_bits() })
+    }
+}
+
+impl PartialEq for Transform {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits().eq(other.bits())
+    }
+}
+
+impl Eq for Transform {}
+
+impl Hash for Transform {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().for_each(|b| b.hash(state));
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: SquareMatrix::new(),
+            m_inv: SquareMatrix::new(),
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    /// The transform with matrix `m`. A singular `m` leaves the inverse
+    /// filled with NaNs.
+    pub fn new(m: SquareMatrix<4>) -> Self {
+        let m_inv = inverse(&m).unwrap_or(SquareMatrix([[Float::NAN; 4]; 4]));
+        Self { m, m_inv }
+    }
+
+    /// The transform whose matrix has `m` as its rows.
+    pub fn from_array(m: &[Float; 16]) -> Self {
+        let mut rows = [[0.0; 4]; 4];
+        for (row, values) in rows.iter_mut().zip(m.chunks(4)) {
+            row.copy_from_slice(values);
+        }
+        Self::new(SquareMatrix(rows))
+    }
+
+    pub fn from_matrices(m: SquareMatrix<4>, m_inv: SquareMatrix<4>) -> Self {
+        Self { m, m_inv }
+    }
+
+    pub fn matrix(&self) -> &SquareMatrix<4> {
+        &self.m
+    }
+
+    pub fn inverse_matrix(&self) -> &SquareMatrix<4> {
+        &self.m_inv
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.m == SquareMatrix::new()
+    }
+
+    pub fn apply_point(&self, p: Point3f) -> Point3f {
+        let [x, y, z, w] = self.m.mul_vec([p.x(), p.y(), p.z(), 1.0]);
+        if w == 1.0 {
+            Point3f::new(x, y, z)
+        } else {
+            Point3f::new(x / w, y / w, z / w)
+        }
+    }
+
+    pub fn apply_vector(&self, v: Vector3f) -> Vector3f {
+        let [x, y, z, _] = self.m.mul_vec([v.x(), v.y(), v.z(), 0.0]);
+        Vector3f::new(x, y, z)
+    }
+
+    /// Transforms a surface normal, which takes the inverse transpose so
+    /// that it stays perpendicular to the surface.
+    pub fn apply_normal(&self, n: Normal3f) -> Normal3f {
+        let m = &self.m_inv.0;
+        Normal3f::new(
+            m[0][0] * n.x() + m[1][0] * n.y() + m[2][0] * n.z(),
+            m[0][1] * n.x() + m[1][1] * n.y() + m[2][1] * n.z(),
+            m[0][2] * n.x() + m[1][2] * n.y() + m[2][2] * n.z(),
+        )
+    }
+
+    /// Whether the transform turns a right-handed coordinate system into a
+    /// left-handed one, which flips the surfaces it's applied to inside out.
+    pub fn swaps_handedness(&self) -> bool {
+        let m = &self.m.0;
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        det < 0.0
+    }
+
+    pub fn apply_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        (1..8).fold(
+            Bounds3f::from_point(self.apply_point(b.corner(0))),
+            |bounds, i| bounds.union_point(self.apply_point(b.corner(i))),
+        )
+    }
+}
+
+impl Mul for &Transform {
+    type Output = Transform;
+
+    /// Applies `rhs` first, then `self`.
+    fn mul(self, rhs: &Transform) -> Transform {
+        Transform {
+            m: self.m.mul(&rhs.m),
+            m_inv: rhs.m_inv.mul(&self.m_inv),
+        }
+    }
+}
+
+pub fn transpose(t: &Transform) -> Transform {
+    Transform {
+        m: t.m.transpose(),
+        m_inv: t.m_inv.transpose(),
+    }
+}
+
+/// The values the Transform and ConcatTransform directives give `t` as,
+/// which are its matrix transposed.
+pub fn directive_values(t: &Transform) -> [Float; 16] {
+    let m = transpose(t).matrix().0;
+    std::array::from_fn(|i| m[i / 4][i % 4])
+}
+
+/// The transform into the coordinate system with orthonormal axes `x`, `y`
+/// and `z`.
+pub fn from_frame(x: Vector3f, y: Vector3f, z: Vector3f) -> Transform {
+    let m = SquareMatrix([
+        [x.x(), x.y(), x.z(), 0.0],
+        [y.x(), y.y(), y.z(), 0.0],
+        [z.x(), z.y(), z.z(), 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    Transform {
+        m,
+        m_inv: m.transpose(),
+    }
+}
+
+pub fn translate(delta: Vector3f) -> Transform {
+    let m = SquareMatrix([
+        [1.0, 0.0, 0.0, delta.x()],
+        [0.0, 1.0, 0.0, delta.y()],
+        [0.0, 0.0, 1.0, delta.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    let m_inv = SquareMatrix([
+        [1.0, 0.0, 0.0, -delta.x()],
+        [0.0, 1.0, 0.0, -delta.y()],
+        [0.0, 0.0, 1.0, -delta.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    Transform { m, m_inv }
+}
+
+pub fn scale(x: Float, y: Float, z: Float) -> Transform {
+    Transform {
+        m: SquareMatrix::diag([x, y, z, 1.0]),
+        m_inv: SquareMatrix::diag([1.0 / x, 1.0 / y, 1.0 / z, 1.0]),
+    }
+}
+
+/// Rotation by `theta` degrees counterclockwise around `axis`.
+pub fn rotate(theta: Float, axis: Vector3f) -> Transform {
+    let (sin_theta, cos_theta) = radians(theta).sin_cos();
+    let a = normalize(axis);
+    let (x, y, z) = (a.x(), a.y(), a.z());
+    let m = SquareMatrix([
+        [
+            x * x + (1.0 - x * x) * cos_theta,
+            x * y * (1.0 - cos_theta) - z * sin_theta,
+            x * z * (1.0 - cos_theta) + y * sin_theta,
+            0.0,
+        ],
+        [
+            x * y * (1.0 - cos_theta) + z * sin_theta,
+            y * y + (1.0 - y * y) * cos_theta,
+            y * z * (1.0 - cos_theta) - x * sin_theta,
+            0.0,
+        ],
+        [
+            x * z * (1.0 - cos_theta) - y * sin_theta,
+            y * z * (1.0 - cos_theta) + x * sin_theta,
+            z * z + (1.0 - z * z) * cos_theta,
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    // Rotations are orthogonal, so the inverse is the transpose.
+    Transform {
+        m,
+        m_inv: m.transpose(),
+    }
+}
+
+/// The camera-from-world transform of a camera at `pos` looking at `look`.
+/// Returns `None` if `up` is parallel to the viewing direction.
+pub fn look_at(pos: Point3f, look: Point3f, up: Vector3f) -> Option<Transform> {
     let mut world_from_camera = SquareMatrix::<4>::new();
     world_from_camera[0][3] = pos.x();
     world_from_camera[1][3] = pos.y();
@@ -26,7 +257,9 @@ pub fn look_at(pos: Point3f, look: Point3f, up: Vector3f) -> Transform {
     world_from_camera[3][3] = 1.0;
 
     let dir = normalize(look - pos);
-    // unimplemented!(lookat, up same direction)
+    if length(cross(normalize(up), dir)) == 0.0 {
+        return None;
+    }
     let right = normalize(cross(normalize(up), dir));
     let new_up = cross(dir, right);
 
@@ -43,11 +276,238 @@ pub fn look_at(pos: Point3f, look: Point3f, up: Vector3f) -> Transform {
     world_from_camera[2][2] = dir.z();
     world_from_camera[3][2] = 0.0;
 
-    let camera_from_world = inverse(&world_from_camera).unwrap();
-    Transform {
-        m: world_from_camera,
-        m_inv: camera_from_world,
+    let camera_from_world = inverse(&world_from_camera)?;
+    Some(Transform {
+        m: camera_from_world,
+        m_inv: world_from_camera,
+    })
+}
+
+/// A transform that moves between two keyframes over
+/// `[start_time, end_time]`. Each keyframe is decomposed into translation,
+/// rotation and scale, which are interpolated separately so that rotating
+/// objects don't shear or shrink along the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedTransform {
+    pub start_transform: Transform,
+    pub end_transform: Transform,
+    pub start_time: Float,
+    pub end_time: Float,
+    actually_animated: bool,
+    t: [Vector3f; 2],
+    r: [Quaternion; 2],
+    s: [SquareMatrix<4>; 2],
+    has_rotation: bool,
+}
+
+impl AnimatedTransform {
+    pub fn new(
+        start_transform: &Transform,
+        start_time: Float,
+        end_transform: &Transform,
+        end_time: Float,
+    ) -> Self {
+        let mut at = Self {
+            start_transform: start_transform.clone(),
+            end_transform: end_transform.clone(),
+            start_time,
+            end_time,
+            actually_animated: start_transform != end_transform,
+            t: [Vector3f::new(0.0, 0.0, 0.0); 2],
+            r: [Quaternion::default(); 2],
+            s: [SquareMatrix::new(); 2],
+            has_rotation: false,
+        };
+        if !at.actually_animated {
+            return at;
+        }
+        let (t0, r0, s0) = decompose(start_transform.matrix());
+        let (t1, mut r1, s1) = decompose(end_transform.matrix());
+        // q and -q are the same rotation; take the one that's the shorter
+        // way around.
+        if r0.dot(&r1) < 0.0 {
+            r1 = -r1;
+        }
+        at.has_rotation = r0.dot(&r1) < 0.9995;
+        at.t = [t0, t1];
+        at.r = [r0, r1];
+        at.s = [s0, s1];
+        at
+    }
+
+    /// A transform that doesn't move.
+    pub fn from_transform(t: &Transform) -> Self {
+        Self::new(t, 0.0, t, 1.0)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.actually_animated
+    }
+
+    pub fn interpolate(&self, time: Float) -> Transform {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform.clone();
+        }
+        if time >= self.end_time {
+            return self.end_transform.clone();
+        }
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let trans = (1.0 - dt) * self.t[0] + dt * self.t[1];
+        let rotate = slerp(dt, &self.r[0], &self.r[1]);
+        let mut scale = SquareMatrix::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                scale[i][j] = lerp(dt, self.s[0][i][j], self.s[1][i][j]);
+            }
+        }
+        &(&translate(trans) * &rotate.to_transform()) * &Transform::new(scale)
+    }
+
+    pub fn apply_point(&self, p: Point3f, time: Float) -> Point3f {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform.apply_point(p);
+        }
+        if time >= self.end_time {
+            return self.end_transform.apply_point(p);
+        }
+        self.interpolate(time).apply_point(p)
+    }
+
+    pub fn apply_vector(&self, v: Vector3f, time: Float) -> Vector3f {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform.apply_vector(v);
+        }
+        if time >= self.end_time {
+            return self.end_transform.apply_vector(v);
+        }
+        self.interpolate(time).apply_vector(v)
+    }
+
+    /// Bounds `b` over the whole time range.
+    pub fn motion_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        if !self.actually_animated {
+            return self.start_transform.apply_bounds(b);
+        }
+        if !self.has_rotation {
+            // Translation and scale alone move points along straight lines.
+            return self
+                .start_transform
+                .apply_bounds(b)
+                .union(&self.end_transform.apply_bounds(b));
+        }
+        // Every intermediate box is the hull of its corners.
+        (1..8).fold(self.bound_point_motion(b.corner(0)), |bounds, i| {
+            bounds.union(&self.bound_point_motion(b.corner(i)))
+        })
+    }
+
+    /// Bounds the path of `p` over the whole time range, by evaluating the
+    /// interpolation with interval arithmetic over pieces of the range.
+    pub fn bound_point_motion(&self, p: Point3f) -> Bounds3f {
+        let ends = Bounds3f::from_point(self.start_transform.apply_point(p))
+            .union_point(self.end_transform.apply_point(p));
+        if !self.has_rotation {
+            return ends;
+        }
+        const PIECES: usize = 32;
+        let [q0, q1] = self.r;
+        let cos_theta = q0.dot(&q1);
+        let theta = cos_theta.clamp(-1.0, 1.0).acos();
+        let q_perp = (q1 - q0 * cos_theta).normalize();
+        let components = |q: &Quaternion| [q.v.x(), q.v.y(), q.v.z(), q.w];
+        let (q0, q_perp) = (components(&q0), components(&q_perp));
+        let p = [p.x(), p.y(), p.z()];
+        (0..PIECES).fold(ends, |bounds, piece| {
+            let dt = Interval::new(
+                piece as Float / PIECES as Float,
+                (piece + 1) as Float / PIECES as Float,
+            );
+            let lerp = |a: Float, b: Float| Interval::from_value(a) + dt * (b - a);
+            // The same slerp as interpolate() uses.
+            let (cos, sin) = (interval::cos(dt * theta), interval::sin(dt * theta));
+            let [x, y, z, w] = [0, 1, 2, 3].map(|i| cos * q0[i] + sin * q_perp[i]);
+            let one = Interval::from_value(1.0);
+            let r = [
+                [
+                    one - (y * y + z * z) * 2.0,
+                    (x * y - w * z) * 2.0,
+                    (x * z + w * y) * 2.0,
+                ],
+                [
+                    (x * y + w * z) * 2.0,
+                    one - (x * x + z * z) * 2.0,
+                    (y * z - w * x) * 2.0,
+                ],
+                [
+                    (x * z - w * y) * 2.0,
+                    (y * z + w * x) * 2.0,
+                    one - (x * x + y * y) * 2.0,
+                ],
+            ];
+            let scaled = [0, 1, 2].map(|i| {
+                (0..3).fold(Interval::from_value(0.0), |sum, j| {
+                    sum + lerp(self.s[0][i][j], self.s[1][i][j]) * p[j]
+                })
+            });
+            let t = [
+                (self.t[0].x(), self.t[1].x()),
+                (self.t[0].y(), self.t[1].y()),
+                (self.t[0].z(), self.t[1].z()),
+            ];
+            let moved = [0, 1, 2]
+                .map(|i| (0..3).fold(lerp(t[i].0, t[i].1), |sum, j| sum + r[i][j] * scaled[j]));
+            bounds.union(&Bounds3f::new(
+                Point3f::new(
+                    moved[0].lower_bound(),
+                    moved[1].lower_bound(),
+                    moved[2].lower_bound(),
+                ),
+                Point3f::new(
+                    moved[0].upper_bound(),
+                    moved[1].upper_bound(),
+                    moved[2].upper_bound(),
+                ),
+            ))
+        })
+    }
+}
+
+/// Splits `m` into translation, rotation and the remaining scale.
+fn decompose(m: &SquareMatrix<4>) -> (Vector3f, Quaternion, SquareMatrix<4>) {
+    let t = Vector3f::new(m[0][3], m[1][3], m[2][3]);
+    let mut upper = *m;
+    for i in 0..3 {
+        upper[i][3] = 0.0;
+        upper[3][i] = 0.0;
+    }
+    upper[3][3] = 1.0;
+
+    // Polar decomposition: averaging with the inverse transpose converges
+    // to the rotation.
+    let mut r = upper;
+    for _ in 0..100 {
+        let Some(r_inv) = inverse(&r) else {
+            break;
+        };
+        let r_it = r_inv.transpose();
+        let mut next = r;
+        let mut norm: Float = 0.0;
+        for i in 0..3 {
+            let mut row = 0.0;
+            for j in 0..3 {
+                next[i][j] = 0.5 * (r[i][j] + r_it[i][j]);
+                row += (r[i][j] - next[i][j]).abs();
+            }
+            norm = norm.max(row);
+        }
+        r = next;
+        if norm <= 1e-4 {
+            break;
+        }
     }
+    let q = Quaternion::from_transform(&Transform::from_matrices(r, r.transpose()));
+    let s = inverse(&r).unwrap_or_else(SquareMatrix::new).mul(&upper);
+    (t, q, s)
 }
 
 #[inline]
@@ -81,7 +541,7 @@ pub fn inverse(m: &SquareMatrix<4>) -> Option<SquareMatrix<4>> {
             s * inner_product_internal_6(m[1][1], c5, m[1][3], c3, -m[1][2], c4).to_float(),
             s * inner_product_internal_6(-m[0][1], c5, m[0][2], c4, -m[0][3], c3).to_float(),
             s * inner_product_internal_6(m[3][1], s5, m[3][3], s3, -m[3][2], s4).to_float(),
-            s * inner_product_internal_6(-m[2][1], s5, m[2][2], s5, -m[2][3], s4).to_float(),
+            s * inner_product_internal_6(-m[2][1], s5, m[2][2], s4, -m[2][3], s3).to_float(),
         ],
         [
             s * inner_product_internal_6(-m[1][0], c5, m[1][2], c2, -m[1][3], c1).to_float(),
@@ -104,4 +564,180 @@ pub fn inverse(m: &SquareMatrix<4>) -> Option<SquareMatrix<4>> {
     ]))
 }
 
-mod test {}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_matrix_eq(a: &SquareMatrix<4>, b: &SquareMatrix<4>) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a[i][j] - b[i][j]).abs() < 1e-5, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn assert_point_eq(p: Point3f, x: Float, y: Float, z: Float) {
+        assert!(
+            (p.x() - x).abs() < 1e-5 && (p.y() - y).abs() < 1e-5 && (p.z() - z).abs() < 1e-5,
+            "{:?} != ({}, {}, {})",
+            p,
+            x,
+            y,
+            z
+        );
+    }
+
+    // Checks that the stored inverse really is one.
+    fn assert_consistent(t: &Transform) {
+        assert_matrix_eq(&t.m.mul(&t.m_inv), &SquareMatrix::new());
+    }
+
+    #[test]
+    fn inverse_of_general_matrix() {
+        let m = SquareMatrix([
+            [2.0, 0.5, 0.0, 1.0],
+            [0.0, 1.0, 3.0, -2.0],
+            [1.0, 0.0, 1.0, 0.5],
+            [0.0, 0.25, 0.0, 1.0],
+        ]);
+        let m_inv = inverse(&m).unwrap();
+        assert_matrix_eq(&m.mul(&m_inv), &SquareMatrix::new());
+        assert_matrix_eq(&m_inv.mul(&m), &SquareMatrix::new());
+        assert!(inverse(&SquareMatrix::diag([1.0, 1.0, 0.0, 1.0])).is_none());
+    }
+
+    #[test]
+    fn translate_and_scale() {
+        let t = translate(Vector3f::new(1.0, 2.0, 3.0));
+        assert_point_eq(t.apply_point(Point3f::new(1.0, 1.0, 1.0)), 2.0, 3.0, 4.0);
+        // Vectors aren't translated.
+        let v = t.apply_vector(Vector3f::new(1.0, 0.0, 0.0));
+        assert_eq!((v.x(), v.y(), v.z()), (1.0, 0.0, 0.0));
+        assert_consistent(&t);
+
+        let s = scale(2.0, 3.0, 4.0);
+        assert_point_eq(s.apply_point(Point3f::new(1.0, 1.0, 1.0)), 2.0, 3.0, 4.0);
+        assert_consistent(&s);
+    }
+
+    #[test]
+    fn rotate_about_axes() {
+        let r = rotate(90.0, Vector3f::new(0.0, 0.0, 1.0));
+        assert_point_eq(r.apply_point(Point3f::new(1.0, 0.0, 0.0)), 0.0, 1.0, 0.0);
+        let r = rotate(90.0, Vector3f::new(1.0, 0.0, 0.0));
+        assert_point_eq(r.apply_point(Point3f::new(0.0, 1.0, 0.0)), 0.0, 0.0, 1.0);
+        // A third of a turn around (1, 1, 1) cycles the axes.
+        let r = rotate(120.0, Vector3f::new(1.0, 1.0, 1.0));
+        assert_point_eq(r.apply_point(Point3f::new(1.0, 0.0, 0.0)), 0.0, 1.0, 0.0);
+        assert_consistent(&r);
+    }
+
+    #[test]
+    fn composition_applies_right_operand_first() {
+        let t = &translate(Vector3f::new(1.0, 0.0, 0.0)) * &scale(2.0, 2.0, 2.0);
+        assert_matrix_eq(
+            &t.m,
+            &SquareMatrix([
+                [2.0, 0.0, 0.0, 1.0],
+                [0.0, 2.0, 0.0, 0.0],
+                [0.0, 0.0, 2.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+        );
+        assert_point_eq(t.apply_point(Point3f::new(1.0, 1.0, 1.0)), 3.0, 2.0, 2.0);
+        assert_consistent(&t);
+    }
+
+    #[test]
+    fn look_at_is_camera_from_world() {
+        let t = look_at(
+            Point3f::new(0.0, 0.0, -5.0),
+            Point3f::new(0.0, 0.0, 0.0),
+            Vector3f::new(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        // The camera sits at the origin of camera space, looking down +z.
+        assert_point_eq(t.apply_point(Point3f::new(0.0, 0.0, -5.0)), 0.0, 0.0, 0.0);
+        assert_point_eq(t.apply_point(Point3f::new(0.0, 0.0, 0.0)), 0.0, 0.0, 5.0);
+        assert_point_eq(t.apply_point(Point3f::new(0.0, 1.0, -5.0)), 0.0, 1.0, 0.0);
+        assert_consistent(&t);
+
+        assert!(look_at(
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(0.0, 1.0, 0.0),
+            Vector3f::new(0.0, 1.0, 0.0),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn transforms_intern_by_value() {
+        use crate::util::containers::InternCache;
+
+        let cache = InternCache::new();
+        let a = cache.lookup(&translate(Vector3f::new(1.0, 2.0, 3.0)));
+        let b = cache.lookup(&translate(Vector3f::new(1.0, 2.0, 3.0)));
+        assert!(std::sync::Arc::ptr_eq(&a, &b));
+        // -0 and 0 are the same transform.
+        let c = cache.lookup(&scale(1.0, 1.0, 1.0));
+        let d = cache.lookup(&Transform::from_array(&[
+            1.0, -0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+        ]));
+        assert!(std::sync::Arc::ptr_eq(&c, &d));
+        // A singular transform still equals itself.
+        let singular = scale(0.0, 1.0, 1.0);
+        assert_eq!(singular, singular.clone());
+        cache.lookup(&singular);
+        cache.lookup(&singular);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn animated_transform_interpolates_components() {
+        let start = &translate(Vector3f::new(1.0, 0.0, 0.0)) * &scale(2.0, 2.0, 2.0);
+        let end = &(&translate(Vector3f::new(3.0, 0.0, 0.0))
+            * &rotate(90.0, Vector3f::new(0.0, 0.0, 1.0)))
+            * &scale(4.0, 4.0, 4.0);
+        let at = AnimatedTransform::new(&start, 0.0, &end, 2.0);
+        assert!(at.is_animated());
+        assert_matrix_eq(at.interpolate(0.0).matrix(), start.matrix());
+        assert_matrix_eq(at.interpolate(2.0).matrix(), end.matrix());
+        // Halfway: translated by 2, rotated by 45 degrees, scaled by 3.
+        let s = 3.0 * (0.5 as Float).sqrt();
+        assert_point_eq(
+            at.apply_point(Point3f::new(1.0, 0.0, 0.0), 1.0),
+            2.0 + s,
+            s,
+            0.0,
+        );
+        assert_consistent(&at.interpolate(1.0));
+
+        let still = AnimatedTransform::from_transform(&start);
+        assert!(!still.is_animated());
+        assert_matrix_eq(still.interpolate(0.5).matrix(), start.matrix());
+    }
+
+    #[test]
+    fn motion_bounds_contain_the_path() {
+        let b = Bounds3f::new(Point3f::new(-1.0, 0.5, -0.5), Point3f::new(1.0, 2.0, 0.5));
+        let end = &(&translate(Vector3f::new(0.0, 1.0, 3.0))
+            * &rotate(160.0, Vector3f::new(1.0, 1.0, 0.0)))
+            * &scale(1.0, 2.0, 1.0);
+        for end in [end, translate(Vector3f::new(1.0, 2.0, 3.0))] {
+            let at = AnimatedTransform::new(&Transform::identity(), 0.0, &end, 1.0);
+            let bounds = at.motion_bounds(&b);
+            for i in 0..=64 {
+                let time = i as Float / 64.0;
+                let t = at.interpolate(time);
+                for c in 0..8 {
+                    assert!(bounds.inside(t.apply_point(b.corner(c))), "{} {}", time, c);
+                }
+            }
+            let ends = at
+                .start_transform
+                .apply_bounds(&b)
+                .union(&at.end_transform.apply_bounds(&b));
+            assert!(bounds.inside(ends.p_min) && bounds.inside(ends.p_max));
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use super::point::Point3;
+use super::*;
+
+/// An axis-aligned box, given by its two extreme corners.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bounds3<T> {
+    pub p_min: Point3<T>,
+    pub p_max: Point3<T>,
+}
+
+impl<T> Bounds3<T>
+where
+    T: Num + PartialOrd,
+{
+    pub fn new(p1: Point3<T>, p2: Point3<T>) -> Self {
+        Self {
+            p_min: p1.min(p2),
+            p_max: p1.max(p2),
+        }
+    }
+
+    pub fn from_point(p: Point3<T>) -> Self {
+        Self { p_min: p, p_max: p }
+    }
+
+    /// One of the eight corners: bits 0, 1 and 2 of `corner` pick the
+    /// maximum rather than the minimum in x, y and z.
+    pub fn corner(&self, corner: usize) -> Point3<T> {
+        let pick = |bit: usize, min: T, max: T| if corner & bit != 0 { max } else { min };
+        Point3::new(
+            pick(1, self.p_min.x(), self.p_max.x()),
+            pick(2, self.p_min.y(), self.p_max.y()),
+            pick(4, self.p_min.z(), self.p_max.z()),
+        )
+    }
+
+    pub fn union_point(&self, p: Point3<T>) -> Self {
+        Self {
+            p_min: self.p_min.min(p),
+            p_max: self.p_max.max(p),
+        }
+    }
+
+    pub fn union(&self, b: &Self) -> Self {
+        Self {
+            p_min: self.p_min.min(b.p_min),
+            p_max: self.p_max.max(b.p_max),
+        }
+    }
+
+    pub fn inside(&self, p: Point3<T>) -> bool {
+        p.x() >= self.p_min.x()
+            && p.x() <= self.p_max.x()
+            && p.y() >= self.p_min.y()
+            && p.y() <= self.p_max.y()
+            && p.z() >= self.p_min.z()
+            && p.z() <= self.p_max.z()
+    }
+}
@@ -1,13 +1,16 @@
 use crate::util::float::Num;
 use crate::*;
 
+mod bounds;
 mod normal;
 mod point;
 pub mod vector;
 
+pub type Bounds3f = bounds::Bounds3<Float>;
 pub type Vector3f = vector::Vector3<Float>;
 pub type Point3f = point::Point3<Float>;
 pub type Point2f = point::Point2<Float>;
+pub type Normal3f = normal::Normal3<Float>;
 
 pub trait Tuple2<T: Num> {
     fn new(x: T, y: T) -> Self;
@@ -0,0 +1,43 @@
+use crate::util::float::Num;
+
+use super::Tuple3;
+use std::ops::{Add, Div, Mul, Sub};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Normal3<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+}
+
+impl<T> Tuple3<T> for Normal3<T>
+where
+    T: Num,
+{
+    #[inline]
+    fn x(&self) -> T {
+        self.x
+    }
+
+    #[inline]
+    fn y(&self) -> T {
+        self.y
+    }
+
+    #[inline]
+    fn z(&self) -> T {
+        self.z
+    }
+
+    #[inline]
+    fn new(x: T, y: T, z: T) -> Self {
+        let v = Self { x, y, z };
+        debug_assert!(!v.has_nan());
+        v
+    }
+}
+
+super::tuple3_binary!(Normal3, Add, add);
+super::tuple3_binary!(Normal3, Sub, sub);
+super::scalar_binary!(Normal3, Mul, mul);
+super::scalar_binary!(Normal3, Div, div);
@@ -3,6 +3,7 @@ use super::*;
 
 use std::ops::{Add, Div, Mul, Sub};
 
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Point2<T> {
     x: T,
     y: T,
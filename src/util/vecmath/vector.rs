@@ -79,6 +79,14 @@ where
     v / length(v)
 }
 
+#[inline]
+pub fn dot<T>(v: Vector3<T>, w: Vector3<T>) -> T
+where
+    T: Num + Add<Output = T> + Mul<Output = T>,
+{
+    v.x() * w.x() + v.y() * w.y() + v.z() * w.z()
+}
+
 #[inline]
 pub fn cross<T>(v: Vector3<T>, w: Vector3<T>) -> Vector3<T>
 where
@@ -91,3 +99,15 @@ where
         T::difference_of_products(v.x(), w.y(), v.y(), w.x()),
     )
 }
+
+/// Two unit vectors that form an orthonormal basis with the unit vector
+/// `v`, following Duff et al., "Building an Orthonormal Basis, Revisited".
+pub fn coordinate_system(v: Vector3<Float>) -> (Vector3<Float>, Vector3<Float>) {
+    let sign = (1.0 as Float).copysign(v.z());
+    let a = -1.0 / (sign + v.z());
+    let b = v.x() * v.y() * a;
+    (
+        Vector3::new(1.0 + sign * v.x() * v.x() * a, sign * b, -sign * v.x()),
+        Vector3::new(b, sign + v.y() * v.y() * a, -v.y()),
+    )
+}
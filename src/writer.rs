@@ -0,0 +1,634 @@
+//! Writing a `BasicScene` back out as a pbrt-v4 scene description.
+//!
+//! Whatever the scene was built from, whether files with Includes and
+//! Imports or the API in `api`, it is written as a single file. Reading
+//! that file back gives an equivalent scene: the same entities with the
+//! same parameters and transforms, though each shape gets its own
+//! attribute block and materials may be repeated.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::paramdict::{ParameterDictionary, ParsedParameter, ParsedParameterVector};
+use crate::parser::{FormattingParserTarget, ParserTarget};
+use crate::scene::{
+    BasicScene, InstanceTransform, SceneEntity, ShapeSceneEntity, TransformedSceneEntity,
+};
+use crate::util::colorspace::{self, sRGB, RGBColorSpace};
+use crate::util::error::{FileLoc, ParseError};
+use crate::util::string::InternedString;
+use crate::util::transform::{directive_values, AnimatedTransform, Transform};
+use crate::Float;
+
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// Triangle meshes with at least this many vertices are written to PLY
+    /// files and referred to by "plymesh" shapes, rather than given inline.
+    pub ply_min_vertices: Option<usize>,
+    /// Where PLY files are written. The scene refers to them by their
+    /// names alone, so it should be written to the same directory.
+    pub ply_directory: PathBuf,
+}
+
+/// Returns the scene description of `scene`, which must have been read
+/// up to WorldBegin at least, writing any PLY files `options` asks for.
+pub fn write_scene(scene: &BasicScene, options: &WriteOptions) -> io::Result<String> {
+    let Some(scene_options) = scene.options.get() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the scene has no WorldBegin",
+        ));
+    };
+    let world_from_render = scene_options
+        .camera
+        .camera_transform
+        .world_from_render()
+        .clone();
+    let mut writer = SceneWriter {
+        out: FormattingParserTarget::new(false),
+        options,
+        render_from_world: world_from_render.inverse(),
+        world_from_render,
+        color_spaces: vec![&sRGB],
+        n_ply_files: 0,
+    };
+
+    // The options block.
+    let (start_time, end_time) = (
+        scene_options.transform_start_time,
+        scene_options.transform_end_time,
+    );
+    if (start_time, end_time) != (0.0, 1.0) {
+        writer.emit(|f, loc| f.transform_times(start_time, end_time, loc));
+    }
+    let camera = &scene_options.camera;
+    if let Some(medium) = &camera.medium {
+        writer.emit(|f, loc| f.medium_interface("", medium, loc));
+    }
+    // The CTM at the Camera directive is camera from world.
+    let world_from_camera = camera.camera_transform.render_from_camera();
+    writer.write_transform(world_from_camera, |w, t| {
+        (&w.world_from_render * t).inverse()
+    });
+    writer.write_options_entity(&camera.scene_entity, |f, name, params, loc| {
+        f.camera(name, params, loc)
+    });
+    for (entity, directive) in [
+        (&scene_options.sampler, ParserTarget::sampler as Directive),
+        (&scene_options.film, ParserTarget::film),
+        (&scene_options.filter, ParserTarget::pixel_filter),
+        (&scene_options.integrator, ParserTarget::integrator),
+        (&scene_options.accelerator, ParserTarget::accelerator),
+    ] {
+        writer.write_options_entity(entity, directive);
+    }
+
+    writer.emit(|f, loc| f.world_begin(loc));
+    for medium in scene.media.lock().unwrap().iter() {
+        writer.write_transformed("medium", medium, |f, name, params, loc| {
+            // Named media are known by the name of their entity.
+            f.make_named_medium(name, params, loc)
+        });
+    }
+    for (textures, typename) in [
+        (&scene.float_textures, "float"),
+        (&scene.spectrum_textures, "spectrum"),
+    ] {
+        for (name, texture) in textures.lock().unwrap().iter() {
+            writer.write_transformed("texture", texture, |f, texname, params, loc| {
+                f.texture(name, typename, texname, params, loc)
+            });
+        }
+    }
+    for (name, material) in scene.named_materials.lock().unwrap().iter() {
+        writer.attribute_begin();
+        writer.write_entity("material", material, |f, _, params, loc| {
+            f.make_named_material(name, params, loc)
+        });
+        writer.attribute_end();
+    }
+    for light in scene.lights.lock().unwrap().iter() {
+        writer.attribute_begin();
+        if let Some(medium) = &light.medium {
+            writer.emit(|f, loc| f.medium_interface("", medium, loc));
+        }
+        writer.write_transform(&light.render_from_object, |w, t| &w.world_from_render * t);
+        writer.write_entity("light", &light.scene_entity, |f, name, params, loc| {
+            f.light_source(name, params, loc)
+        });
+        writer.attribute_end();
+    }
+
+    let materials = scene.materials.lock().unwrap();
+    let area_lights = scene.area_lights.lock().unwrap();
+    let context = ShapeContext {
+        materials: &materials,
+        area_lights: &area_lights,
+    };
+    for shape in scene.shapes.lock().unwrap().iter() {
+        writer.write_shape(&context, &Shape::Static(shape))?;
+    }
+    for shape in scene.animated_shapes.lock().unwrap().iter() {
+        writer.write_shape(&context, &Shape::Animated(shape))?;
+    }
+    // Sorted by name, so that the output doesn't depend on hashing.
+    let definitions = scene.instance_definitions.lock().unwrap();
+    let mut definitions: Vec<_> = definitions.values().collect();
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+    for definition in definitions {
+        writer.emit(|f, loc| f.object_begin(&definition.name, loc));
+        for shape in &definition.shapes {
+            writer.write_shape(&context, &Shape::Static(shape))?;
+        }
+        for shape in &definition.animated_shapes {
+            writer.write_shape(&context, &Shape::Animated(shape))?;
+        }
+        writer.emit(|f, loc| f.object_end(loc));
+    }
+    for instance in scene.instances.lock().unwrap().iter() {
+        writer.attribute_begin();
+        // Instances are placed by render from instance, which is the CTM
+        // taken to render space with the definition in render space.
+        let world_from_instance =
+            |w: &SceneWriter, t: &Transform| &(&w.world_from_render * t) * &w.render_from_world;
+        match &instance.render_from_instance {
+            InstanceTransform::Static(t) => {
+                let t = world_from_instance(&writer, t);
+                writer.write_static_transform(&t);
+            }
+            InstanceTransform::Animated(t) => writer.write_transform(t, world_from_instance),
+        }
+        writer.emit(|f, loc| f.object_instance(&instance.name, loc));
+        writer.attribute_end();
+    }
+    Ok(writer.out.output().to_string())
+}
+
+type Directive =
+    fn(&mut FormattingParserTarget, &str, ParsedParameterVector, FileLoc) -> Result<(), ParseError>;
+
+// Static and animated shapes are written the same way but for their
+// transforms.
+enum Shape<'a> {
+    Static(&'a ShapeSceneEntity),
+    Animated(&'a crate::scene::AnimatedShapeSceneEntity),
+}
+
+// The entities that shapes refer to by index.
+struct ShapeContext<'a> {
+    materials: &'a [SceneEntity],
+    area_lights: &'a [SceneEntity],
+}
+
+struct SceneWriter<'a> {
+    out: FormattingParserTarget,
+    options: &'a WriteOptions,
+    world_from_render: Transform,
+    render_from_world: Transform,
+    // The color space in effect, for each attribute block we're in.
+    color_spaces: Vec<&'static RGBColorSpace>,
+    n_ply_files: usize,
+}
+
+impl SceneWriter<'_> {
+    // Writing to the formatter can't fail.
+    fn emit(
+        &mut self,
+        f: impl FnOnce(&mut FormattingParserTarget, FileLoc) -> Result<(), ParseError>,
+    ) {
+        f(&mut self.out, FileLoc::default()).expect("formatting never fails");
+    }
+
+    fn attribute_begin(&mut self) {
+        self.emit(|f, loc| f.attribute_begin(loc));
+        let current = *self.color_spaces.last().unwrap();
+        self.color_spaces.push(current);
+    }
+
+    fn attribute_end(&mut self) {
+        self.emit(|f, loc| f.attribute_end(loc));
+        self.color_spaces.pop();
+    }
+
+    fn set_color_space(&mut self, color_space: &'static RGBColorSpace) {
+        let current = self.color_spaces.last_mut().unwrap();
+        if std::ptr::eq(*current, color_space) {
+            return;
+        }
+        *current = color_space;
+        // Only the named color spaces can be given in scenes.
+        if let Some(name) = colorspace::name_of(color_space) {
+            self.emit(|f, loc| f.color_space(name, loc));
+        }
+    }
+
+    // Entities are written where the CTM is the identity, which needn't
+    // be given.
+    fn write_static_transform(&mut self, t: &Transform) {
+        if !t.is_identity() {
+            self.emit(|f, loc| f.transform(directive_values(t), loc));
+        }
+    }
+
+    // Writes the CTM that the entity was declared with, for either one
+    // transform or both, given the transform that `world_from` makes of
+    // each of `t`'s.
+    fn write_transform(
+        &mut self,
+        t: &AnimatedTransform,
+        world_from: impl Fn(&Self, &Transform) -> Transform,
+    ) {
+        let start = world_from(self, &t.start_transform);
+        if !t.is_animated() {
+            self.write_static_transform(&start);
+            return;
+        }
+        let end = world_from(self, &t.end_transform);
+        self.emit(|f, loc| f.active_transform_start_time(loc));
+        self.emit(|f, loc| f.transform(directive_values(&start), loc));
+        self.emit(|f, loc| f.active_transform_end_time(loc));
+        self.emit(|f, loc| f.transform(directive_values(&end), loc));
+        self.emit(|f, loc| f.active_transform_all(loc));
+    }
+
+    // Entities from the options block, which have no attributes and can't
+    // be put in attribute blocks.
+    fn write_options_entity(&mut self, entity: &SceneEntity, directive: Directive) {
+        let name = entity.name.as_deref().map_or("", |n| n.as_str());
+        self.set_color_space(entity.parameters.color_space());
+        let params = entity.parameters.params().to_vec();
+        self.emit(|f, loc| directive(f, name, params, loc));
+    }
+
+    // Writes an entity inside an attribute block. Parameters it got from
+    // the Attribute directive are written with that directive for
+    // `target`, so that they're still allowed to go unused.
+    fn write_entity(
+        &mut self,
+        target: &str,
+        entity: &SceneEntity,
+        directive: impl FnOnce(
+            &mut FormattingParserTarget,
+            &str,
+            ParsedParameterVector,
+            FileLoc,
+        ) -> Result<(), ParseError>,
+    ) {
+        let name = entity.name.as_deref().map_or("", |n| n.as_str());
+        let (attributes, params): (Vec<_>, Vec<_>) = entity
+            .parameters
+            .params()
+            .iter()
+            .cloned()
+            .partition(|p| p.may_be_unused);
+        self.write_attributes(target, attributes, entity.parameters.color_space());
+        self.set_color_space(entity.parameters.color_space());
+        self.emit(|f, loc| directive(f, name, params, loc));
+    }
+
+    fn write_attributes(
+        &mut self,
+        target: &str,
+        attributes: Vec<ParsedParameter>,
+        color_space: &'static RGBColorSpace,
+    ) {
+        // One Attribute directive for each run of parameters given in the
+        // same color space.
+        let mut attributes = attributes.into_iter().peekable();
+        while let Some(first) = attributes.next() {
+            let cs = first.color_space.unwrap_or(color_space);
+            let mut run = vec![first];
+            while let Some(p) =
+                attributes.next_if(|p| std::ptr::eq(p.color_space.unwrap_or(color_space), cs))
+            {
+                run.push(p);
+            }
+            self.set_color_space(cs);
+            self.emit(|f, loc| f.attribute(target, run, loc));
+        }
+    }
+
+    // Media and textures, which are placed by their own transforms.
+    fn write_transformed(
+        &mut self,
+        target: &str,
+        entity: &TransformedSceneEntity,
+        directive: impl FnOnce(
+            &mut FormattingParserTarget,
+            &str,
+            ParsedParameterVector,
+            FileLoc,
+        ) -> Result<(), ParseError>,
+    ) {
+        self.attribute_begin();
+        self.write_transform(&entity.render_from_object, |w, t| &w.world_from_render * t);
+        self.write_entity(target, &entity.scene_entity, directive);
+        self.attribute_end();
+    }
+
+    fn write_shape(&mut self, context: &ShapeContext, shape: &Shape) -> io::Result<()> {
+        let (entity, material_index, material_name, light_index, inside, outside, reverse) =
+            match shape {
+                Shape::Static(s) => (
+                    &s.scene_entity,
+                    s.material_index,
+                    &s.material_name,
+                    s.light_index,
+                    &s.inside_medium,
+                    &s.outside_medium,
+                    s.reverse_orientation,
+                ),
+                Shape::Animated(s) => (
+                    &s.scene_entity,
+                    s.material_index,
+                    &s.material_name,
+                    s.light_index,
+                    &s.inside_medium,
+                    &s.outside_medium,
+                    s.reverse_orientation,
+                ),
+            };
+        self.attribute_begin();
+        match material_name {
+            Some(name) => self.emit(|f, loc| f.named_material(name, loc)),
+            // The first material is the default one that every scene
+            // starts with.
+            None if material_index == 0 => {}
+            None => self.write_entity(
+                "material",
+                &context.materials[material_index],
+                |f, name, params, loc| f.material(name, params, loc),
+            ),
+        }
+        if inside.is_some() || outside.is_some() {
+            let name = |m: &Option<InternedString>| m.as_deref().cloned().unwrap_or_default();
+            let (inside, outside) = (name(inside), name(outside));
+            self.emit(|f, loc| f.medium_interface(&inside, &outside, loc));
+        }
+        if let Some(i) = light_index {
+            self.write_entity("light", &context.area_lights[i], |f, name, params, loc| {
+                f.area_light_source(name, params, loc)
+            });
+        }
+        if reverse {
+            self.emit(|f, loc| f.reverse_orientation(loc));
+        }
+        match shape {
+            Shape::Static(s) => {
+                let t = &self.world_from_render * &s.render_from_object;
+                self.write_static_transform(&t);
+            }
+            Shape::Animated(s) => {
+                self.write_transform(&s.render_from_object, |w, t| &w.world_from_render * t)
+            }
+        }
+        match self.write_ply(entity)? {
+            Some(plymesh) => self.write_entity("shape", &plymesh, |f, name, params, loc| {
+                f.shape(name, params, loc)
+            }),
+            None => self.write_entity("shape", entity, |f, name, params, loc| {
+                f.shape(name, params, loc)
+            }),
+        }
+        self.attribute_end();
+        Ok(())
+    }
+
+    // Writes a triangle mesh that's large enough to a PLY file, returning
+    // the "plymesh" shape that refers to it.
+    fn write_ply(&mut self, entity: &SceneEntity) -> io::Result<Option<SceneEntity>> {
+        let Some(min_vertices) = self.options.ply_min_vertices else {
+            return Ok(None);
+        };
+        if entity.name.as_deref().map(|n| n.as_str()) != Some("trianglemesh") {
+            return Ok(None);
+        }
+        // The buffers PLY files hold, and the types they're given as.
+        const BUFFERS: [(&str, &str); 4] = [
+            ("P", "point3"),
+            ("N", "normal"),
+            ("uv", "point2"),
+            ("indices", "integer"),
+        ];
+        let dict = &entity.parameters;
+        let buffer = |name: &str| dict.params().iter().find(|p| *p.name == name);
+        let (Some(p), Some(indices)) = (buffer("P"), buffer("indices")) else {
+            return Ok(None);
+        };
+        let n_vertices = p.floats.len() / 3;
+        let n = buffer("N").filter(|n| n.floats.len() == 3 * n_vertices);
+        let uv = buffer("uv").filter(|uv| uv.floats.len() == 2 * n_vertices);
+        let unsupported = dict.params().iter().any(|p| {
+            !p.may_be_unused
+                && BUFFERS.iter().any(|(name, _)| *p.name == *name)
+                && !BUFFERS.contains(&(p.name.as_str(), p.type_name.as_str()))
+        });
+        if n_vertices < min_vertices || indices.ints.len() % 3 != 0 || unsupported {
+            return Ok(None);
+        }
+
+        self.n_ply_files += 1;
+        let filename = format!("mesh_{:05}.ply", self.n_ply_files);
+        let mut file = BufWriter::new(File::create(self.options.ply_directory.join(&filename))?);
+        write!(
+            file,
+            "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n",
+            n_vertices
+        )?;
+        if n.is_some() {
+            write!(
+                file,
+                "property float nx\nproperty float ny\nproperty float nz\n"
+            )?;
+        }
+        if uv.is_some() {
+            write!(file, "property float u\nproperty float v\n")?;
+        }
+        write!(
+            file,
+            "element face {}\nproperty list uchar int vertex_indices\nend_header\n",
+            indices.ints.len() / 3
+        )?;
+        for i in 0..n_vertices {
+            let mut vertex: Vec<Float> = p.floats[3 * i..3 * i + 3].to_vec();
+            if let Some(n) = n {
+                vertex.extend_from_slice(&n.floats[3 * i..3 * i + 3]);
+            }
+            if let Some(uv) = uv {
+                vertex.extend_from_slice(&uv.floats[2 * i..2 * i + 2]);
+            }
+            for v in vertex {
+                file.write_all(&v.to_le_bytes())?;
+            }
+        }
+        for face in indices.ints.chunks(3) {
+            file.write_all(&[3])?;
+            for i in face {
+                file.write_all(&i.to_le_bytes())?;
+            }
+        }
+        file.flush()?;
+
+        // The mesh's other parameters stay with the shape, followed by
+        // the file's name.
+        let mut params: ParsedParameterVector = dict
+            .params()
+            .iter()
+            .filter(|p| p.may_be_unused || !BUFFERS.iter().any(|(name, _)| *p.name == *name))
+            .cloned()
+            .collect();
+        let mut filename_param = ParsedParameter::new();
+        filename_param.type_name = crate::scene::INTERNED_STRINGS.lookup(&"string".to_string());
+        filename_param.name = crate::scene::INTERNED_STRINGS.lookup(&"filename".to_string());
+        filename_param.strings.push(filename);
+        params.insert(0, filename_param);
+        Ok(Some(SceneEntity::new(
+            "plymesh",
+            ParameterDictionary::new(params, dict.color_space()),
+            entity.loc.clone(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::parser::{parse, Tokenizer};
+    use crate::scene::BasicSceneBuilder;
+
+    fn parse_scene(text: &str) -> Arc<BasicScene> {
+        let scene = Arc::new(BasicScene::new());
+        let mut builder = BasicSceneBuilder::new(scene.clone());
+        parse(
+            &mut builder,
+            Tokenizer::new("test", text.as_bytes().to_vec()),
+        )
+        .unwrap();
+        builder.end_of_files().unwrap();
+        scene
+    }
+
+    const SCENE: &str = "LookAt 0 2 -10  0 0 0  0 1 0\n\
+        Camera \"perspective\" \"float fov\" 40\n\
+        Film \"rgb\" \"string filename\" \"out.exr\"\n\
+        Sampler \"halton\" \"integer pixelsamples\" 16\n\
+        WorldBegin\n\
+        MakeNamedMedium \"fog\" \"string type\" \"homogeneous\"\n\
+        Texture \"checks\" \"spectrum\" \"checkerboard\" \"float uscale\" 8\n\
+        MakeNamedMaterial \"floor\" \"string type\" \"diffuse\" \"texture reflectance\" \"checks\"\n\
+        LightSource \"distant\" \"blackbody L\" 3000\n\
+        AttributeBegin\n\
+          NamedMaterial \"floor\"\n\
+          Scale 10 1 10\n\
+          Shape \"bilinearmesh\" \"point3 P\" [-1 0 -1  1 0 -1  -1 0 1  1 0 1]\n\
+        AttributeEnd\n\
+        AttributeBegin\n\
+          Attribute \"shape\" \"float radius\" 2\n\
+          Material \"conductor\"\n\
+          MediumInterface \"fog\" \"\"\n\
+          AreaLightSource \"diffuse\" \"rgb L\" [4 4 4]\n\
+          ReverseOrientation\n\
+          Translate 0 3 0\n\
+          Shape \"sphere\"\n\
+        AttributeEnd\n\
+        ObjectBegin \"tree\"\n\
+          Shape \"disk\"\n\
+        ObjectEnd\n\
+        Translate 5 0 0\n\
+        ObjectInstance \"tree\"\n";
+
+    #[test]
+    fn written_scenes_read_back_the_same() {
+        let scene = parse_scene(SCENE);
+        let written = write_scene(&scene, &WriteOptions::default()).unwrap();
+        for line in [
+            "Camera \"perspective\"",
+            "    \"float fov\" [ 40 ]",
+            "WorldBegin",
+            "MakeNamedMedium \"fog\"",
+            "Texture \"checks\" \"spectrum\" \"checkerboard\"",
+            "MakeNamedMaterial \"floor\"",
+            "LightSource \"distant\"",
+            "NamedMaterial \"floor\"",
+            "Material \"conductor\"",
+            "MediumInterface \"fog\" \"\"",
+            "AreaLightSource \"diffuse\"",
+            "ReverseOrientation",
+            "Attribute \"shape\"",
+            "ObjectBegin \"tree\"",
+            "ObjectInstance \"tree\"",
+        ] {
+            assert!(
+                written.contains(line),
+                "{} missing from:\n{}",
+                line,
+                written
+            );
+        }
+
+        // Writing the scene read from the output gives the same output.
+        let reread = parse_scene(&written);
+        assert_eq!(
+            write_scene(&reread, &WriteOptions::default()).unwrap(),
+            written
+        );
+        // Transforms come back to within rounding, having been taken to
+        // world space and back.
+        let (shapes, reread_shapes) = (scene.shapes.lock().unwrap(), reread.shapes.lock().unwrap());
+        assert_eq!(shapes.len(), reread_shapes.len());
+        for (a, b) in shapes.iter().zip(reread_shapes.iter()) {
+            assert_eq!(a.reverse_orientation, b.reverse_orientation);
+            let (a, b) = (
+                a.render_from_object.matrix().0,
+                b.render_from_object.matrix().0,
+            );
+            for (a, b) in a.iter().flatten().zip(b.iter().flatten()) {
+                assert!((a - b).abs() < 1e-5, "{:?} != {:?}", a, b);
+            }
+        }
+        assert_eq!(reread.instances.lock().unwrap().len(), 1);
+        assert_eq!(reread.area_lights.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn large_meshes_go_to_ply_files() {
+        let scene = parse_scene(
+            "WorldBegin\n\
+             Shape \"trianglemesh\" \"point3 P\" [0 0 0  1 0 0  0 1 0]\n\
+               \"integer indices\" [0 1 2] \"float alpha\" 0.5\n\
+             Shape \"trianglemesh\" \"point3 P\" [0 0 0  1 0 0  0 1 0  1 1 0]\n\
+               \"point2 uv\" [0 0  1 0  0 1  1 1] \"integer indices\" [0 1 2  2 1 3]\n",
+        );
+        let directory = std::env::temp_dir().join(format!("rpbrt-ply-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let options = WriteOptions {
+            ply_min_vertices: Some(4),
+            ply_directory: directory.clone(),
+        };
+        let written = write_scene(&scene, &options).unwrap();
+        // Only the second mesh is large enough.
+        assert!(written.contains("Shape \"trianglemesh\""));
+        assert!(written.contains("\"float alpha\" [ 0.5 ]"));
+        assert!(written
+            .contains("Shape \"plymesh\"\n        \"string filename\" [ \"mesh_00001.ply\" ]"));
+
+        let ply = std::fs::read(directory.join("mesh_00001.ply")).unwrap();
+        let header_end = b"end_header\n";
+        let body = ply
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .unwrap()
+            + header_end.len();
+        let header = String::from_utf8(ply[..body].to_vec()).unwrap();
+        assert!(header.contains("element vertex 4\n"));
+        assert!(header.contains("property float u\nproperty float v\n"));
+        assert!(header.contains("element face 2\n"));
+        // Five floats per vertex, then a count and three indices per face.
+        assert_eq!(ply.len() - body, 4 * 5 * 4 + 2 * (1 + 3 * 4));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}